@@ -28,6 +28,25 @@ in {
       default = 30;
       description = "Interval in seconds for interface statistics updates";
     };
+
+    restorePolicy = lib.mkOption {
+      type = lib.types.enum ["adopt" "remove"];
+      default = "adopt";
+      description = "What to do with interfaces left over after defguard-service restarts";
+    };
+
+    metricsAddress = lib.mkOption {
+      type = lib.types.nullOr lib.types.str;
+      default = null;
+      example = "127.0.0.1:9189";
+      description = "Address on which defguard-service serves metrics; disabled if null";
+    };
+
+    killSwitch = lib.mkOption {
+      type = lib.types.bool;
+      default = false;
+      description = "Let clients block traffic outside the tunnel while full-tunnel connections are active";
+    };
   };
 
   config = lib.mkIf cfg.enable {
@@ -39,9 +58,10 @@ in {
       wantedBy = ["multi-user.target"];
       wants = ["network-online.target"];
       after = ["network-online.target"];
+      path = lib.optional cfg.killSwitch pkgs.nftables;
       serviceConfig = {
         Group = "defguard";
-        ExecStart = "${cfg.package}/bin/defguard-service --log-level ${cfg.logLevel} --stats-period ${toString cfg.statsPeriod}";
+        ExecStart = "${cfg.package}/bin/defguard-service --log-level ${cfg.logLevel} --stats-period ${toString cfg.statsPeriod} --restore-policy ${cfg.restorePolicy}${lib.optionalString (cfg.metricsAddress != null) " --metrics-address ${cfg.metricsAddress}"}${lib.optionalString cfg.killSwitch " --kill-switch"}";
         ExecReload = "kill -HUP $MAINPID";
         KillMode = "process";
         KillSignal = "SIGINT";
@@ -51,6 +71,7 @@ in {
         RestartSec = 2;
         TasksMax = "infinity";
         OOMScoreAdjust = -1000;
+        StateDirectory = "defguard-service";
         # Security hardening
         NoNewPrivileges = true;
         PrivateTmp = true;
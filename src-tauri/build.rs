@@ -1,10 +1,50 @@
+use std::fs;
+
 use vergen_git2::{Emitter, Git2Builder};
 
+const CLIENT_PROTO: &str = "proto/client/client.proto";
+// Parts of the desktop daemon API which the client and the daemon are built against. The proto
+// submodule must be at a revision which has all of them.
+const DAEMON_API: &[&str] = &[
+    "message ManagedInterface",
+    "rpc ListInterfaces(",
+    "rpc UpdateInterface(",
+    "enum InterfaceEventKind",
+    "rpc WatchEvents(",
+    "optional uint64 interval = 2;",
+    "bool kill_switch = 3;",
+    "bool reconnect = 3;",
+    "enum DnsMode",
+    "DnsMode dns_mode = 4;",
+    "optional uint32 route_table = 5;",
+    "optional uint32 fwmark = 7;",
+];
+
+/// Fail early with a clear message, rather than with errors about missing types of generated code.
+fn check_daemon_api() -> Result<(), Box<dyn std::error::Error>> {
+    let proto = fs::read_to_string(CLIENT_PROTO)
+        .map_err(|err| format!("Failed to read {CLIENT_PROTO}: {err}"))?;
+    let proto = proto.split_whitespace().collect::<Vec<_>>().join(" ");
+    let missing: Vec<_> = DAEMON_API
+        .iter()
+        .filter(|definition| !proto.contains(*definition))
+        .collect();
+    if !missing.is_empty() {
+        return Err(format!(
+            "{CLIENT_PROTO} lacks definitions of the desktop daemon API: {missing:?}; \
+            update the proto submodule"
+        )
+        .into());
+    }
+    Ok(())
+}
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     // set VERGEN_GIT_SHA env variable based on git commit hash
     let git2 = Git2Builder::default().branch(true).sha(true).build()?;
     Emitter::default().add_instructions(&git2)?.emit()?;
 
+    check_daemon_api()?;
     tonic_prost_build::configure()
         // Enable optional fields.
         .protoc_arg("--experimental_allow_proto3_optional")
@@ -16,6 +56,8 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         )
         // Make all messages serde-serializable.
         .type_attribute(".", "#[derive(serde::Serialize,serde::Deserialize)]")
+        // Fields added to persisted requests must not break reading state of older versions.
+        .type_attribute(".CreateInterfaceRequest", "#[serde(default)]")
         .compile_protos(
             &["proto/client/client.proto", "proto/core/proxy.proto"],
             &["proto/client", "proto/core"],
@@ -1,16 +1,18 @@
 #[cfg(not(windows))]
 use std::os::unix::fs::PermissionsExt;
 use std::{
+    collections::{HashMap, HashSet},
     fmt,
     fs::{create_dir_all, OpenOptions},
     path::{Path, PathBuf},
+    process::ExitCode,
     str::FromStr,
-    sync::Arc,
-    time::Duration,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
 };
 
-use clap::{builder::FalseyValueParser, command, value_parser, Arg, Command};
-use common::{dns_borrow, find_free_tcp_port, get_interface_name};
+use clap::{builder::FalseyValueParser, command, value_parser, Arg, ArgMatches, Command};
+use common::{dns_borrow, find_free_tcp_port, get_interface_name_excluding};
 #[cfg(not(target_os = "macos"))]
 use defguard_wireguard_rs::Kernel;
 #[cfg(target_os = "macos")]
@@ -24,40 +26,151 @@ use serde::{Deserialize, Serialize};
 use thiserror::Error;
 #[cfg(unix)]
 use tokio::signal::unix::{signal, SignalKind};
-#[cfg(not(unix))]
-use tokio::time::sleep;
-use tokio::{select, signal::ctrl_c, sync::Notify, time::interval};
+use tokio::{
+    select,
+    signal::ctrl_c,
+    sync::{mpsc, watch, Notify},
+    task::JoinHandle,
+    time::{interval, sleep, Interval},
+};
 use tracing::{debug, error, info, level_filters::LevelFilter, trace, warn};
-use tracing_subscriber::EnvFilter;
+use tracing_subscriber::{
+    fmt::writer::BoxMakeWriter, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter,
+};
 
+mod control;
+mod export;
+#[cfg(test)]
+mod fixtures;
+mod mfa;
+mod output;
 mod proto {
     include!(concat!(env!("OUT_DIR"), "/defguard.proxy.rs"));
 }
+mod secrets;
+mod systemd;
+
+use control::{ControlMessage, ControlRequest, ControlResponse, LocationStatus};
+use mfa::MfaMethod;
+use output::{ConnectionEvent, OutputFormat};
+use secrets::SecretStorage;
+use systemd::State;
 
+/// Defguard instance this device has been enrolled in, along with all its locations.
 #[derive(Clone, Default, Deserialize, Serialize)]
-struct CliConfig {
+struct CliInstance {
     private_key: Key,
     device: proto::Device,
-    device_config: proto::DeviceConfig,
+    device_configs: Vec<proto::DeviceConfig>,
     instance_info: proto::InstanceInfo,
     // polling token used for further client-core communication
     token: Option<String>,
+    /// Network IDs of locations which should be connected by `dg`.
+    #[serde(default)]
+    active_locations: HashSet<i64>,
+    /// Network IDs of locations which should route all traffic through the tunnel,
+    /// unless the instance's client traffic policy says otherwise.
+    #[serde(default)]
+    route_all_traffic: HashSet<i64>,
 }
 
-impl fmt::Debug for CliConfig {
+impl fmt::Debug for CliInstance {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.debug_struct("CliConfig")
+        f.debug_struct("CliInstance")
             .field("private_key", &"<HIDDEN>")
             .field("device", &self.device)
-            .field("device_config", &self.device_config)
+            .field("device_configs", &self.device_configs)
             .field("instance_info", &self.instance_info)
             .field("token", &self.token)
+            .field("active_locations", &self.active_locations)
+            .field("route_all_traffic", &self.route_all_traffic)
             .finish()
     }
 }
 
+impl fmt::Display for CliInstance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.instance_info.name)
+    }
+}
+
+impl CliInstance {
+    /// Find location configuration by its network ID.
+    fn device_config(&self, network_id: i64) -> Option<&proto::DeviceConfig> {
+        self.device_configs
+            .iter()
+            .find(|config| config.network_id == network_id)
+    }
+
+    /// Forget about active locations which are no longer present in the configuration.
+    fn retain_known_locations(&mut self) {
+        let network_ids: HashSet<i64> = self
+            .device_configs
+            .iter()
+            .map(|config| config.network_id)
+            .collect();
+        self.active_locations
+            .retain(|network_id| network_ids.contains(network_id));
+        self.route_all_traffic
+            .retain(|network_id| network_ids.contains(network_id));
+    }
+
+    /// Decide whether all traffic of a location should be routed through the tunnel,
+    /// honouring the instance's client traffic policy.
+    fn routes_all_traffic(&self, network_id: i64) -> bool {
+        // Values match `ClientTrafficPolicy` from the Defguard proxy protocol.
+        match (
+            self.instance_info.client_traffic_policy,
+            #[allow(deprecated)]
+            self.instance_info.disable_all_traffic,
+        ) {
+            // Clients are forced to route all traffic through the VPN.
+            (Some(2), _) => true,
+            // Clients are not allowed to route all traffic through the VPN.
+            (Some(1), _) | (None, true) => false,
+            _ => self.route_all_traffic.contains(&network_id),
+        }
+    }
+}
+
+/// Configuration format used before `dg` supported multiple instances and locations.
+#[derive(Deserialize)]
+struct LegacyCliConfig {
+    private_key: Key,
+    device: proto::Device,
+    device_config: proto::DeviceConfig,
+    instance_info: proto::InstanceInfo,
+    token: Option<String>,
+}
+
+impl From<LegacyCliConfig> for CliInstance {
+    fn from(legacy: LegacyCliConfig) -> Self {
+        // The only location was always connected, so keep it that way.
+        let active_locations = HashSet::from([legacy.device_config.network_id]);
+        Self {
+            private_key: legacy.private_key,
+            device: legacy.device,
+            device_configs: vec![legacy.device_config],
+            instance_info: legacy.instance_info,
+            token: legacy.token,
+            active_locations,
+            route_all_traffic: HashSet::new(),
+        }
+    }
+}
+
+/// CLI configuration; stores all enrolled instances.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+struct CliConfig {
+    /// Where private keys and polling tokens are kept; see the `secrets` module.
+    #[serde(default)]
+    secret_storage: SecretStorage,
+    instances: Vec<CliInstance>,
+}
+
 impl CliConfig {
     /// Load configuration from a file at `path`.
+    /// Configuration files written by older versions (single location) are converted on the fly.
     fn load(path: &Path) -> Result<Self, CliError> {
         let file = match OpenOptions::new().read(true).open(path) {
             Ok(file) => file,
@@ -66,17 +179,41 @@ impl CliConfig {
                 return Err(CliError::ConfigNotFound(path.to_string_lossy().to_string()));
             }
         };
-        match serde_json::from_reader::<_, Self>(file) {
-            Ok(config) => Ok(config),
-            Err(err) => Err(CliError::ConfigParse(
-                path.to_string_lossy().to_string(),
-                err.to_string(),
-            )),
+        let parse_error = |err: serde_json::Error| {
+            CliError::ConfigParse(path.to_string_lossy().to_string(), err.to_string())
+        };
+        let mut value =
+            serde_json::from_reader::<_, serde_json::Value>(file).map_err(parse_error)?;
+        if value.get("instances").is_some() {
+            secrets::restore(&mut value)?;
+            serde_json::from_value::<Self>(value).map_err(parse_error)
+        } else {
+            debug!("Found configuration in the legacy format at {path:?}, converting.");
+            let legacy = serde_json::from_value::<LegacyCliConfig>(value).map_err(parse_error)?;
+            Ok(Self {
+                // Older versions kept secrets in the configuration file only.
+                secret_storage: SecretStorage::Plaintext,
+                instances: vec![legacy.into()],
+            })
+        }
+    }
+
+    /// Load configuration from a file at `path`, or return an empty one if it doesn't exist yet.
+    fn load_or_default(path: &Path) -> Result<Self, CliError> {
+        match Self::load(path) {
+            Err(CliError::ConfigNotFound(_)) => Ok(Self::default()),
+            result => result,
         }
     }
 
     /// Save configuration to a file at `path`.
     fn save(&self, path: &Path) -> Result<(), CliError> {
+        let save_error = |err: serde_json::Error| {
+            CliError::ConfigSave(path.to_string_lossy().to_string(), err.to_string())
+        };
+        // Move secrets to their storage before the file gets truncated.
+        let mut value = serde_json::to_value(self).map_err(save_error)?;
+        secrets::store(&mut value, self.secret_storage)?;
         let file = match OpenOptions::new()
             .create(true)
             .truncate(true)
@@ -108,23 +245,97 @@ impl CliConfig {
             }
             debug!("Config file permissions have been set.");
         }
-        match serde_json::to_writer(file, &self) {
-            Ok(()) => debug!(
-                "Configuration file has been saved to {}",
-                path.to_string_lossy()
-            ),
-            Err(err) => {
-                return Err(CliError::ConfigSave(
-                    path.to_string_lossy().to_string(),
-                    err.to_string(),
-                ));
+        serde_json::to_writer(file, &value).map_err(save_error)?;
+        debug!(
+            "Configuration file has been saved to {}",
+            path.to_string_lossy()
+        );
+
+        Ok(())
+    }
+
+    /// Add newly enrolled instance, replacing the previous enrollment of the same instance.
+    fn add_instance(&mut self, mut instance: CliInstance) {
+        if let Some(existing) = self
+            .instances
+            .iter_mut()
+            .find(|existing| existing.instance_info.id == instance.instance_info.id)
+        {
+            debug!("Instance {instance} has already been enrolled, replacing its configuration.");
+            instance.active_locations = existing.active_locations.clone();
+            instance.route_all_traffic = existing.route_all_traffic.clone();
+            instance.retain_known_locations();
+            *existing = instance;
+        } else {
+            self.instances.push(instance);
+        }
+    }
+
+    /// Find instance by its UUID.
+    fn instance_mut(&mut self, uuid: &str) -> Option<&mut CliInstance> {
+        self.instances
+            .iter_mut()
+            .find(|instance| instance.instance_info.id == uuid)
+    }
+
+    /// Find a location by its name, optionally narrowing the search to a single instance.
+    /// Returns instance index and location network ID.
+    fn find_location(
+        &self,
+        location_name: &str,
+        instance_name: Option<&str>,
+    ) -> Result<(usize, i64), CliError> {
+        let mut found = Vec::new();
+        for (index, instance) in self.instances.iter().enumerate() {
+            if instance_name.is_some_and(|name| name != instance.instance_info.name) {
+                continue;
             }
+            for device_config in &instance.device_configs {
+                if device_config.network_name == location_name {
+                    found.push((index, device_config.network_id));
+                }
+            }
+        }
+        match found.len() {
+            0 => Err(CliError::LocationNotFound(location_name.to_string())),
+            1 => Ok(found[0]),
+            _ => Err(CliError::AmbiguousLocation(location_name.to_string())),
         }
+    }
 
-        Ok(())
+    /// Mark location as active (to be connected).
+    fn activate_location(
+        &mut self,
+        location_name: &str,
+        instance_name: Option<&str>,
+        route_all_traffic: bool,
+    ) -> Result<LocationKey, CliError> {
+        let (index, network_id) = self.find_location(location_name, instance_name)?;
+        let instance = &mut self.instances[index];
+        instance.active_locations.insert(network_id);
+        if route_all_traffic {
+            instance.route_all_traffic.insert(network_id);
+        } else {
+            instance.route_all_traffic.remove(&network_id);
+        }
+        Ok((instance.instance_info.id.clone(), network_id))
+    }
+
+    /// Mark location as inactive (to be disconnected).
+    fn deactivate_location(
+        &mut self,
+        location_name: &str,
+        instance_name: Option<&str>,
+    ) -> Result<LocationKey, CliError> {
+        let (index, network_id) = self.find_location(location_name, instance_name)?;
+        let instance = &mut self.instances[index];
+        instance.active_locations.remove(&network_id);
+        Ok((instance.instance_info.id.clone(), network_id))
     }
 }
 
+/// Errors reported by `dg`. Each variant maps to a distinct process exit code, see
+/// [`CliError::exit_code`].
 #[derive(Debug, Error)]
 enum CliError {
     #[error("Error while communicating with Defguard: {0}")]
@@ -133,8 +344,8 @@ enum CliError {
     MissingData,
     #[error(transparent)]
     Reqwest(#[from] reqwest::Error),
-    #[error("Expected to receive 1 device config, found {0}")]
-    TooManyDevices(usize),
+    #[error("Expected to receive at least 1 device config, found none")]
+    NoDevices,
     #[error(transparent)]
     WireGuard(#[from] WireguardInterfaceError),
     #[error("Couldn't open CLI configuration at path: \"{0}\".")]
@@ -147,95 +358,251 @@ enum CliError {
     ConfigSave(String, String),
     #[error("Failed to find free TCP port")]
     FreeTCPPort,
+    #[error("Location \"{0}\" not found")]
+    LocationNotFound(String),
+    #[error("Location \"{0}\" exists in multiple instances; use \"--instance\" to choose one")]
+    AmbiguousLocation(String),
+    #[error("Instance \"{0}\" not found")]
+    InstanceNotFound(String),
+    #[error("Control socket error at {0}: {1}. Make sure dg is running.")]
+    ControlSocket(String, String),
+    #[error("dg agent returned an error: {0}")]
+    Agent(String),
+    #[error("MFA failed: {0}")]
+    Mfa(String),
+    #[error("Failed to set up WireGuard interface {0}: {1}")]
+    InterfaceSetup(String, String),
+    #[error("Invalid endpoint {0}: {1}")]
+    InvalidEndpoint(String, String),
+    #[error("Invalid gateway public key of location {0}: {1}")]
+    InvalidPeerKey(String, String),
+    #[error("Failed to access secrets: {0}")]
+    Secrets(String),
+    #[error("Failed to export {0}: {1}")]
+    Export(String, String),
 }
 
-/// Connect to Defguard Gateway.
-async fn connect(config: CliConfig, ifname: String, trigger: Arc<Notify>) -> Result<(), CliError> {
-    let network_name = config.device_config.network_name.clone();
-    debug!("Connecting to network {network_name}.");
+impl CliError {
+    /// Process exit code. Codes are grouped by the source of the error:
+    /// 1x - communication with Defguard, 2x - WireGuard interface, 3x - CLI configuration,
+    /// 4x - location or instance lookup, 5x - running agent, 6x - MFA.
+    fn exit_code(&self) -> u8 {
+        match self {
+            Self::DefguardApi(_) => 10,
+            Self::MissingData => 11,
+            Self::Reqwest(_) => 12,
+            Self::NoDevices => 13,
+            Self::EnterpriseDisabled => 14,
+            Self::WireGuard(_) => 20,
+            Self::FreeTCPPort => 21,
+            Self::InterfaceSetup(..) => 22,
+            Self::InvalidEndpoint(..) => 23,
+            Self::InvalidPeerKey(..) => 24,
+            Self::ConfigNotFound(_) => 30,
+            Self::ConfigParse(..) => 31,
+            Self::ConfigSave(..) => 32,
+            Self::Secrets(_) => 33,
+            Self::Export(..) => 34,
+            Self::LocationNotFound(_) => 40,
+            Self::AmbiguousLocation(_) => 41,
+            Self::InstanceNotFound(_) => 42,
+            Self::ControlSocket(..) => 50,
+            Self::Agent(_) => 51,
+            Self::Mfa(_) => 60,
+        }
+    }
+}
 
-    #[cfg(not(target_os = "macos"))]
-    let mut wgapi = WGApi::<Kernel>::new(ifname.clone()).expect("Failed to setup WireGuard API");
-    #[cfg(target_os = "macos")]
-    let mut wgapi = WGApi::<Userspace>::new(ifname.clone()).expect("Failed to setup WireGuard API");
+/// Exit codes listed in `dg --help`; must be kept in sync with [`CliError::exit_code`].
+const EXIT_CODES_HELP: &str = "Exit codes:
+  0   success
+  1   unexpected error
+  2   invalid command line arguments
+  10  Defguard API error
+  11  missing data in Defguard response
+  12  network request failed
+  13  no device configuration received
+  14  Defguard enterprise features are disabled
+  20  WireGuard interface error
+  21  no free port for the interface
+  22  failed to set up WireGuard interface
+  23  invalid or unresolvable gateway endpoint
+  24  invalid gateway public key
+  30  configuration file not found
+  31  invalid configuration file
+  32  failed to save configuration file
+  33  failed to access secrets in keyring or encrypted configuration
+  34  failed to export WireGuard configuration
+  40  location not found
+  41  location name is ambiguous
+  42  instance not found
+  50  failed to reach the dg agent
+  51  dg agent rejected the request
+  60  MFA failed";
 
-    // Create new interface.
-    debug!("Creating new interface {ifname}");
-    wgapi
-        .create_interface()
-        .expect("Failed to create WireGuard interface");
+const DEFAULT_ROUTE_IPV4: &str = "0.0.0.0/0";
+const DEFAULT_ROUTE_IPV6: &str = "::/0";
+const DEFAULT_KEEPALIVE_INTERVAL: u16 = 25;
+
+/// Interface settings which don't come from `DeviceConfig`.
+#[derive(Clone, Default, PartialEq)]
+struct ConnectionOptions {
+    route_all_traffic: bool,
+    preshared_key: Option<Key>,
+    mtu: Option<u32>,
+}
+
+/// Location settings sent to a running connection, to be applied without re-creating its
+/// interface.
+type ConnectionUpdate = (proto::DeviceConfig, ConnectionOptions);
+
+/// Parse IP addresses and networks separated by commas, skipping invalid entries.
+fn parse_addresses(addresses: &str) -> Vec<IpAddrMask> {
+    addresses
+        .split(',')
+        .map(str::trim)
+        .filter_map(|addr| {
+            let ipaddrmask = addr.parse::<IpAddrMask>();
+            if let Err(err) = &ipaddrmask {
+                error!(
+                    "Error parsing IP address `{addr}` while setting up interface: {err}. \
+                    Trying to parse the remaining addresses if any."
+                );
+            }
+            ipaddrmask.ok()
+        })
+        .collect()
+}
+
+/// Persistent keepalive interval of a location, in seconds.
+fn keepalive_interval(device_config: &proto::DeviceConfig) -> u16 {
+    // Older Defguard versions don't send the keepalive interval.
+    u16::try_from(device_config.keepalive_interval)
+        .ok()
+        .filter(|interval| *interval > 0)
+        .unwrap_or(DEFAULT_KEEPALIVE_INTERVAL)
+}
+
+/// Prepare WireGuard peer representing Defguard Gateway of a location.
+fn gateway_peer(
+    device_config: &proto::DeviceConfig,
+    options: &ConnectionOptions,
+) -> Result<Peer, CliError> {
+    let network_name = &device_config.network_name;
+    let peer_key = Key::from_str(&device_config.pubkey)
+        .map_err(|err| CliError::InvalidPeerKey(network_name.clone(), err.to_string()))?;
+
+    let mut peer = Peer::new(peer_key);
+    // Resolves the endpoint host name, which may fail temporarily.
+    peer.set_endpoint(&device_config.endpoint).map_err(|err| {
+        CliError::InvalidEndpoint(device_config.endpoint.clone(), err.to_string())
+    })?;
+    let keepalive_interval = keepalive_interval(device_config);
+    debug!("Using keepalive interval of {keepalive_interval}s for network {network_name}");
+    peer.persistent_keepalive_interval = Some(keepalive_interval);
+    if let Some(psk) = &options.preshared_key {
+        info!("Using preshared key for network {network_name}.");
+        peer.preshared_key = Some(psk.clone());
+    }
+
+    peer.allowed_ips = if options.route_all_traffic {
+        debug!("Using all traffic routing for network {network_name}");
+        parse_addresses(&format!("{DEFAULT_ROUTE_IPV4},{DEFAULT_ROUTE_IPV6}"))
+    } else {
+        debug!(
+            "Using predefined network {network_name} traffic: {}",
+            device_config.allowed_ips
+        );
+        parse_addresses(&device_config.allowed_ips)
+    };
+    debug!("Parsed allowed IPs: {:?}", peer.allowed_ips);
+
+    Ok(peer)
+}
+
+/// Apply changed location settings to an existing interface: update the gateway peer, its
+/// routes and DNS.
+fn update_interface(
+    wgapi: &mut impl WireguardInterfaceApi,
+    ifname: &str,
+    current: &proto::DeviceConfig,
+    device_config: &proto::DeviceConfig,
+    options: &ConnectionOptions,
+) -> Result<(), CliError> {
+    let peer = gateway_peer(device_config, options)?;
+    if current.pubkey != device_config.pubkey {
+        if let Ok(old_key) = Key::from_str(&current.pubkey) {
+            debug!("Removing previous gateway peer {old_key} from interface {ifname}");
+            wgapi.remove_peer(&old_key)?;
+        }
+    }
+    debug!(
+        "Updating gateway peer {} of interface {ifname}",
+        peer.public_key
+    );
+    wgapi.configure_peer(&peer)?;
+    #[cfg(not(windows))]
+    {
+        debug!("Updating interface {ifname} routing");
+        wgapi.configure_peer_routing(&[peer])?;
+    }
+    if current.dns != device_config.dns {
+        let (dns, search_domains) = dns_borrow(&device_config.dns);
+        debug!(
+            "The following DNS servers will be set: {dns:?}, search domains: \
+            {search_domains:?}"
+        );
+        wgapi.configure_dns(&dns, &search_domains)?;
+    }
+
+    Ok(())
+}
+
+/// Helper function used to perform required configuration steps for a new interface.
+///
+/// This allows us to roll back interface creation if some configuration step fails.
+fn configure_new_interface(
+    wgapi: &mut impl WireguardInterfaceApi,
+    ifname: &str,
+    private_key: &Key,
+    device_config: &proto::DeviceConfig,
+    options: &ConnectionOptions,
+) -> Result<(), CliError> {
+    let setup_error = |step: &str, err: &dyn fmt::Display| {
+        CliError::InterfaceSetup(ifname.into(), format!("{step}: {err}"))
+    };
 
     debug!("Preparing DNS configuration for interface {ifname}");
     // We assume that every entry that can't be parsed as an IP address is a domain name.
-    let (dns, search_domains) = dns_borrow(&config.device_config.dns);
+    let (dns, search_domains) = dns_borrow(&device_config.dns);
     debug!(
         "DNS configuration for interface {ifname}: DNS: {dns:?}, Search domains: \
         {search_domains:?}"
     );
-    let peer_key = Key::from_str(&config.device_config.pubkey).unwrap();
-
-    let mut peer = Peer::new(peer_key);
-    peer.set_endpoint(&config.device_config.endpoint).unwrap();
-    peer.persistent_keepalive_interval = Some(25);
-    // TODO:
-    // if let Some(psk) = preshared_key {
-    //     debug!("Decoding location {location} preshared key.");
-    //     let peer_psk = Key::from_str(&psk)?;
-    //     info!("Location {location} preshared key decoded.");
-    //     peer.preshared_key = Some(peer_psk);
-    // }
-
-    let ip_addr_parser = |addr: &str| {
-        let ipaddrmask = addr.parse::<IpAddrMask>();
-        if let Err(err) = &ipaddrmask {
-            error!(
-                "Error parsing IP address `{addr}` while setting up interface: {err}. \
-                Trying to parse the remaining addresses if any."
-            );
-        }
-        ipaddrmask.ok()
-    };
+    let peer = gateway_peer(device_config, options)?;
 
-    peer.allowed_ips =
-        //if location.route_all_traffic {
-        //     eprintln!("Using all traffic routing for location {location}: {DEFAULT_ROUTE_IPV4} {DEFAULT_ROUTE_IPV6}");
-        //     vec![DEFAULT_ROUTE_IPV4.into(), DEFAULT_ROUTE_IPV6.into()]
-        // } else {
-        config
-            .device_config
-            .allowed_ips
-            .split(',')
-            .filter_map(ip_addr_parser)
-            .collect::<Vec<_>>();
-    debug!("Parsed allowed IPs: {:?}", peer.allowed_ips);
-
-    let addresses = config
-        .device_config
-        .assigned_ip
-        .split(',')
-        .filter_map(ip_addr_parser)
-        .collect::<Vec<_>>();
+    let addresses = parse_addresses(&device_config.assigned_ip);
     debug!("Parsed assigned IPs: {addresses:?}");
 
     let config = InterfaceConfiguration {
-        name: config.instance_info.name.clone(),
-        prvkey: config.private_key.to_string(),
+        name: ifname.to_string(),
+        prvkey: private_key.to_string(),
         addresses,
         port: find_free_tcp_port().ok_or(CliError::FreeTCPPort)?,
-        peers: vec![peer.clone()],
-        mtu: None,
+        peers: vec![peer],
+        mtu: options.mtu,
         fwmark: None,
     };
-    let configure_interface_result = wgapi.configure_interface(&config);
-
-    configure_interface_result.expect("Failed to configure WireGuard interface");
+    wgapi
+        .configure_interface(&config)
+        .map_err(|err| setup_error("failed to configure interface", &err))?;
 
     #[cfg(not(windows))]
     {
         debug!("Configuring interface {ifname} routing");
         wgapi
             .configure_peer_routing(&config.peers)
-            .expect("Failed to configure routing for WireGuard interface");
+            .map_err(|err| setup_error("failed to configure routing", &err))?;
     }
     if dns.is_empty() {
         debug!("No DNS configuration provided for interface {ifname}, skipping DNS configuration");
@@ -246,28 +613,184 @@ async fn connect(config: CliConfig, ifname: String, trigger: Arc<Notify>) -> Res
         );
         wgapi
             .configure_dns(&dns, &search_domains)
-            .expect("Failed to configure DNS for WireGuard interface");
+            .map_err(|err| setup_error("failed to configure DNS", &err))?;
+    }
+
+    Ok(())
+}
+
+/// WireGuard implementation used for location interfaces.
+#[cfg(not(target_os = "macos"))]
+type LocationWGApi = WGApi<Kernel>;
+#[cfg(target_os = "macos")]
+type LocationWGApi = WGApi<Userspace>;
+
+/// Create and configure interface `ifname` for a location. The interface is removed if any of
+/// the configuration steps fails, so no half-configured interfaces and routes are left behind.
+fn create_interface(
+    ifname: &str,
+    private_key: &Key,
+    device_config: &proto::DeviceConfig,
+    options: &ConnectionOptions,
+) -> Result<LocationWGApi, CliError> {
+    let mut wgapi = LocationWGApi::new(ifname.to_string()).map_err(|err| {
+        CliError::InterfaceSetup(
+            ifname.into(),
+            format!("failed to set up WireGuard API: {err}"),
+        )
+    })?;
+
+    debug!("Creating new interface {ifname}");
+    wgapi.create_interface().map_err(|err| {
+        CliError::InterfaceSetup(ifname.into(), format!("failed to create interface: {err}"))
+    })?;
+
+    // attempt to configure new interface
+    // remove interface if configuration fails to avoid dangling interfaces and routes
+    if let Err(err) =
+        configure_new_interface(&mut wgapi, ifname, private_key, device_config, options)
+    {
+        debug!("Removing newly created interface {ifname} due to configuration failure");
+        if let Err(err) = wgapi.remove_interface() {
+            error!("Failed to remove WireGuard interface {ifname}: {err}");
+        }
+        return Err(err);
     }
 
     debug!("Finished creating a new interface {ifname}");
-    info!("Connected to network {network_name}.");
+    Ok(wgapi)
+}
 
-    trigger.notified().await;
-    debug!(
-        "Closing the interface {ifname} for network {network_name} because of a received signal."
-    );
-    if let Err(err) = wgapi.remove_interface() {
-        error!(
-            "Failed to close the interface {ifname} for network {network_name}: {err}. The \
-            interface may've been already closed or it's not available."
-        );
-    } else {
-        info!("Connection to the network {network_name} has been terminated.");
+/// Connect to Defguard Gateway.
+/// Keeps the connection up until `trigger` is notified, applying settings received through
+/// `updates` in place. Failed connection attempts are retried with exponential backoff.
+/// Connection state is published through `state`: `None` until the first attempt finishes,
+/// then whether the interface is up.
+async fn connect(
+    private_key: Key,
+    mut device_config: proto::DeviceConfig,
+    mut options: ConnectionOptions,
+    ifname: String,
+    trigger: Arc<Notify>,
+    mut updates: mpsc::UnboundedReceiver<ConnectionUpdate>,
+    state: watch::Sender<Option<bool>>,
+) -> Result<(), CliError> {
+    let network_name = device_config.network_name.clone();
+    let mut backoff = RETRY_BACKOFF_MIN;
+    'connection: loop {
+        debug!("Connecting to network {network_name}.");
+        let mut wgapi = match create_interface(&ifname, &private_key, &device_config, &options) {
+            Ok(wgapi) => wgapi,
+            Err(err) => {
+                error!(
+                    "Failed to connect to network {network_name}: {err}. Retrying in {}s.",
+                    backoff.as_secs()
+                );
+                output::event(&ConnectionEvent::Failed {
+                    location: &network_name,
+                    interface: &ifname,
+                    error: err.to_string(),
+                });
+                state.send_replace(Some(false));
+                select! {
+                    () = trigger.notified() => {
+                        debug!("Giving up connecting to network {network_name}.");
+                        return Ok(());
+                    }
+                    Some(update) = updates.recv() => {
+                        // New settings may fix the problem; retry immediately.
+                        (device_config, options) = update;
+                        backoff = RETRY_BACKOFF_MIN;
+                    }
+                    () = sleep(backoff) => {
+                        backoff = (backoff * 2).min(RETRY_BACKOFF_MAX);
+                    }
+                }
+                continue;
+            }
+        };
+        backoff = RETRY_BACKOFF_MIN;
+
+        info!("Connected to network {network_name}.");
+        output::event(&ConnectionEvent::Connected {
+            location: &network_name,
+            interface: &ifname,
+        });
+        state.send_replace(Some(true));
+
+        loop {
+            select! {
+                () = trigger.notified() => {
+                    debug!(
+                        "Closing the interface {ifname} for network {network_name} because of a \
+                        received signal."
+                    );
+                    if let Err(err) = wgapi.remove_interface() {
+                        error!(
+                            "Failed to close the interface {ifname} for network {network_name}: \
+                            {err}. The interface may've been already closed or it's not \
+                            available."
+                        );
+                    } else {
+                        info!("Connection to the network {network_name} has been terminated.");
+                    }
+                    return Ok(());
+                }
+                Some(update) = updates.recv() => {
+                    debug!("Applying new configuration of network {network_name} to interface {ifname}");
+                    let result =
+                        update_interface(&mut wgapi, &ifname, &device_config, &update.0, &update.1);
+                    (device_config, options) = update;
+                    if let Err(err) = result {
+                        error!(
+                            "Failed to update interface {ifname} for network {network_name}: \
+                            {err}. Re-creating the interface."
+                        );
+                        if let Err(err) = wgapi.remove_interface() {
+                            error!("Failed to close the interface {ifname}: {err}");
+                        }
+                        state.send_replace(Some(false));
+                        continue 'connection;
+                    }
+                    info!("Configuration of network {network_name} has been updated.");
+                }
+            }
+        }
     }
-    // Send cleanup ack to a task that may've cancelled the connection.
-    trigger.notify_one();
+}
 
-    Ok(())
+/// Read current state of a location connection from its WireGuard interface.
+fn location_status(instance_name: &str, connection: &LocationConnection) -> LocationStatus {
+    let mut status = LocationStatus {
+        instance: instance_name.to_string(),
+        location: connection.device_config.network_name.clone(),
+        interface: connection.ifname.clone(),
+        endpoint: None,
+        handshake_age: None,
+        rx_bytes: 0,
+        tx_bytes: 0,
+    };
+
+    let wgapi = LocationWGApi::new(connection.ifname.clone());
+    let host = match wgapi.and_then(|wgapi| wgapi.read_interface_data()) {
+        Ok(host) => host,
+        Err(err) => {
+            warn!("Failed to read interface {} data: {err}", connection.ifname);
+            return status;
+        }
+    };
+    if let Some(peer) = host.peers.into_values().next() {
+        status.endpoint = peer.endpoint.map(|addr| addr.to_string());
+        status.handshake_age = peer
+            .last_handshake
+            .filter(|last_handshake| *last_handshake != SystemTime::UNIX_EPOCH)
+            .and_then(|last_handshake| SystemTime::now().duration_since(last_handshake).ok())
+            .map(|age| age.as_secs());
+        status.rx_bytes = peer.rx_bytes;
+        status.tx_bytes = peer.tx_bytes;
+    }
+
+    status
 }
 
 #[derive(Deserialize)]
@@ -276,7 +799,7 @@ struct ApiError {
 }
 
 /// Enroll device.
-async fn enroll(base_url: &Url, token: String) -> Result<CliConfig, CliError> {
+async fn enroll(base_url: &Url, token: String) -> Result<CliInstance, CliError> {
     debug!("Starting enrollment through Defguard Proxy at {base_url}.");
     let client = Client::builder().cookie_store(true).build()?;
     let mut url = base_url.clone();
@@ -338,9 +861,8 @@ async fn enroll(base_url: &Url, token: String) -> Result<CliConfig, CliError> {
         )));
     };
 
-    let count = response.configs.len();
-    if count != 1 {
-        return Err(CliError::TooManyDevices(count));
+    if response.configs.is_empty() {
+        return Err(CliError::NoDevices);
     }
     let Some(instance_info) = response.instance else {
         error!("Missing InstanceInfo in the configuration received from Defguard Proxy.");
@@ -351,27 +873,42 @@ async fn enroll(base_url: &Url, token: String) -> Result<CliConfig, CliError> {
         return Err(CliError::MissingData);
     };
 
-    let config = CliConfig {
+    // Connect right away if there is nothing to choose from.
+    let active_locations = if let [device_config] = response.configs.as_slice() {
+        HashSet::from([device_config.network_id])
+    } else {
+        HashSet::new()
+    };
+    let instance = CliInstance {
         private_key: prvkey,
         device,
-        device_config: response.configs[0].clone(),
+        device_configs: response.configs,
         instance_info,
         token: response.token,
+        active_locations,
+        // Client traffic policy of the instance decides unless the user opts in.
+        route_all_traffic: HashSet::new(),
     };
     debug!("Enrollment done, returning the received configuration.");
 
-    Ok(config)
+    Ok(instance)
 }
 
 const INTERVAL_SECONDS: Duration = Duration::from_secs(30);
 const HTTP_REQ_TIMEOUT: Duration = Duration::from_secs(5);
+/// Delays between attempts to connect to a location, doubled after every failure.
+const RETRY_BACKOFF_MIN: Duration = Duration::from_secs(1);
+const RETRY_BACKOFF_MAX: Duration = Duration::from_secs(60);
+/// How long the agent may go without successful configuration polls or WireGuard handshakes
+/// before it's considered stalled by the systemd watchdog.
+const STALL_TIMEOUT: Duration = Duration::from_secs(5 * 60);
 
 /// Fetch configuration from Defguard proxy.
 async fn fetch_config(
     client: &Client,
     url: Url,
     token: String,
-) -> Result<proto::DeviceConfig, CliError> {
+) -> Result<proto::DeviceConfigResponse, CliError> {
     let result = client
         .post(url.clone())
         .json(&proto::InstanceInfoRequest { token })
@@ -396,36 +933,31 @@ async fn fetch_config(
         ));
     };
 
-    let count = response.configs.len();
-    if count != 1 {
-        error!("Expected one device config, found {count}.");
-        return Err(CliError::TooManyDevices(count));
-    }
-    // let Some(instance_info) = response.instance else {
-    //     eprintln!("Missing InstanceInfo");
-    //     return Err(CliError::MissingData);
-    // };
-    // let Some(device) = response.device else {
-    //     eprintln!("Missing Device");
-    //     return Err(CliError::MissingData);
-    // };
-    let Some(device_config) = response.configs.into_iter().next() else {
-        // This should not happen.
-        return Err(CliError::MissingData);
-    };
+    Ok(response)
+}
 
-    Ok(device_config)
+/// Instance configuration change detected by [`poll_config`].
+struct ConfigUpdate {
+    /// UUID of the instance, as stored in the CLI configuration.
+    instance_id: String,
+    device_configs: Vec<proto::DeviceConfig>,
+    instance_info: proto::InstanceInfo,
 }
 
-/// Poll configuration from Defguard proxy in regular intervals.
-/// Exit when `DeviceConfig` differs from the current one.
-async fn poll_config(config: &mut CliConfig) {
-    debug!("Starting the configuration polling task.");
+/// Poll instance configuration from Defguard proxy in regular intervals.
+/// Send an update whenever it differs from the current one. Time of the latest successful poll
+/// is stored in `last_poll`.
+async fn poll_config(
+    instance: CliInstance,
+    tx: mpsc::Sender<ConfigUpdate>,
+    last_poll: Arc<Mutex<Instant>>,
+) {
+    debug!("Starting the configuration polling task for instance {instance}.");
     // sanity check
-    let Some(token) = config.clone().token else {
+    let Some(token) = instance.token.clone() else {
         debug!(
-            "No polling token found in the CLI configuration. Make sure you are using the latest \
-            Defguard version. Exiting."
+            "No polling token found for instance {instance} in the CLI configuration. Make sure \
+            you are using the latest Defguard version. Exiting."
         );
         return;
     };
@@ -436,35 +968,62 @@ async fn poll_config(config: &mut CliConfig) {
             return;
         }
     };
-    let mut url = match Url::parse(&config.instance_info.proxy_url) {
+    let mut url = match Url::parse(&instance.instance_info.proxy_url) {
         Ok(url) => url,
         Err(err) => {
             error!(
                 "Failed to parse proxy URL ({}) for config polling: {err}",
-                &config.instance_info.proxy_url
+                &instance.instance_info.proxy_url
             );
             return;
         }
     };
     url.set_path("/api/v1/poll");
+    let instance_id = instance.instance_info.id.clone();
+    let mut device_configs = instance.device_configs;
+    let mut instance_info = instance.instance_info;
     debug!("Config polling setup done, starting the polling loop.");
     let mut interval = interval(INTERVAL_SECONDS);
     loop {
         interval.tick().await;
-        debug!("Polling network configuration from proxy.");
+        debug!(
+            "Polling network configuration of instance {} from proxy.",
+            instance_info.name
+        );
         match fetch_config(&client, url.clone(), token.clone()).await {
-            Ok(device_config) => {
-                if config.device_config != device_config {
-                    debug!("Network configuration has changed, re-configuring.");
-                    trace!(
-                        "Old configuration: {:?}. New configuration: {device_config:?}.",
-                        config.device_config,
-                    );
-                    config.device_config = device_config;
-                    debug!("New configuration has been successfully applied.");
+            Ok(response) => {
+                if let Ok(mut last_poll) = last_poll.lock() {
+                    *last_poll = Instant::now();
+                }
+                let info_changed = response
+                    .instance
+                    .as_ref()
+                    .is_some_and(|info| *info != instance_info);
+                if device_configs == response.configs && !info_changed {
+                    debug!("Network configuration has not changed. Continuing.");
+                    continue;
+                }
+                debug!(
+                    "Network configuration of instance {} has changed.",
+                    instance_info.name
+                );
+                trace!(
+                    "Old configuration: {device_configs:?}. New configuration: {:?}.",
+                    response.configs,
+                );
+                device_configs = response.configs;
+                if let Some(info) = response.instance {
+                    instance_info = info;
+                }
+                let update = ConfigUpdate {
+                    instance_id: instance_id.clone(),
+                    device_configs: device_configs.clone(),
+                    instance_info: instance_info.clone(),
+                };
+                if tx.send(update).await.is_err() {
+                    debug!("Configuration updates are no longer received, exiting.");
                     break;
                 }
-                debug!("Network configuration has not changed. Continuing.");
             }
             Err(CliError::EnterpriseDisabled) => {
                 debug!("Enterprise features are disabled on this Defguard instance. Skipping.");
@@ -482,6 +1041,884 @@ async fn poll_config(config: &mut CliConfig) {
     }
 }
 
+/// Spawn configuration polling tasks for all instances.
+fn spawn_pollers(
+    config: &CliConfig,
+    tx: &mpsc::Sender<ConfigUpdate>,
+    last_poll: &Arc<Mutex<Instant>>,
+) -> Vec<JoinHandle<()>> {
+    config
+        .instances
+        .iter()
+        .filter(|instance| instance.token.is_some())
+        .map(|instance| {
+            tokio::spawn(poll_config(
+                instance.clone(),
+                tx.clone(),
+                Arc::clone(last_poll),
+            ))
+        })
+        .collect()
+}
+
+/// Identifies a location: instance UUID and location network ID.
+type LocationKey = (String, i64);
+
+/// Connection to a single location, running in a separate task.
+struct LocationConnection {
+    ifname: String,
+    private_key: Key,
+    device_config: proto::DeviceConfig,
+    options: ConnectionOptions,
+    trigger: Arc<Notify>,
+    updates: mpsc::UnboundedSender<ConnectionUpdate>,
+    state: watch::Receiver<Option<bool>>,
+    task: JoinHandle<Result<(), CliError>>,
+}
+
+/// Differences between running connection settings and the desired ones.
+#[derive(Default)]
+struct ConnectionChanges {
+    /// Human-readable list of changed settings.
+    summary: Vec<&'static str>,
+    /// Changes can't be applied to the existing interface.
+    recreate: bool,
+}
+
+impl ConnectionChanges {
+    fn add(&mut self, setting: &'static str, recreate: bool) {
+        self.summary.push(setting);
+        self.recreate |= recreate;
+    }
+
+    fn is_empty(&self) -> bool {
+        self.summary.is_empty()
+    }
+}
+
+impl LocationConnection {
+    fn start(
+        private_key: Key,
+        device_config: proto::DeviceConfig,
+        options: ConnectionOptions,
+        ifname: String,
+    ) -> Self {
+        let trigger = Arc::new(Notify::new());
+        let (updates, updates_rx) = mpsc::unbounded_channel();
+        let (state_tx, state) = watch::channel(None);
+        // Must be spawned as a separate task, otherwise trigger won't reach it.
+        let task = tokio::spawn(connect(
+            private_key.clone(),
+            device_config.clone(),
+            options.clone(),
+            ifname.clone(),
+            Arc::clone(&trigger),
+            updates_rx,
+            state_tx,
+        ));
+        Self {
+            ifname,
+            private_key,
+            device_config,
+            options,
+            trigger,
+            updates,
+            state,
+            task,
+        }
+    }
+
+    /// Wait until the first connection attempt has finished, successfully or not.
+    async fn wait_ready(&mut self) {
+        let _ = self.state.wait_for(Option::is_some).await;
+    }
+
+    /// Check if the interface is up; `false` while connection attempts are being retried.
+    fn is_connected(&self) -> bool {
+        *self.state.borrow() == Some(true)
+    }
+
+    /// Compare connection settings with the desired ones. Address, private key and MTU can't be
+    /// changed on a live interface; neither can routes be withdrawn, so switching traffic
+    /// routing, dropping allowed IPs or clearing DNS also requires re-creating the interface.
+    fn changes(
+        &self,
+        private_key: &Key,
+        device_config: &proto::DeviceConfig,
+        options: &ConnectionOptions,
+    ) -> ConnectionChanges {
+        let current = &self.device_config;
+        let mut changes = ConnectionChanges::default();
+        if self.private_key != *private_key {
+            changes.add("private key", true);
+        }
+        if current.assigned_ip != device_config.assigned_ip {
+            changes.add("address", true);
+        }
+        if self.options.mtu != options.mtu {
+            changes.add("MTU", true);
+        }
+        if self.options.route_all_traffic != options.route_all_traffic {
+            changes.add("traffic routing", true);
+        } else if !options.route_all_traffic && current.allowed_ips != device_config.allowed_ips {
+            let allowed_ips = |config: &proto::DeviceConfig| {
+                config
+                    .allowed_ips
+                    .split(',')
+                    .map(|ip| ip.trim().to_string())
+                    .collect::<HashSet<_>>()
+            };
+            let removed = !allowed_ips(current).is_subset(&allowed_ips(device_config));
+            changes.add("allowed IPs", removed);
+        }
+        if current.dns != device_config.dns {
+            let cleared = device_config.dns.as_deref().is_none_or(str::is_empty);
+            changes.add("DNS", cleared);
+        }
+        if current.pubkey != device_config.pubkey {
+            changes.add("gateway public key", false);
+        }
+        if current.endpoint != device_config.endpoint {
+            changes.add("endpoint", false);
+        }
+        if current.keepalive_interval != device_config.keepalive_interval {
+            changes.add("keepalive interval", false);
+        }
+        if self.options.preshared_key != options.preshared_key {
+            changes.add("preshared key", false);
+        }
+        changes
+    }
+
+    /// Apply new settings to the running connection without re-creating its interface.
+    fn update(&mut self, device_config: proto::DeviceConfig, options: ConnectionOptions) {
+        if self
+            .updates
+            .send((device_config.clone(), options.clone()))
+            .is_err()
+        {
+            debug!(
+                "Connection to network {} has already terminated.",
+                self.device_config.network_name
+            );
+        }
+        self.device_config = device_config;
+        self.options = options;
+    }
+
+    /// Terminate the connection and wait for the interface cleanup.
+    async fn stop(self) {
+        self.trigger.notify_one();
+        let location = &self.device_config.network_name;
+        let interface = &self.ifname;
+        let error = match self.task.await {
+            Ok(Ok(())) => {
+                output::event(&ConnectionEvent::Disconnected {
+                    location,
+                    interface,
+                });
+                return;
+            }
+            Ok(Err(err)) => {
+                error!("Connection to network {location} has failed: {err}");
+                err.to_string()
+            }
+            Err(err) => {
+                error!("Failed to operate: {err}");
+                err.to_string()
+            }
+        };
+        output::event(&ConnectionEvent::Failed {
+            location,
+            interface,
+            error,
+        });
+    }
+}
+
+/// Bring running connections in line with the configuration: connect newly activated
+/// locations, disconnect deactivated ones and reconnect locations which have changed.
+/// Locations with MFA enabled are connected only once MFA has been completed for them, i.e. a
+/// preshared key is present in `session_keys`.
+async fn sync_connections(
+    config: &CliConfig,
+    mtu: Option<u32>,
+    session_keys: &HashMap<LocationKey, Key>,
+    connections: &mut HashMap<LocationKey, LocationConnection>,
+) {
+    let mut desired = HashMap::new();
+    for instance in &config.instances {
+        for network_id in &instance.active_locations {
+            let Some(device_config) = instance.device_config(*network_id) else {
+                continue;
+            };
+            let key = (instance.instance_info.id.clone(), *network_id);
+            let preshared_key = session_keys.get(&key).cloned();
+            if preshared_key.is_none() && mfa::mfa_required(device_config) {
+                warn!(
+                    "Location {} of instance {instance} requires MFA. Use \"dg up {}\" to \
+                    connect to it.",
+                    device_config.network_name, device_config.network_name
+                );
+                continue;
+            }
+            let options = ConnectionOptions {
+                route_all_traffic: instance.routes_all_traffic(*network_id),
+                preshared_key,
+                mtu,
+            };
+            desired.insert(key, (instance, device_config, options));
+        }
+    }
+
+    // Stop connections which are no longer wanted, have already failed, or have changed in
+    // a way which can't be applied in place. Other changes are applied to live interfaces.
+    let mut stale = Vec::new();
+    for (key, connection) in connections.iter_mut() {
+        let Some((instance, device_config, options)) = desired.get(key) else {
+            stale.push(key.clone());
+            continue;
+        };
+        if connection.task.is_finished() {
+            stale.push(key.clone());
+            continue;
+        }
+        let changes = connection.changes(&instance.private_key, device_config, options);
+        if changes.is_empty() {
+            // Nothing affecting the interface has changed, e.g. location name.
+            connection.device_config = (*device_config).clone();
+        } else {
+            info!(
+                "Configuration of network {} has changed: {}.",
+                device_config.network_name,
+                changes.summary.join(", ")
+            );
+            if changes.recreate {
+                info!(
+                    "Interface {} has to be re-created to apply the changes.",
+                    connection.ifname
+                );
+                stale.push(key.clone());
+            } else {
+                output::event(&ConnectionEvent::Updated {
+                    location: &device_config.network_name,
+                    interface: &connection.ifname,
+                    changes: &changes.summary,
+                });
+                connection.update((*device_config).clone(), options.clone());
+            }
+        }
+    }
+    for key in stale {
+        if let Some(connection) = connections.remove(&key) {
+            info!(
+                "Disconnecting from network {}.",
+                connection.device_config.network_name
+            );
+            connection.stop().await;
+        }
+    }
+
+    for (key, (instance, device_config, options)) in desired {
+        if connections.contains_key(&key) {
+            continue;
+        }
+        // Interfaces are created concurrently, so make sure names don't collide.
+        let taken: Vec<&str> = connections
+            .values()
+            .map(|connection| connection.ifname.as_str())
+            .collect();
+        let ifname = get_interface_name_excluding(&device_config.network_name, &taken);
+        info!(
+            "Connecting to network {} of instance {instance} using interface {ifname}.",
+            device_config.network_name
+        );
+        connections.insert(
+            key,
+            LocationConnection::start(
+                instance.private_key.clone(),
+                device_config.clone(),
+                options,
+                ifname,
+            ),
+        );
+    }
+}
+
+/// Handle a request received through the control socket. Connected and disconnected locations
+/// are saved to the configuration at `config_path`, so they persist across reloads and restarts.
+fn handle_control_request(
+    config_path: &Path,
+    config: &mut CliConfig,
+    connections: &HashMap<LocationKey, LocationConnection>,
+    session_keys: &mut HashMap<LocationKey, Key>,
+    request: ControlRequest,
+) -> ControlResponse {
+    let result = match request {
+        ControlRequest::Up {
+            location,
+            instance,
+            route_all_traffic,
+            preshared_key,
+        } => {
+            info!("Received a request to connect to location {location}.");
+            up_location(
+                config,
+                session_keys,
+                &location,
+                instance.as_deref(),
+                route_all_traffic,
+                preshared_key.as_deref(),
+            )
+        }
+        ControlRequest::Down { location, instance } => {
+            info!("Received a request to disconnect from location {location}.");
+            config
+                .deactivate_location(&location, instance.as_deref())
+                .map(|key| {
+                    // MFA session ends with the connection.
+                    session_keys.remove(&key);
+                })
+        }
+        ControlRequest::Status => {
+            debug!("Received a status request.");
+            let mut locations: Vec<LocationStatus> = connections
+                .iter()
+                .map(|((instance_id, _), connection)| {
+                    let instance_name = config
+                        .instances
+                        .iter()
+                        .find(|instance| instance.instance_info.id == *instance_id)
+                        .map(|instance| instance.instance_info.name.as_str())
+                        .unwrap_or_default();
+                    location_status(instance_name, connection)
+                })
+                .collect();
+            locations.sort_by(|a, b| (&a.instance, &a.location).cmp(&(&b.instance, &b.location)));
+            return ControlResponse::Status { locations };
+        }
+    };
+    match result.and_then(|()| config.save(config_path)) {
+        Ok(()) => ControlResponse::Ok,
+        Err(err) => ControlResponse::Error {
+            message: err.to_string(),
+        },
+    }
+}
+
+/// Summary of connected locations, reported to systemd.
+fn connection_status(connections: &HashMap<LocationKey, LocationConnection>) -> String {
+    let mut names: Vec<&str> = connections
+        .values()
+        .filter(|connection| connection.is_connected())
+        .map(|connection| connection.device_config.network_name.as_str())
+        .collect();
+    let retrying = connections.len() - names.len();
+    let mut status = if names.is_empty() {
+        "No locations are connected".to_string()
+    } else {
+        names.sort_unstable();
+        format!(
+            "Connected to {} location(s): {}",
+            names.len(),
+            names.join(", ")
+        )
+    };
+    if retrying > 0 {
+        status.push_str(&format!("; retrying {retrying} location(s)"));
+    }
+    status
+}
+
+/// Decide whether the agent is making progress, for the purpose of systemd watchdog: no
+/// connection task has died, and either configuration polling or WireGuard handshakes succeed.
+/// Connections which are being retried don't count as failed, as restarting won't help them.
+fn is_healthy(connections: &HashMap<LocationKey, LocationConnection>, last_poll: Instant) -> bool {
+    if let Some(connection) = connections
+        .values()
+        .find(|connection| connection.task.is_finished())
+    {
+        warn!(
+            "Connection to network {} has stopped unexpectedly.",
+            connection.device_config.network_name
+        );
+        return false;
+    }
+    if connections.is_empty() || last_poll.elapsed() < STALL_TIMEOUT {
+        return true;
+    }
+    let handshaking = connections.values().any(|connection| {
+        location_status("", connection)
+            .handshake_age
+            .is_some_and(|age| age < STALL_TIMEOUT.as_secs())
+    });
+    if !handshaking {
+        warn!(
+            "Neither configuration polling nor WireGuard handshakes have succeeded for {}s.",
+            STALL_TIMEOUT.as_secs()
+        );
+    }
+    handshaking
+}
+
+/// Wait for the next tick of watchdog `interval`, or forever if the watchdog is disabled.
+async fn watchdog_tick(interval: &mut Option<Interval>) {
+    match interval {
+        Some(interval) => {
+            interval.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+/// Activate a location requested through the control socket, storing the preshared key
+/// obtained through MFA for the duration of the connection.
+fn up_location(
+    config: &mut CliConfig,
+    session_keys: &mut HashMap<LocationKey, Key>,
+    location_name: &str,
+    instance_name: Option<&str>,
+    route_all_traffic: bool,
+    preshared_key: Option<&str>,
+) -> Result<(), CliError> {
+    let (index, network_id) = config.find_location(location_name, instance_name)?;
+    let preshared_key = match preshared_key {
+        Some(preshared_key) => Some(
+            Key::from_str(preshared_key)
+                .map_err(|err| CliError::Mfa(format!("invalid preshared key: {err}")))?,
+        ),
+        None => {
+            let requires_mfa = config.instances[index]
+                .device_config(network_id)
+                .is_some_and(mfa::mfa_required);
+            if requires_mfa {
+                return Err(CliError::Mfa(format!(
+                    "location {location_name} requires MFA, use \"dg up\" to connect"
+                )));
+            }
+            None
+        }
+    };
+    let key = config.activate_location(location_name, instance_name, route_all_traffic)?;
+    match preshared_key {
+        Some(preshared_key) => session_keys.insert(key, preshared_key),
+        None => session_keys.remove(&key),
+    };
+    Ok(())
+}
+
+/// Keep active locations connected until interrupted. Configuration is reloaded on hangup
+/// (HUP) signal and updated by polling Defguard proxy. Locations can also be connected and
+/// disconnected through the control socket at `socket_path`; such changes are saved to
+/// `config_path`.
+async fn run(config_path: &Path, socket_path: &Path, mtu: Option<u32>, mut config: CliConfig) {
+    let mut connections = HashMap::new();
+    // Preshared keys of MFA sessions; kept in memory only, as they expire with the connection.
+    let mut session_keys = HashMap::new();
+    let (tx, mut rx) = mpsc::channel(16);
+    let last_poll = Arc::new(Mutex::new(Instant::now()));
+    let mut pollers = spawn_pollers(&config, &tx, &last_poll);
+    let (control_tx, mut control_rx) = mpsc::channel::<ControlMessage>(16);
+    let control_server = match control::spawn_server(socket_path, control_tx) {
+        Ok(handle) => Some(handle),
+        Err(err) => {
+            warn!("{err}. Control requests won't be accepted.");
+            None
+        }
+    };
+
+    // Ping the watchdog twice per timeout, as recommended by sd_watchdog_enabled(3).
+    let mut watchdog = systemd::watchdog_timeout().map(|timeout| {
+        debug!("systemd watchdog is enabled with timeout of {timeout:?}.");
+        interval(timeout / 2)
+    });
+    let mut sync = true;
+    let mut notify_ready = true;
+
+    debug!("Starting the main CLI loop.");
+    loop {
+        if sync {
+            sync_connections(&config, mtu, &session_keys, &mut connections).await;
+            if connections.is_empty() {
+                info!(
+                    "No locations are selected for connection. Use \"dg up <LOCATION>\" to \
+                    connect."
+                );
+            }
+            if notify_ready {
+                for connection in connections.values_mut() {
+                    connection.wait_ready().await;
+                }
+                let status = connection_status(&connections);
+                systemd::notify(&[State::Ready, State::Status(&status)]);
+                notify_ready = false;
+            } else {
+                systemd::notify(&[State::Status(&connection_status(&connections))]);
+            }
+        }
+        sync = true;
+        select! {
+            biased;
+            () = wait_for_hangup() => {
+                info!("Re-configuring.");
+                systemd::notify(&[State::Reloading]);
+                notify_ready = true;
+                match CliConfig::load(config_path) {
+                    Ok(new_config) => {
+                        info!("Configuration has been reloaded, applying changes.");
+                        config = new_config;
+                        for poller in pollers.drain(..) {
+                            poller.abort();
+                        }
+                        pollers = spawn_pollers(&config, &tx, &last_poll);
+                    }
+                    // Keep running with the previous configuration. Readiness is still reported
+                    // on the next sync, so systemd finishes the reload.
+                    Err(err) => {
+                        error!(
+                            "Failed to reload configuration, keeping the previous one: {err}"
+                        );
+                    }
+                }
+            },
+            _ = ctrl_c() => {
+                debug!("Quitting and shutting down the connections.");
+                break;
+            },
+            Some((request, reply)) = control_rx.recv() => {
+                let response = handle_control_request(
+                    config_path,
+                    &mut config,
+                    &connections,
+                    &mut session_keys,
+                    request,
+                );
+                let _ = reply.send(response);
+            },
+            Some(update) = rx.recv() => {
+                let Some(instance) = config.instance_mut(&update.instance_id) else {
+                    continue;
+                };
+                info!(
+                    "Location configuration of instance {instance} has changed, re-configuring."
+                );
+                instance.device_configs = update.device_configs;
+                instance.instance_info = update.instance_info;
+                instance.retain_known_locations();
+            },
+            () = watchdog_tick(&mut watchdog) => {
+                sync = false;
+                let last_poll = last_poll.lock().map_or_else(|_| Instant::now(), |last| *last);
+                if is_healthy(&connections, last_poll) {
+                    trace!("Pinging systemd watchdog.");
+                    systemd::notify(&[State::Watchdog]);
+                } else {
+                    warn!("The agent is stalled, skipping systemd watchdog ping.");
+                }
+            },
+        }
+    }
+
+    systemd::notify(&[State::Stopping]);
+    if let Some(handle) = control_server {
+        handle.abort();
+        if let Err(err) = std::fs::remove_file(socket_path) {
+            debug!("Failed to remove control socket at {socket_path:?}: {err}");
+        }
+    }
+    for poller in pollers {
+        poller.abort();
+    }
+    for (_, connection) in connections.drain() {
+        connection.stop().await;
+    }
+}
+
+/// Send a request to the running agent and report the outcome.
+async fn control_agent(socket_path: &Path, request: ControlRequest) -> Result<(), CliError> {
+    match control::send_request(socket_path, &request).await? {
+        ControlResponse::Ok => {
+            output::ok();
+            Ok(())
+        }
+        ControlResponse::Status { locations } => {
+            print_status(&locations);
+            Ok(())
+        }
+        ControlResponse::Error { message } => Err(CliError::Agent(message)),
+    }
+}
+
+/// Print state of connected locations, similarly to `wg show`.
+fn print_status(locations: &[LocationStatus]) {
+    if output::is_json() {
+        output::print_json(&serde_json::json!({ "locations": locations }));
+        return;
+    }
+    if locations.is_empty() {
+        println!("No locations are connected.");
+        return;
+    }
+    for status in locations {
+        println!("location: {} ({})", status.location, status.instance);
+        println!("  interface: {}", status.interface);
+        if let Some(endpoint) = &status.endpoint {
+            println!("  endpoint: {endpoint}");
+        }
+        match status.handshake_age {
+            Some(age) => println!("  latest handshake: {age} seconds ago"),
+            None => println!("  latest handshake: never"),
+        }
+        println!(
+            "  transfer: {} B received, {} B sent",
+            status.rx_bytes, status.tx_bytes
+        );
+    }
+}
+
+/// Print all enrolled instances and their locations; active locations are marked with `*`.
+fn list_locations(config: &CliConfig) {
+    if output::is_json() {
+        let instances: Vec<_> = config
+            .instances
+            .iter()
+            .map(|instance| {
+                let locations: Vec<_> = instance
+                    .device_configs
+                    .iter()
+                    .map(|device_config| {
+                        serde_json::json!({
+                            "name": device_config.network_name,
+                            "assigned_ip": device_config.assigned_ip,
+                            "endpoint": device_config.endpoint,
+                            "active": instance.active_locations.contains(&device_config.network_id),
+                            "route_all_traffic":
+                                instance.routes_all_traffic(device_config.network_id),
+                            "mfa": mfa::mfa_required(device_config),
+                        })
+                    })
+                    .collect();
+                serde_json::json!({
+                    "name": instance.instance_info.name,
+                    "url": instance.instance_info.url,
+                    "locations": locations,
+                })
+            })
+            .collect();
+        output::print_json(&serde_json::json!({ "instances": instances }));
+        return;
+    }
+    if config.instances.is_empty() {
+        println!("No instances enrolled.");
+        return;
+    }
+    for instance in &config.instances {
+        println!(
+            "{} ({})",
+            instance.instance_info.name, instance.instance_info.url
+        );
+        for device_config in &instance.device_configs {
+            let marker = if instance
+                .active_locations
+                .contains(&device_config.network_id)
+            {
+                '*'
+            } else {
+                ' '
+            };
+            let traffic = if instance.routes_all_traffic(device_config.network_id) {
+                "all traffic"
+            } else {
+                "predefined traffic"
+            };
+            println!(
+                "  {marker} {}\t{}\t{}\t{traffic}",
+                device_config.network_name, device_config.assigned_ip, device_config.endpoint
+            );
+        }
+    }
+}
+
+/// Mark locations given on the command line as active (to be connected) or inactive.
+fn set_locations_active(
+    config_path: &Path,
+    matches: &ArgMatches,
+    active: bool,
+) -> Result<(), CliError> {
+    let mut config = CliConfig::load(config_path)?;
+    let instance_name = matches.get_one::<String>("instance").map(String::as_str);
+    for location_name in matches.get_many::<String>("location").unwrap_or_default() {
+        if active {
+            let route_all_traffic = matches.get_flag("route_all_traffic");
+            let (instance_id, network_id) =
+                config.activate_location(location_name, instance_name, route_all_traffic)?;
+            let requires_mfa = config
+                .instance_mut(&instance_id)
+                .and_then(|instance| instance.device_config(network_id))
+                .is_some_and(mfa::mfa_required);
+            if requires_mfa {
+                warn!(
+                    "Location {location_name} requires MFA and won't be connected automatically. \
+                    Use \"dg up {location_name}\" to connect to it."
+                );
+            }
+        } else {
+            config.deactivate_location(location_name, instance_name)?;
+        }
+    }
+    config.save(config_path)
+}
+
+/// Connect to a location through the running agent, going through MFA first if the location
+/// requires it.
+async fn up(
+    config_path: &Path,
+    socket_path: &Path,
+    location: String,
+    instance: Option<String>,
+    route_all_traffic: bool,
+    mfa_method: MfaMethod,
+) -> Result<(), CliError> {
+    let config = CliConfig::load(config_path)?;
+    let (index, network_id) = config.find_location(&location, instance.as_deref())?;
+    let cli_instance = &config.instances[index];
+    let preshared_key = match cli_instance.device_config(network_id) {
+        Some(device_config) if mfa::mfa_required(device_config) => {
+            Some(mfa::authenticate(cli_instance, device_config, mfa_method).await?)
+        }
+        _ => None,
+    };
+    control_agent(
+        socket_path,
+        ControlRequest::Up {
+            location,
+            instance,
+            route_all_traffic,
+            preshared_key,
+        },
+    )
+    .await
+}
+
+/// Enroll the device in an instance and save it in the configuration at `config_path`.
+/// Secrets are moved to `secret_storage`, if given.
+async fn enroll_and_save(
+    config_path: &Path,
+    url: &Url,
+    token: String,
+    secret_storage: Option<SecretStorage>,
+) -> Result<(), CliError> {
+    let mut config = CliConfig::load_or_default(config_path)?;
+    if let Some(secret_storage) = secret_storage {
+        config.secret_storage = secret_storage;
+    }
+    let instance = enroll(url, token).await?;
+    debug!("Successfully enrolled the device, saving the configuration.");
+    let instance_name = instance.instance_info.name.clone();
+    let active_locations: Vec<String> = instance
+        .device_configs
+        .iter()
+        .filter(|device_config| {
+            instance
+                .active_locations
+                .contains(&device_config.network_id)
+        })
+        .map(|device_config| device_config.network_name.clone())
+        .collect();
+    let locations: Vec<String> = instance
+        .device_configs
+        .iter()
+        .map(|device_config| device_config.network_name.clone())
+        .collect();
+    config.add_instance(instance);
+    config.save(config_path)?;
+    info!(
+        "Device has been successfully enrolled in instance {instance_name} and the CLI \
+        configuration has been saved to {config_path:?}"
+    );
+    if active_locations.is_empty() {
+        info!(
+            "Use \"dg list\" to display available locations and \"dg connect \
+            <LOCATION>\" to select locations to connect to."
+        );
+    }
+    if output::is_json() {
+        output::print_json(&serde_json::json!({
+            "instance": instance_name,
+            "config": config_path,
+            "locations": locations,
+            "active_locations": active_locations,
+        }));
+    }
+
+    Ok(())
+}
+
+/// Report the error in the selected output format and return the matching exit code.
+fn exit_with_error(err: &CliError) -> ExitCode {
+    let exit_code = err.exit_code();
+    output::error(&err.to_string(), exit_code);
+    ExitCode::from(exit_code)
+}
+
+/// Remove an enrolled instance along with all its locations.
+fn remove_instance(config_path: &Path, instance_name: &str) -> Result<(), CliError> {
+    let mut config = CliConfig::load(config_path)?;
+    let Some(index) = config
+        .instances
+        .iter()
+        .position(|instance| instance.instance_info.name == instance_name)
+    else {
+        return Err(CliError::InstanceNotFound(instance_name.to_string()));
+    };
+    let instance = config.instances.remove(index);
+    config.save(config_path)?;
+    if config.secret_storage == SecretStorage::Keyring {
+        secrets::forget(&instance.instance_info.id);
+    }
+    Ok(())
+}
+
+/// Export locations as WireGuard configuration files and report their paths.
+fn export_locations(
+    config_path: &Path,
+    mtu: Option<u32>,
+    matches: &ArgMatches,
+) -> Result<(), CliError> {
+    let config = CliConfig::load(config_path)?;
+    let location_name = matches.get_one::<String>("location").map(String::as_str);
+    let instance_name = matches.get_one::<String>("instance").map(String::as_str);
+    let dir = matches
+        .get_one::<PathBuf>("dir")
+        .expect("Output directory has a default value");
+    let paths = export::export(&config, location_name, instance_name, mtu, dir)?;
+    if output::is_json() {
+        output::print_json(&serde_json::json!({ "files": paths }));
+    } else if paths.is_empty() {
+        println!("No locations to export.");
+    } else {
+        for path in paths {
+            println!("{}", path.display());
+        }
+    }
+    Ok(())
+}
+
+/// Move secrets of all instances to another storage.
+fn migrate_secrets(config_path: &Path, storage: SecretStorage) -> Result<(), CliError> {
+    let mut config = CliConfig::load(config_path)?;
+    let previous = config.secret_storage;
+    config.secret_storage = storage;
+    config.save(config_path)?;
+    if previous == SecretStorage::Keyring && storage != SecretStorage::Keyring {
+        for instance in &config.instances {
+            secrets::forget(&instance.instance_info.id);
+        }
+    }
+    info!("Secrets have been moved from {previous:?} to {storage:?} storage.");
+    Ok(())
+}
+
 /// Wait for hangup (HUP) signal.
 #[cfg(unix)]
 async fn wait_for_hangup() {
@@ -495,9 +1932,8 @@ async fn wait_for_hangup() {
     sleep(Duration::new(u64::MAX, 0)).await;
 }
 
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 #[tokio::main]
-async fn main() {
+async fn main() -> ExitCode {
     // Define command line arguments.
     let config_opt = Arg::new("config")
         .help("Configuration file path")
@@ -506,6 +1942,15 @@ async fn main() {
         .value_name("CONFIG")
         .env("DG_CONFIG")
         .value_parser(value_parser!(PathBuf));
+    let socket_opt = Arg::new("socket")
+        .help("Control socket path of the running dg agent")
+        .long("socket")
+        .short('s')
+        .value_name("SOCKET")
+        .env("DG_SOCKET")
+        .default_value(control::DEFAULT_SOCKET_PATH)
+        .global(true)
+        .value_parser(value_parser!(PathBuf));
     let debug_opt = Arg::new("debug")
         .help("Enable debug logs")
         .long("debug")
@@ -514,6 +1959,15 @@ async fn main() {
         .env("DG_DEBUG")
         .global(true)
         .action(clap::ArgAction::SetTrue);
+    let output_opt = Arg::new("output")
+        .help("Output format; JSON documents are printed one per line, logs go to stderr")
+        .long("output")
+        .short('o')
+        .value_name("FORMAT")
+        .env("DG_OUTPUT")
+        .default_value("text")
+        .global(true)
+        .value_parser(value_parser!(OutputFormat));
     let verbose_opt = Arg::new("verbose")
         .help("Enable logging everything")
         .long("verbose")
@@ -535,11 +1989,62 @@ async fn main() {
         .short('u')
         .value_name("URL")
         .value_parser(value_parser!(Url));
+    let location_arg = Arg::new("location")
+        .help("Location name")
+        .required(true)
+        .num_args(1..)
+        .value_name("LOCATION");
+    let instance_opt = Arg::new("instance")
+        .help("Instance name; required if the location name is used by more than one instance")
+        .long("instance")
+        .short('i')
+        .value_name("INSTANCE");
+    let route_all_traffic_opt = Arg::new("route_all_traffic")
+        .help(
+            "Route all traffic through the tunnel, unless the instance's traffic policy forbids \
+            it",
+        )
+        .long("route-all-traffic")
+        .short('a')
+        .action(clap::ArgAction::SetTrue);
+    let mfa_method_opt = Arg::new("mfa_method")
+        .help("MFA method used for locations with internal MFA")
+        .long("mfa-method")
+        .value_name("METHOD")
+        .default_value("totp")
+        .value_parser(value_parser!(MfaMethod));
+    let key_file_opt = Arg::new("key_file")
+        .help(
+            "Key file used to encrypt secrets; a passphrase can be given in DG_PASSPHRASE \
+            instead",
+        )
+        .long("key-file")
+        .short('k')
+        .value_name("KEY_FILE")
+        .env("DG_KEY_FILE")
+        .global(true)
+        .value_parser(value_parser!(PathBuf));
+    let storage_arg = Arg::new("storage")
+        .help("Where to store private keys and polling tokens")
+        .value_name("STORAGE")
+        .value_parser(value_parser!(SecretStorage));
+    let mtu_opt = Arg::new("mtu")
+        .help("MTU of the WireGuard interfaces")
+        .long("mtu")
+        .short('m')
+        .value_name("MTU")
+        .env("DG_MTU")
+        .value_parser(value_parser!(u32));
 
     let matches = command!()
         .arg(config_opt)
+        .arg(socket_opt)
+        .arg(mtu_opt)
         .arg(debug_opt)
         .arg(verbose_opt)
+        .arg(output_opt)
+        .arg(key_file_opt)
+        .after_help(EXIT_CODES_HELP)
         .arg_required_else_help(false)
         .propagate_version(true)
         .subcommand_required(false)
@@ -550,7 +2055,97 @@ async fn main() {
                     device.",
                 )
                 .arg(token_opt)
-                .arg(url_opt),
+                .arg(url_opt)
+                .arg(storage_arg.clone().long("secret-storage")),
+        )
+        .subcommand(
+            Command::new("list")
+                .visible_alias("ls")
+                .about("List enrolled instances and their locations. Active locations are marked."),
+        )
+        .subcommand(
+            Command::new("connect")
+                .about(
+                    "Mark locations as active. Running dg connects them after reloading the \
+                    configuration.",
+                )
+                .arg(location_arg.clone())
+                .arg(instance_opt.clone())
+                .arg(route_all_traffic_opt.clone()),
+        )
+        .subcommand(
+            Command::new("disconnect")
+                .about(
+                    "Mark locations as inactive. Running dg disconnects them after reloading the \
+                    configuration.",
+                )
+                .arg(location_arg.clone())
+                .arg(instance_opt.clone()),
+        )
+        .subcommand(
+            Command::new("up")
+                .about(
+                    "Connect to a location using the running dg agent. Locations with MFA \
+                    enabled ask for a code or an OpenID login first.",
+                )
+                .arg(location_arg.clone().num_args(1))
+                .arg(instance_opt.clone())
+                .arg(route_all_traffic_opt)
+                .arg(mfa_method_opt),
+        )
+        .subcommand(
+            Command::new("down")
+                .about("Disconnect from a location using the running dg agent.")
+                .arg(location_arg.num_args(1))
+                .arg(instance_opt.clone()),
+        )
+        .subcommand(Command::new("status").about(
+            "Show connected locations of the running dg agent, with their latest handshake \
+                and transfer statistics.",
+        ))
+        .subcommand(
+            Command::new("remove")
+                .about("Remove an enrolled instance along with all its locations.")
+                .arg(
+                    Arg::new("instance")
+                        .help("Instance name")
+                        .required(true)
+                        .value_name("INSTANCE"),
+                ),
+        )
+        .subcommand(
+            Command::new("export")
+                .about(
+                    "Export locations as WireGuard configuration files, usable with wg-quick. \
+                    Files are named after locations. Locations with MFA enabled are skipped. \
+                    MTU given with --mtu is included.",
+                )
+                .arg(
+                    Arg::new("location")
+                        .help("Export only this location")
+                        .long("location")
+                        .short('l')
+                        .value_name("LOCATION"),
+                )
+                .arg(instance_opt)
+                .arg(
+                    Arg::new("dir")
+                        .help("Directory to write configuration files to")
+                        .long("dir")
+                        .short('D')
+                        .value_name("DIR")
+                        .default_value(".")
+                        .value_parser(value_parser!(PathBuf)),
+                ),
+        )
+        .subcommand(
+            Command::new("secrets")
+                .about(
+                    "Move private keys and polling tokens of all instances to another storage: \
+                    the configuration file (plaintext), the system keyring, or the \
+                    configuration file encrypted with a passphrase or a key file.",
+                )
+                .arg(storage_arg.required(true)),
         )
         .get_matches();
 
@@ -562,6 +2157,20 @@ async fn main() {
         LevelFilter::INFO
     };
 
+    let output_format = *matches
+        .get_one::<OutputFormat>("output")
+        .expect("Output format has a default value");
+    output::set_format(output_format);
+    if let Some(key_file) = matches.get_one::<PathBuf>("key_file") {
+        secrets::set_key_file(key_file.clone());
+    }
+    // Keep standard output clean for JSON documents.
+    let log_writer = if output_format == OutputFormat::Json {
+        BoxMakeWriter::new(std::io::stderr)
+    } else {
+        BoxMakeWriter::new(std::io::stdout)
+    };
+
     tracing_subscriber::registry()
         .with(
             EnvFilter::builder()
@@ -570,7 +2179,7 @@ async fn main() {
                 .add_directive("hyper_util=error".parse().unwrap())
                 .add_directive("reqwest=error".parse().unwrap()),
         )
-        .with(tracing_subscriber::fmt::layer())
+        .with(tracing_subscriber::fmt::layer().with_writer(log_writer))
         .init();
 
     debug!("Starting CLI.");
@@ -584,117 +2193,337 @@ async fn main() {
                 if !path.exists() {
                     if let Err(err) = create_dir_all(&path) {
                         error!("Failed to create default configuration path: {err}");
-                        return;
+                        return exit_with_error(&CliError::ConfigSave(
+                            path.to_string_lossy().to_string(),
+                            err.to_string(),
+                        ));
                     }
                 }
                 path.push("config.json");
                 path
             } else {
-                error!(
-                    "Default configuration path is not available on this platform. Please, \
-                    specify it explicitly."
-                );
-                return;
+                let message = "Default configuration path is not available on this platform. \
+                    Please, specify it explicitly.";
+                error!("{message}");
+                output::error(message, 1);
+                return ExitCode::FAILURE;
             }
         }
     };
     debug!("The following configuration will be used: {config_path:?}");
+    let socket_path = matches
+        .get_one::<PathBuf>("socket")
+        .expect("Control socket path has a default value")
+        .clone();
 
-    if let Some(("enroll", submatches)) = matches.subcommand() {
-        debug!("Enrollment command has been selected, starting enrollment.");
-        let token = submatches
-            .get_one::<String>("token")
-            .expect("No enrollment token was provided or it's invalid")
-            .clone();
-        let url = submatches
-            .get_one::<Url>("url")
-            .expect("No enrollment URL was provided or it's invalid");
-        debug!("Successfully parsed enrollment token and URL");
-        let config = match enroll(url, token).await {
-            Ok(cfg) => cfg,
-            Err(err) => {
-                error!("Enrollment process failed with error: {err}");
-                return;
-            }
-        };
-        debug!("Successfully enrolled the device, saving the configuration.");
-        if let Err(err) = config.save(&config_path) {
-            error!("{err}");
-            return;
+    let result = match matches.subcommand() {
+        Some(("enroll", submatches)) => {
+            debug!("Enrollment command has been selected, starting enrollment.");
+            let token = submatches
+                .get_one::<String>("token")
+                .expect("No enrollment token was provided or it's invalid")
+                .clone();
+            let url = submatches
+                .get_one::<Url>("url")
+                .expect("No enrollment URL was provided or it's invalid");
+            debug!("Successfully parsed enrollment token and URL");
+            let secret_storage = submatches.get_one::<SecretStorage>("storage").copied();
+            enroll_and_save(&config_path, url, token, secret_storage)
+                .await
+                .inspect_err(|err| error!("Enrollment process failed with error: {err}"))
         }
-        info!(
-            "Device has been successfully enrolled and the CLI configuration has been saved to \
-            {config_path:?}"
-        );
-    } else {
-        debug!("No command has been selected, trying to proceed with establishing a connection.");
-        let mut config = match CliConfig::load(&config_path) {
-            Ok(config) => config,
-            Err(err) => {
-                if let CliError::ConfigNotFound(path) = err {
+        Some(("list", _)) => CliConfig::load_or_default(&config_path)
+            .map(|config| list_locations(&config))
+            .inspect_err(|err| error!("Failed to load CLI configuration: {err}")),
+        Some((command @ ("connect" | "disconnect"), submatches)) => {
+            let active = command == "connect";
+            set_locations_active(&config_path, submatches, active)
+                .map(|()| {
+                    info!(
+                        "Location selection has been saved. Reload running dg (e.g. \"systemctl \
+                        reload dg\") to apply the changes."
+                    );
+                    output::ok();
+                })
+                .inspect_err(|err| error!("Failed to {command} locations: {err}"))
+        }
+        Some((command @ ("up" | "down"), submatches)) => {
+            let location = submatches
+                .get_one::<String>("location")
+                .expect("No location name was provided")
+                .clone();
+            let instance = submatches.get_one::<String>("instance").cloned();
+            let result = if command == "up" {
+                let mfa_method = *submatches
+                    .get_one::<MfaMethod>("mfa_method")
+                    .expect("MFA method has a default value");
+                up(
+                    &config_path,
+                    &socket_path,
+                    location,
+                    instance,
+                    submatches.get_flag("route_all_traffic"),
+                    mfa_method,
+                )
+                .await
+            } else {
+                control_agent(&socket_path, ControlRequest::Down { location, instance }).await
+            };
+            result.inspect_err(|err| error!("{err}"))
+        }
+        Some(("status", _)) => control_agent(&socket_path, ControlRequest::Status)
+            .await
+            .inspect_err(|err| error!("{err}")),
+        Some(("remove", submatches)) => {
+            let instance_name = submatches
+                .get_one::<String>("instance")
+                .expect("No instance name was provided");
+            remove_instance(&config_path, instance_name)
+                .map(|()| {
+                    info!("Instance {instance_name} has been removed.");
+                    output::ok();
+                })
+                .inspect_err(|err| error!("Failed to remove instance: {err}"))
+        }
+        Some(("export", submatches)) => {
+            let mtu = matches.get_one::<u32>("mtu").copied();
+            export_locations(&config_path, mtu, submatches)
+                .inspect_err(|err| error!("Failed to export locations: {err}"))
+        }
+        Some(("secrets", submatches)) => {
+            let storage = *submatches
+                .get_one::<SecretStorage>("storage")
+                .expect("No secret storage was provided");
+            migrate_secrets(&config_path, storage)
+                .map(|()| output::ok())
+                .inspect_err(|err| error!("Failed to move secrets: {err}"))
+        }
+        _ => {
+            debug!(
+                "No command has been selected, trying to proceed with establishing connections."
+            );
+            match CliConfig::load(&config_path) {
+                Ok(config) => {
+                    info!("Using the following CLI configuration: {config_path:?}");
+                    debug!("Successfully loaded CLI configuration");
+                    trace!("CLI configuration: {config:?}");
+                    let mtu = matches.get_one::<u32>("mtu").copied();
+                    run(&config_path, &socket_path, mtu, config).await;
+                    Ok(())
+                }
+                Err(CliError::ConfigNotFound(path)) => {
                     error!(
-                        "No CLI configuration file found at \"{path}\". Proceed with \
-                        enrollment first using \"dg enroll -t <TOKEN> -u <URL>\" or pass a valid \
+                        "No CLI configuration file found at \"{path}\". Proceed with enrollment \
+                        first using \"dg enroll -t <TOKEN> -u <URL>\" or pass a valid \
                         configuration file path using the \"--config\" option. Use \"dg --help\" \
                         to display all options."
                     );
-                    return;
+                    Err(CliError::ConfigNotFound(path))
+                }
+                Err(err) => {
+                    error!("Failed to load CLI configuration: {err}");
+                    Err(err)
                 }
-                error!("Failed to load CLI configuration: {err}");
-                return;
             }
+        }
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => exit_with_error(&err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+
+    use super::*;
+    use crate::fixtures::{connection, device_config, instance};
+
+    #[test]
+    fn select_locations() {
+        let mut config = CliConfig::default();
+        config.add_instance(instance("acme", &[(1, "office"), (2, "lab")]));
+        config.add_instance(instance("globex", &[(1, "office"), (3, "factory")]));
+
+        assert!(matches!(
+            config.find_location("office", None),
+            Err(CliError::AmbiguousLocation(_))
+        ));
+        assert!(matches!(
+            config.find_location("warehouse", None),
+            Err(CliError::LocationNotFound(_))
+        ));
+        assert_eq!(
+            config.find_location("office", Some("globex")).unwrap(),
+            (1, 1)
+        );
+        assert_eq!(config.find_location("lab", None).unwrap(), (0, 2));
+
+        assert_eq!(
+            config.activate_location("lab", None, true).unwrap(),
+            ("acme".to_string(), 2)
+        );
+        config
+            .activate_location("office", Some("acme"), false)
+            .unwrap();
+        assert_eq!(config.instances[0].active_locations, HashSet::from([1, 2]));
+        assert_eq!(config.instances[0].route_all_traffic, HashSet::from([2]));
+        config.deactivate_location("office", Some("acme")).unwrap();
+        assert_eq!(config.instances[0].active_locations, HashSet::from([2]));
+
+        // Re-enrollment keeps selected locations which still exist.
+        config.add_instance(instance("acme", &[(2, "lab"), (4, "lab 2")]));
+        assert_eq!(config.instances.len(), 2);
+        assert_eq!(config.instances[0].device_configs.len(), 2);
+        assert_eq!(config.instances[0].active_locations, HashSet::from([2]));
+        assert_eq!(config.instances[0].route_all_traffic, HashSet::from([2]));
+        config.add_instance(instance("acme", &[(4, "lab 2")]));
+        assert!(config.instances[0].active_locations.is_empty());
+        assert!(config.instances[0].route_all_traffic.is_empty());
+    }
+
+    #[test]
+    fn load_legacy_config() {
+        let private_key = Key::generate();
+        let device_config = device_config(1, "office");
+        let legacy = serde_json::json!({
+            "private_key": private_key,
+            "device": proto::Device::default(),
+            "device_config": device_config,
+            "instance_info": proto::InstanceInfo {
+                id: "instance".into(),
+                name: "acme".into(),
+                ..Default::default()
+            },
+            "token": "token",
+        });
+        let path = env::temp_dir().join(format!("dg-legacy-{}.json", process::id()));
+        fs::write(&path, legacy.to_string()).unwrap();
+        let config = CliConfig::load(&path);
+        fs::remove_file(&path).unwrap();
+
+        let config = config.unwrap();
+        assert_eq!(config.secret_storage, SecretStorage::Plaintext);
+        let [instance] = config.instances.as_slice() else {
+            panic!(
+                "expected a single instance, found {}",
+                config.instances.len()
+            );
         };
-        info!("Using the following CLI configuration: {config_path:?}");
-        debug!("Successfully loaded CLI configuration");
-        trace!("CLI configuration: {config:?}");
-        let trigger = Arc::new(Notify::new());
-        let mut perpetuum = true;
-
-        // Network interface name should not change in the loop below.
-        let ifname = get_interface_name(&config.device.name);
-
-        debug!("Starting the main CLI loop.");
-        while perpetuum {
-            debug!("Starting the connection task.");
-            // Must be spawned as a separate task, otherwise trigger won't reach it.
-            let task = tokio::spawn(connect(config.clone(), ifname.clone(), trigger.clone()));
-            debug!("Connection task has been spawned.");
-            // After cancelling the connection a given task should wait for cleanup confirmation.
-            select! {
-                biased;
-                () = wait_for_hangup() => {
-                    info!("Re-configuring.");
-                    trigger.notify_one();
-                    match CliConfig::load(&config_path) {
-                        Ok(new_config) => {
-                            info!("Configuration has been reloaded, resetting the connection.");
-                            config = new_config;
-                        }
-                        Err(err) => {
-                          error!("Failed to load configuration: {err}");
-                            perpetuum = false;
-                        }
-                    }
-                    trigger.notified().await;
-                },
-                _ = ctrl_c() => {
-                    trigger.notify_one();
-                    debug!("Quitting and shutting down the connection.");
-                    perpetuum = false;
-                    trigger.notified().await;
-                },
-                () = poll_config(&mut config), if config.token.is_some() => {
-                    info!("Location configuration has changed, re-configuring and resetting the \
-                        connection.");
-                    trigger.notify_one();
-                    trigger.notified().await;
-                },
-                Err(err) = task => {
-                    error!("Failed to operate: {err}");
-                    trigger.notify_one();
-                    break;
-                },
-            }
+        assert_eq!(instance.private_key.to_string(), private_key.to_string());
+        assert_eq!(instance.token.as_deref(), Some("token"));
+        assert_eq!(instance.instance_info.name, "acme");
+        assert_eq!(instance.device_configs, [device_config]);
+        // The only location used to be connected unconditionally.
+        assert_eq!(instance.active_locations, HashSet::from([1]));
+        assert!(instance.route_all_traffic.is_empty());
+    }
+
+    #[test]
+    fn exit_codes() {
+        let errors = [
+            CliError::DefguardApi(String::new()),
+            CliError::MissingData,
+            CliError::Reqwest(Client::new().get("not a URL").build().unwrap_err()),
+            CliError::NoDevices,
+            CliError::WireGuard(WireguardInterfaceError::Interface(String::new())),
+            CliError::ConfigNotFound(String::new()),
+            CliError::ConfigParse(String::new(), String::new()),
+            CliError::EnterpriseDisabled,
+            CliError::ConfigSave(String::new(), String::new()),
+            CliError::FreeTCPPort,
+            CliError::LocationNotFound(String::new()),
+            CliError::AmbiguousLocation(String::new()),
+            CliError::InstanceNotFound(String::new()),
+            CliError::ControlSocket(String::new(), String::new()),
+            CliError::Agent(String::new()),
+            CliError::Mfa(String::new()),
+            CliError::InterfaceSetup(String::new(), String::new()),
+            CliError::InvalidEndpoint(String::new(), String::new()),
+            CliError::InvalidPeerKey(String::new(), String::new()),
+            CliError::Secrets(String::new()),
+            CliError::Export(String::new(), String::new()),
+        ];
+        let codes: HashSet<u8> = errors.iter().map(CliError::exit_code).collect();
+        assert_eq!(codes.len(), errors.len(), "exit codes must be distinct");
+        for code in codes {
+            // 1 and 2 are used for unexpected errors and invalid arguments.
+            assert!(code > 2);
+            assert!(
+                EXIT_CODES_HELP
+                    .lines()
+                    .any(|line| line.trim_start().starts_with(&format!("{code} "))),
+                "exit code {code} is missing from help"
+            );
         }
     }
+
+    #[tokio::test]
+    async fn connection_changes() {
+        let private_key = Key::generate();
+        let current = device_config(1, "office");
+        let options = ConnectionOptions::default();
+        let connection = connection(&private_key, &current, &options);
+
+        assert!(connection
+            .changes(&private_key, &current, &options)
+            .is_empty());
+
+        // Peer settings and added allowed IPs are applied in place.
+        let mut desired = current.clone();
+        desired.endpoint = "vpn2.example.com:51820".into();
+        desired.keepalive_interval = 10;
+        desired.allowed_ips = "10.1.0.0/24,10.0.0.0/24,10.2.0.0/24".into();
+        desired.dns = Some("10.0.0.53".into());
+        let changes = connection.changes(&private_key, &desired, &options);
+        assert_eq!(
+            changes.summary,
+            ["allowed IPs", "DNS", "endpoint", "keepalive interval"]
+        );
+        assert!(!changes.recreate);
+
+        // Routes and DNS can't be withdrawn from a live interface.
+        let mut desired = current.clone();
+        desired.allowed_ips = "10.0.0.0/24".into();
+        let changes = connection.changes(&private_key, &desired, &options);
+        assert_eq!(changes.summary, ["allowed IPs"]);
+        assert!(changes.recreate);
+        let mut desired = current.clone();
+        desired.dns = None;
+        assert!(
+            connection
+                .changes(&private_key, &desired, &options)
+                .recreate
+        );
+
+        // Allowed IPs don't matter when all traffic is routed through the tunnel.
+        let all_traffic = ConnectionOptions {
+            route_all_traffic: true,
+            ..Default::default()
+        };
+        let changes = connection.changes(&private_key, &current, &all_traffic);
+        assert_eq!(changes.summary, ["traffic routing"]);
+        assert!(changes.recreate);
+        let connection = self::connection(&private_key, &current, &all_traffic);
+        let mut desired = current.clone();
+        desired.allowed_ips = "10.0.0.0/24".into();
+        assert!(connection
+            .changes(&private_key, &desired, &all_traffic)
+            .is_empty());
+
+        let changes = connection.changes(&Key::generate(), &current, &all_traffic);
+        assert_eq!(changes.summary, ["private key"]);
+        assert!(changes.recreate);
+        let mut desired = current.clone();
+        desired.assigned_ip = "10.0.0.3/24".into();
+        let with_psk = ConnectionOptions {
+            preshared_key: Some(Key::generate()),
+            ..all_traffic
+        };
+        let changes = connection.changes(&private_key, &desired, &with_psk);
+        assert_eq!(changes.summary, ["address", "preshared key"]);
+        assert!(changes.recreate);
+    }
 }
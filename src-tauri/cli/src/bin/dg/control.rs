@@ -0,0 +1,277 @@
+//! Control socket of a running `dg` agent.
+//!
+//! Requests and responses are exchanged as JSON documents, one per line, over a Unix domain
+//! socket, so the agent can be scripted with tools other than `dg` itself.
+
+use std::path::Path;
+#[cfg(unix)]
+use std::{fs, os::unix::fs::PermissionsExt};
+
+#[cfg(unix)]
+use nix::unistd::{chown, Group};
+use serde::{Deserialize, Serialize};
+#[cfg(unix)]
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{UnixListener, UnixStream},
+};
+use tokio::{
+    sync::{mpsc, oneshot},
+    task::JoinHandle,
+};
+#[cfg(unix)]
+use tracing::{debug, error, info, warn};
+
+use crate::CliError;
+
+pub(crate) const DEFAULT_SOCKET_PATH: &str = "/var/run/defguard-dg.socket";
+#[cfg(unix)]
+const SOCKET_GROUP: &str = "defguard";
+
+/// Request sent to the agent together with a channel for the response.
+pub(crate) type ControlMessage = (ControlRequest, oneshot::Sender<ControlResponse>);
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub(crate) enum ControlRequest {
+    /// Connect to a location.
+    Up {
+        location: String,
+        instance: Option<String>,
+        #[serde(default)]
+        route_all_traffic: bool,
+        /// Preshared key obtained through MFA; required by locations with MFA enabled.
+        #[serde(default)]
+        preshared_key: Option<String>,
+    },
+    /// Disconnect from a location.
+    Down {
+        location: String,
+        instance: Option<String>,
+    },
+    /// Report state of all connected locations.
+    Status,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub(crate) enum ControlResponse {
+    Ok,
+    Status { locations: Vec<LocationStatus> },
+    Error { message: String },
+}
+
+/// State of a connected location.
+#[derive(Debug, Deserialize, Serialize)]
+pub(crate) struct LocationStatus {
+    pub(crate) instance: String,
+    pub(crate) location: String,
+    pub(crate) interface: String,
+    pub(crate) endpoint: Option<String>,
+    /// Seconds since the latest handshake; `None` if there wasn't any yet.
+    pub(crate) handshake_age: Option<u64>,
+    pub(crate) rx_bytes: u64,
+    pub(crate) tx_bytes: u64,
+}
+
+/// Start serving control requests on `path` in a separate task.
+#[cfg(unix)]
+pub(crate) fn spawn_server(
+    path: &Path,
+    tx: mpsc::Sender<ControlMessage>,
+) -> Result<JoinHandle<()>, CliError> {
+    let listener = bind(path)?;
+    Ok(tokio::spawn(serve(listener, tx)))
+}
+
+/// Dummy version of the above function for non-UNIX systems.
+#[cfg(not(unix))]
+pub(crate) fn spawn_server(
+    path: &Path,
+    _tx: mpsc::Sender<ControlMessage>,
+) -> Result<JoinHandle<()>, CliError> {
+    Err(unsupported(path))
+}
+
+/// Bind the control socket at `path`.
+/// Members of the `defguard` group, if it exists, are allowed to control the agent.
+#[cfg(unix)]
+fn bind(path: &Path) -> Result<UnixListener, CliError> {
+    let socket_error = |err: &dyn std::fmt::Display| {
+        CliError::ControlSocket(path.to_string_lossy().to_string(), err.to_string())
+    };
+    if path.exists() {
+        debug!("Removing existing socket file at {path:?}");
+        fs::remove_file(path).map_err(|err| socket_error(&err))?;
+    }
+    debug!("Binding control socket at {path:?}");
+    let listener = UnixListener::bind(path).map_err(|err| socket_error(&err))?;
+
+    match Group::from_name(SOCKET_GROUP) {
+        Ok(Some(group)) => {
+            debug!("Changing owner group of control socket at {path:?} to group {SOCKET_GROUP}");
+            if let Err(err) = chown(path, None, Some(group.gid)) {
+                warn!("Failed to change owner group of control socket at {path:?}: {err}");
+            }
+        }
+        Ok(None) => debug!("Group {SOCKET_GROUP} not found, socket will be accessible to owner"),
+        Err(err) => warn!("Failed to look up group {SOCKET_GROUP}: {err}"),
+    }
+    // 0o660 allows read/write for owner and group only
+    fs::set_permissions(path, fs::Permissions::from_mode(0o660))
+        .map_err(|err| socket_error(&err))?;
+
+    info!("Listening for control requests on {path:?}");
+    Ok(listener)
+}
+
+/// Accept control connections and forward their requests to the agent's main loop.
+#[cfg(unix)]
+async fn serve(listener: UnixListener, tx: mpsc::Sender<ControlMessage>) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                tokio::spawn(handle_client(stream, tx.clone()));
+            }
+            Err(err) => {
+                error!("Failed to accept control socket connection: {err}");
+            }
+        }
+    }
+}
+
+/// Handle requests of a single control socket client until it disconnects.
+#[cfg(unix)]
+async fn handle_client(stream: UnixStream, tx: mpsc::Sender<ControlMessage>) {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        debug!("Received control request: {line}");
+        let response = match serde_json::from_str::<ControlRequest>(&line) {
+            Ok(request) => {
+                let (reply_tx, reply_rx) = oneshot::channel();
+                if tx.send((request, reply_tx)).await.is_err() {
+                    debug!("The agent is shutting down, closing control connection.");
+                    break;
+                }
+                reply_rx.await.unwrap_or_else(|_| ControlResponse::Error {
+                    message: "The agent is shutting down".into(),
+                })
+            }
+            Err(err) => ControlResponse::Error {
+                message: format!("Invalid request: {err}"),
+            },
+        };
+        let mut payload = match serde_json::to_vec(&response) {
+            Ok(payload) => payload,
+            Err(err) => {
+                error!("Failed to serialize control response: {err}");
+                break;
+            }
+        };
+        payload.push(b'\n');
+        if let Err(err) = writer.write_all(&payload).await {
+            debug!("Failed to send control response: {err}");
+            break;
+        }
+    }
+}
+
+/// Send a single request to the agent listening on `path` and wait for its response.
+#[cfg(unix)]
+pub(crate) async fn send_request(
+    path: &Path,
+    request: &ControlRequest,
+) -> Result<ControlResponse, CliError> {
+    let socket_error = |err: &dyn std::fmt::Display| {
+        CliError::ControlSocket(path.to_string_lossy().to_string(), err.to_string())
+    };
+    let stream = UnixStream::connect(path)
+        .await
+        .map_err(|err| socket_error(&err))?;
+    let (reader, mut writer) = stream.into_split();
+
+    let mut payload = serde_json::to_vec(request).map_err(|err| socket_error(&err))?;
+    payload.push(b'\n');
+    writer
+        .write_all(&payload)
+        .await
+        .map_err(|err| socket_error(&err))?;
+
+    let mut lines = BufReader::new(reader).lines();
+    let Some(line) = lines.next_line().await.map_err(|err| socket_error(&err))? else {
+        return Err(socket_error(&"connection closed by the agent"));
+    };
+    serde_json::from_str(&line).map_err(|err| socket_error(&err))
+}
+
+/// Dummy version of the above function for non-UNIX systems.
+#[cfg(not(unix))]
+pub(crate) async fn send_request(
+    path: &Path,
+    _request: &ControlRequest,
+) -> Result<ControlResponse, CliError> {
+    Err(unsupported(path))
+}
+
+#[cfg(not(unix))]
+fn unsupported(path: &Path) -> CliError {
+    CliError::ControlSocket(
+        path.to_string_lossy().to_string(),
+        "control socket is not supported on this platform".into(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_requests() {
+        let request: ControlRequest =
+            serde_json::from_str(r#"{"command":"up","location":"office"}"#).unwrap();
+        assert!(matches!(
+            request,
+            ControlRequest::Up {
+                location,
+                instance: None,
+                route_all_traffic: false,
+                preshared_key: None,
+            } if location == "office"
+        ));
+
+        let request: ControlRequest = serde_json::from_str(
+            r#"{"command":"up","location":"office","instance":"acme","route_all_traffic":true,
+            "preshared_key":"key"}"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            request,
+            ControlRequest::Up {
+                location,
+                instance: Some(instance),
+                route_all_traffic: true,
+                preshared_key: Some(preshared_key),
+            } if location == "office" && instance == "acme" && preshared_key == "key"
+        ));
+
+        let request: ControlRequest =
+            serde_json::from_str(r#"{"command":"down","location":"office","instance":"acme"}"#)
+                .unwrap();
+        assert!(matches!(
+            request,
+            ControlRequest::Down {
+                location,
+                instance: Some(instance),
+            } if location == "office" && instance == "acme"
+        ));
+
+        let request: ControlRequest = serde_json::from_str(r#"{"command":"status"}"#).unwrap();
+        assert!(matches!(request, ControlRequest::Status));
+
+        // Unknown commands and missing locations are rejected.
+        assert!(serde_json::from_str::<ControlRequest>(r#"{"command":"restart"}"#).is_err());
+        assert!(serde_json::from_str::<ControlRequest>(r#"{"command":"up"}"#).is_err());
+        assert!(serde_json::from_str::<ControlRequest>("status").is_err());
+    }
+}
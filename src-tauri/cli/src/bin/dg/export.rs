@@ -0,0 +1,271 @@
+//! Export of locations as standard WireGuard configuration files, usable with `wg-quick`,
+//! NetworkManager and other WireGuard clients.
+
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::{
+    collections::HashMap,
+    fs::OpenOptions,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use tracing::{debug, warn};
+
+use crate::{
+    keepalive_interval, mfa, proto, CliConfig, CliError, CliInstance, DEFAULT_ROUTE_IPV4,
+    DEFAULT_ROUTE_IPV6,
+};
+
+/// Maximum interface name length on Linux; `wg-quick` names interfaces after their files.
+const MAX_INTERFACE_NAME_LENGTH: usize = 15;
+
+/// Render WireGuard configuration of a location, with the interface MTU if given.
+fn wireguard_config(
+    instance: &CliInstance,
+    device_config: &proto::DeviceConfig,
+    mtu: Option<u32>,
+) -> String {
+    let allowed_ips = if instance.routes_all_traffic(device_config.network_id) {
+        format!("{DEFAULT_ROUTE_IPV4}, {DEFAULT_ROUTE_IPV6}")
+    } else {
+        device_config.allowed_ips.clone()
+    };
+
+    let mut config = format!(
+        "# {} ({})\n[Interface]\nPrivateKey = {}\nAddress = {}\n",
+        device_config.network_name,
+        instance.instance_info.name,
+        instance.private_key,
+        device_config.assigned_ip
+    );
+    if let Some(dns) = device_config.dns.as_ref().filter(|dns| !dns.is_empty()) {
+        config.push_str(&format!("DNS = {dns}\n"));
+    }
+    if let Some(mtu) = mtu {
+        config.push_str(&format!("MTU = {mtu}\n"));
+    }
+    config.push_str(&format!(
+        "\n[Peer]\nPublicKey = {}\nAllowedIPs = {allowed_ips}\nEndpoint = {}\n\
+        PersistentKeepalive = {}\n",
+        device_config.pubkey,
+        device_config.endpoint,
+        keepalive_interval(device_config)
+    ));
+
+    config
+}
+
+/// Name configuration file after the location, so that it's a valid interface name for
+/// `wg-quick`.
+fn file_name(device_config: &proto::DeviceConfig) -> String {
+    let mut name: String = device_config
+        .network_name
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '=' | '+' | '.' | '-'))
+        .take(MAX_INTERFACE_NAME_LENGTH)
+        .collect();
+    if name.is_empty() {
+        name = format!("wg{}", device_config.network_id);
+    }
+    format!("{name}.conf")
+}
+
+/// Write WireGuard configuration files of selected locations to `dir`: a single location if
+/// `location_name` is given, otherwise all locations, optionally narrowed to one instance.
+/// Locations with MFA enabled are skipped, as their preshared keys are only valid for a single
+/// session. Nothing is written if two locations would share a file name, e.g. locations of the
+/// same name in different instances. Returns paths of written files.
+pub(crate) fn export(
+    config: &CliConfig,
+    location_name: Option<&str>,
+    instance_name: Option<&str>,
+    mtu: Option<u32>,
+    dir: &Path,
+) -> Result<Vec<PathBuf>, CliError> {
+    let mut locations = Vec::new();
+    if let Some(location_name) = location_name {
+        let (index, network_id) = config.find_location(location_name, instance_name)?;
+        let instance = &config.instances[index];
+        if let Some(device_config) = instance.device_config(network_id) {
+            if mfa::mfa_required(device_config) {
+                return Err(CliError::Export(
+                    location_name.to_string(),
+                    "locations with MFA enabled can't be exported".into(),
+                ));
+            }
+            locations.push((instance, device_config));
+        }
+    } else {
+        for instance in &config.instances {
+            if instance_name.is_some_and(|name| name != instance.instance_info.name) {
+                continue;
+            }
+            for device_config in &instance.device_configs {
+                if mfa::mfa_required(device_config) {
+                    warn!(
+                        "Skipping location {} which has MFA enabled.",
+                        device_config.network_name
+                    );
+                    continue;
+                }
+                locations.push((instance, device_config));
+            }
+        }
+        if locations.is_empty() {
+            if let Some(instance_name) = instance_name {
+                if !config
+                    .instances
+                    .iter()
+                    .any(|instance| instance.instance_info.name == instance_name)
+                {
+                    return Err(CliError::InstanceNotFound(instance_name.to_string()));
+                }
+            }
+        }
+    }
+
+    let mut file_names = HashMap::new();
+    for (instance, device_config) in &locations {
+        let name = file_name(device_config);
+        if let Some((other, other_config)) =
+            file_names.insert(name.clone(), (instance, device_config))
+        {
+            return Err(CliError::Export(
+                dir.join(name).to_string_lossy().to_string(),
+                format!(
+                    "locations {} ({other}) and {} ({instance}) would share the file; use \
+                    \"--instance\" or \"--location\" to export them separately",
+                    other_config.network_name, device_config.network_name
+                ),
+            ));
+        }
+    }
+
+    let mut paths = Vec::new();
+    for (instance, device_config) in locations {
+        let path = dir.join(file_name(device_config));
+        debug!(
+            "Exporting location {} to {path:?}",
+            device_config.network_name
+        );
+        write_config(&path, &wireguard_config(instance, device_config, mtu))
+            .map_err(|err| CliError::Export(path.to_string_lossy().to_string(), err.to_string()))?;
+        paths.push(path);
+    }
+
+    Ok(paths)
+}
+
+/// Write configuration to a file readable by its owner only, as it contains the private key.
+fn write_config(path: &Path, config: &str) -> std::io::Result<()> {
+    let mut options = OpenOptions::new();
+    options.create(true).truncate(true).write(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    options.open(path)?.write_all(config.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, env, fs, process};
+
+    use defguard_client::wg_config::parse_wireguard_config;
+    use defguard_wireguard_rs::key::Key;
+
+    use super::*;
+    use crate::fixtures::{device_config, instance};
+
+    #[test]
+    fn render_wireguard_config() {
+        let mut instance = instance("Acme", &[(1, "Office")]);
+        instance.device_configs[0].dns = Some("10.0.0.1, acme.internal".into());
+        let private_key = Key::new([1; 32]);
+        let public_key = Key::new([2; 32]);
+        assert_eq!(
+            wireguard_config(&instance, &instance.device_configs[0], None),
+            format!(
+                "# Office (Acme)\n\
+                [Interface]\n\
+                PrivateKey = {private_key}\n\
+                Address = 10.0.0.2/24\n\
+                DNS = 10.0.0.1, acme.internal\n\
+                \n\
+                [Peer]\n\
+                PublicKey = {public_key}\n\
+                AllowedIPs = 10.0.0.0/24,10.1.0.0/24\n\
+                Endpoint = vpn.example.com:51820\n\
+                PersistentKeepalive = 25\n"
+            )
+        );
+
+        // Empty DNS is left out, and routing all traffic replaces allowed IPs.
+        instance.device_configs[0].dns = Some(String::new());
+        instance.route_all_traffic = HashSet::from([1]);
+        let config = wireguard_config(&instance, &instance.device_configs[0], None);
+        assert!(!config.contains("DNS"));
+        assert!(config.contains("AllowedIPs = 0.0.0.0/0, ::/0\n"));
+        // Unless the instance doesn't allow it.
+        instance.instance_info.client_traffic_policy = Some(1);
+        let config = wireguard_config(&instance, &instance.device_configs[0], None);
+        assert!(config.contains("AllowedIPs = 10.0.0.0/24,10.1.0.0/24\n"));
+    }
+
+    #[test]
+    fn import_exported_config() {
+        let instance = instance("Acme", &[(1, "Office")]);
+        let device_config = &instance.device_configs[0];
+        let config = wireguard_config(&instance, device_config, Some(1380));
+        assert!(config.contains("MTU = 1380\n"));
+
+        // Exported files can be imported as tunnels by the desktop client.
+        let tunnel = parse_wireguard_config(&file_name(device_config), &config).unwrap();
+        assert_eq!(tunnel.name, "Office");
+        assert_eq!(tunnel.prvkey, instance.private_key.to_string());
+        assert_eq!(tunnel.address, device_config.assigned_ip);
+        assert_eq!(tunnel.server_pubkey, device_config.pubkey);
+        assert_eq!(
+            tunnel.allowed_ips.as_ref(),
+            Some(&device_config.allowed_ips)
+        );
+        assert_eq!(tunnel.endpoint, device_config.endpoint);
+        assert_eq!(tunnel.dns, device_config.dns);
+        assert_eq!(tunnel.persistent_keep_alive, 25);
+    }
+
+    #[test]
+    fn file_names() {
+        assert_eq!(file_name(&device_config(1, "office")), "office.conf");
+        // Interface names are limited to 15 characters.
+        assert_eq!(
+            file_name(&device_config(1, "Main office (Berlin)")),
+            "MainofficeBerli.conf"
+        );
+        assert_eq!(file_name(&device_config(7, "Büro ü")), "Bro.conf");
+        assert_eq!(file_name(&device_config(7, "ÜÜ")), "wg7.conf");
+    }
+
+    #[test]
+    fn export_colliding_locations() {
+        let config = CliConfig {
+            instances: vec![
+                instance("Acme", &[(1, "Office"), (2, "Lab")]),
+                instance("Globex", &[(1, "Office")]),
+            ],
+            ..Default::default()
+        };
+        let dir = env::temp_dir().join(format!("dg-export-{}", process::id()));
+
+        let err = export(&config, None, None, None, &dir).unwrap_err();
+        assert!(matches!(err, CliError::Export(path, _) if path.ends_with("Office.conf")));
+        assert!(!dir.exists());
+
+        // Narrowing export down to a single instance avoids the collision.
+        fs::create_dir(&dir).unwrap();
+        let paths = export(&config, None, Some("Globex"), None, &dir);
+        let files = fs::read_dir(&dir).unwrap().count();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(paths.unwrap(), [dir.join("Office.conf")]);
+        assert_eq!(files, 1);
+    }
+}
@@ -0,0 +1,60 @@
+//! Instances, locations and connections shared by tests.
+
+use std::sync::Arc;
+
+use defguard_wireguard_rs::key::Key;
+use tokio::sync::{mpsc, watch, Notify};
+
+use crate::{proto, CliInstance, ConnectionOptions, LocationConnection};
+
+/// Location with a single gateway, without MFA.
+pub(crate) fn device_config(network_id: i64, network_name: &str) -> proto::DeviceConfig {
+    proto::DeviceConfig {
+        network_id,
+        network_name: network_name.to_string(),
+        assigned_ip: "10.0.0.2/24".into(),
+        pubkey: Key::new([2; 32]).to_string(),
+        endpoint: "vpn.example.com:51820".into(),
+        allowed_ips: "10.0.0.0/24,10.1.0.0/24".into(),
+        dns: Some("10.0.0.1".into()),
+        keepalive_interval: 25,
+        ..Default::default()
+    }
+}
+
+/// Instance `name` with the given locations; none of them are active.
+pub(crate) fn instance(name: &str, locations: &[(i64, &str)]) -> CliInstance {
+    CliInstance {
+        private_key: Key::new([1; 32]),
+        device_configs: locations
+            .iter()
+            .map(|(network_id, network_name)| device_config(*network_id, network_name))
+            .collect(),
+        instance_info: proto::InstanceInfo {
+            id: name.to_lowercase(),
+            name: name.to_string(),
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+/// Connection with the given settings, without any interface behind it.
+pub(crate) fn connection(
+    private_key: &Key,
+    device_config: &proto::DeviceConfig,
+    options: &ConnectionOptions,
+) -> LocationConnection {
+    let (updates, _) = mpsc::unbounded_channel();
+    let (_, state) = watch::channel(None);
+    LocationConnection {
+        ifname: "wg0".into(),
+        private_key: private_key.clone(),
+        device_config: device_config.clone(),
+        options: options.clone(),
+        trigger: Arc::new(Notify::new()),
+        updates,
+        state,
+        task: tokio::spawn(async { Ok(()) }),
+    }
+}
@@ -0,0 +1,237 @@
+//! Client MFA flow, required to connect to locations protected with multi-factor authentication.
+//!
+//! The flow mirrors the one implemented in the desktop client's frontend: an MFA session is
+//! started through Defguard proxy, the user provides a TOTP/email code or logs in through an
+//! external OpenID provider, and the proxy responds with a preshared key for the session.
+
+use std::{
+    io::{stderr, stdin, Write},
+    time::Duration,
+};
+
+use clap::ValueEnum;
+use reqwest::{Client, StatusCode, Url};
+use serde::{Deserialize, Serialize};
+use tokio::time::{sleep, Instant};
+use tracing::{debug, info};
+
+use crate::{proto, ApiError, CliError, CliInstance, HTTP_REQ_TIMEOUT};
+
+const CLIENT_MFA_ENDPOINT: &str = "/api/v1/client-mfa";
+const OPENID_POLL_INTERVAL: Duration = Duration::from_secs(5);
+const OPENID_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// MFA methods supported by the CLI. Values match the ones used by Defguard proxy.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub(crate) enum MfaMethod {
+    /// Code from an authenticator app.
+    Totp = 0,
+    /// Code sent by email.
+    Email = 1,
+    /// Login through an external OpenID provider.
+    Openid = 2,
+}
+
+#[derive(Serialize)]
+struct MfaStartRequest<'a> {
+    method: i32,
+    pubkey: &'a str,
+    location_id: i64,
+}
+
+#[derive(Deserialize)]
+struct MfaStartResponse {
+    token: String,
+}
+
+#[derive(Serialize)]
+struct MfaFinishRequest<'a> {
+    token: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<&'a str>,
+}
+
+#[derive(Deserialize)]
+struct MfaFinishResponse {
+    preshared_key: String,
+}
+
+/// Location MFA mode, taking legacy Defguard versions into account.
+pub(crate) fn mfa_mode(device_config: &proto::DeviceConfig) -> proto::LocationMfaMode {
+    match device_config.location_mfa_mode {
+        Some(_) => device_config.location_mfa_mode(),
+        None => {
+            // handle legacy core response
+            // DEPRECATED(1.5): superseeded by location_mfa_mode
+            #[allow(deprecated)]
+            if device_config.mfa_enabled {
+                proto::LocationMfaMode::Internal
+            } else {
+                proto::LocationMfaMode::Disabled
+            }
+        }
+    }
+}
+
+/// Check if connecting to a location requires going through the MFA flow.
+pub(crate) fn mfa_required(device_config: &proto::DeviceConfig) -> bool {
+    matches!(
+        mfa_mode(device_config),
+        proto::LocationMfaMode::Internal | proto::LocationMfaMode::External
+    )
+}
+
+/// Perform MFA for a location interactively and return the session's preshared key.
+/// Locations with external MFA always use OpenID; `method` applies to internal MFA only.
+pub(crate) async fn authenticate(
+    instance: &CliInstance,
+    device_config: &proto::DeviceConfig,
+    method: MfaMethod,
+) -> Result<String, CliError> {
+    let method = if mfa_mode(device_config) == proto::LocationMfaMode::External {
+        MfaMethod::Openid
+    } else if method == MfaMethod::Openid {
+        return Err(CliError::Mfa(format!(
+            "location {} doesn't support OpenID MFA",
+            device_config.network_name
+        )));
+    } else {
+        method
+    };
+    info!(
+        "Location {} requires MFA, authenticating using {method:?}.",
+        device_config.network_name
+    );
+
+    let client = Client::builder().cookie_store(true).build()?;
+    let base_url = Url::parse(&instance.instance_info.proxy_url).map_err(|err| {
+        CliError::Mfa(format!(
+            "invalid proxy URL {}: {err}",
+            instance.instance_info.proxy_url
+        ))
+    })?;
+    let token = start(&client, &base_url, instance, device_config, method).await?;
+
+    let preshared_key = if method == MfaMethod::Openid {
+        let mut url = base_url.clone();
+        url.set_path("/openid/mfa");
+        url.query_pairs_mut().append_pair("token", &token);
+        eprintln!("Open the following URL in a web browser to authenticate:\n\n  {url}\n");
+        eprintln!("Waiting for the authentication to complete...");
+        wait_for_openid(&client, &base_url, &token).await?
+    } else {
+        let code = prompt_code(method)?;
+        match finish(&client, &base_url, &token, Some(&code)).await? {
+            Some(preshared_key) => preshared_key,
+            None => return Err(CliError::Mfa("authentication is still pending".into())),
+        }
+    };
+    info!(
+        "MFA for location {} has been completed.",
+        device_config.network_name
+    );
+
+    Ok(preshared_key)
+}
+
+/// Start client MFA session. Returns session token.
+async fn start(
+    client: &Client,
+    base_url: &Url,
+    instance: &CliInstance,
+    device_config: &proto::DeviceConfig,
+    method: MfaMethod,
+) -> Result<String, CliError> {
+    let mut url = base_url.clone();
+    url.set_path(&format!("{CLIENT_MFA_ENDPOINT}/start"));
+    debug!("Starting MFA session through Defguard Proxy at {url}.");
+    let result = client
+        .post(url)
+        .json(&MfaStartRequest {
+            method: method as i32,
+            pubkey: &instance.device.pubkey,
+            location_id: device_config.network_id,
+        })
+        .timeout(HTTP_REQ_TIMEOUT)
+        .send()
+        .await?;
+
+    if result.status() == StatusCode::OK {
+        let response: MfaStartResponse = result.json().await?;
+        debug!("MFA session has been started.");
+        Ok(response.token)
+    } else {
+        let error: ApiError = result.json().await?;
+        Err(CliError::Mfa(format!(
+            "failed to start MFA session: {}",
+            error.error
+        )))
+    }
+}
+
+/// Finish client MFA session. Returns `None` if the authentication is still pending.
+async fn finish(
+    client: &Client,
+    base_url: &Url,
+    token: &str,
+    code: Option<&str>,
+) -> Result<Option<String>, CliError> {
+    let mut url = base_url.clone();
+    url.set_path(&format!("{CLIENT_MFA_ENDPOINT}/finish"));
+    let result = client
+        .post(url)
+        .json(&MfaFinishRequest { token, code })
+        .timeout(HTTP_REQ_TIMEOUT)
+        .send()
+        .await?;
+
+    match result.status() {
+        StatusCode::OK => {
+            let response: MfaFinishResponse = result.json().await?;
+            Ok(Some(response.preshared_key))
+        }
+        // The user hasn't completed the OpenID login yet.
+        StatusCode::PRECONDITION_REQUIRED => Ok(None),
+        _ => {
+            let error: ApiError = result.json().await?;
+            let message = match error.error.as_str() {
+                "Unauthorized" => "invalid code".to_string(),
+                "invalid token" | "login session not found" => {
+                    "MFA session has expired, try again".to_string()
+                }
+                _ => error.error,
+            };
+            Err(CliError::Mfa(message))
+        }
+    }
+}
+
+/// Poll MFA session state until the user logs in through the OpenID provider.
+async fn wait_for_openid(client: &Client, base_url: &Url, token: &str) -> Result<String, CliError> {
+    let deadline = Instant::now() + OPENID_TIMEOUT;
+    while Instant::now() < deadline {
+        sleep(OPENID_POLL_INTERVAL).await;
+        debug!("Checking OpenID MFA session state.");
+        if let Some(preshared_key) = finish(client, base_url, token, None).await? {
+            return Ok(preshared_key);
+        }
+    }
+
+    Err(CliError::Mfa("authentication has timed out".into()))
+}
+
+/// Ask the user for a TOTP or email code.
+fn prompt_code(method: MfaMethod) -> Result<String, CliError> {
+    let prompt = match method {
+        MfaMethod::Email => "Enter the code sent to your email address: ",
+        _ => "Enter the code from your authenticator app: ",
+    };
+    eprint!("{prompt}");
+    let mut code = String::new();
+    stderr()
+        .flush()
+        .and_then(|()| stdin().read_line(&mut code))
+        .map_err(|err| CliError::Mfa(format!("failed to read code: {err}")))?;
+
+    Ok(code.trim().to_string())
+}
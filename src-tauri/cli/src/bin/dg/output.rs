@@ -0,0 +1,83 @@
+//! Output of `dg` commands: human-readable text, or JSON for use in scripts.
+//!
+//! In JSON mode every document is printed as a single line on standard output, while logs are
+//! written to standard error.
+
+use std::sync::OnceLock;
+
+use clap::ValueEnum;
+use serde::Serialize;
+
+static FORMAT: OnceLock<OutputFormat> = OnceLock::new();
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub(crate) enum OutputFormat {
+    /// Human-readable text.
+    #[default]
+    Text,
+    /// JSON documents, one per line.
+    Json,
+}
+
+/// Set output format for the rest of the program; only the first call has any effect.
+pub(crate) fn set_format(format: OutputFormat) {
+    let _ = FORMAT.set(format);
+}
+
+pub(crate) fn is_json() -> bool {
+    FORMAT.get() == Some(&OutputFormat::Json)
+}
+
+/// Print `value` as a single line of JSON on standard output.
+pub(crate) fn print_json<T: Serialize>(value: &T) {
+    match serde_json::to_string(value) {
+        Ok(json) => println!("{json}"),
+        Err(err) => eprintln!("Failed to serialize output: {err}"),
+    }
+}
+
+/// Report successful completion of a command which doesn't produce any other output.
+pub(crate) fn ok() {
+    if is_json() {
+        print_json(&serde_json::json!({ "result": "ok" }));
+    }
+}
+
+/// Report failure of a command. In text mode errors are only logged.
+pub(crate) fn error(message: &str, exit_code: u8) {
+    if is_json() {
+        print_json(&serde_json::json!({ "error": message, "exit_code": exit_code }));
+    }
+}
+
+/// State change of a location connection, reported by the running agent.
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub(crate) enum ConnectionEvent<'a> {
+    Connected {
+        location: &'a str,
+        interface: &'a str,
+    },
+    Updated {
+        location: &'a str,
+        interface: &'a str,
+        changes: &'a [&'static str],
+    },
+    Disconnected {
+        location: &'a str,
+        interface: &'a str,
+    },
+    Failed {
+        location: &'a str,
+        interface: &'a str,
+        error: String,
+    },
+}
+
+/// Report connection state change. Events are only printed in JSON mode, as they're already
+/// logged otherwise.
+pub(crate) fn event(event: &ConnectionEvent) {
+    if is_json() {
+        print_json(event);
+    }
+}
@@ -0,0 +1,406 @@
+//! Storage of instance secrets: WireGuard private keys and polling tokens.
+//!
+//! Secrets are kept in the configuration file by default. They can be moved to the system
+//! keyring (Secret Service and kernel keyring on Linux), or encrypted with a key derived from
+//! a passphrase (`DG_PASSPHRASE`) or a key file (`--key-file`), in which case the configuration
+//! file holds everything else.
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+use argon2::Argon2;
+use base64::{prelude::BASE64_STANDARD, Engine};
+use chacha20poly1305::{
+    aead::{rand_core::RngCore, Aead, AeadCore, KeyInit, OsRng},
+    ChaCha20Poly1305, Nonce,
+};
+use clap::ValueEnum;
+use keyring::Entry;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use tracing::{debug, warn};
+
+use crate::CliError;
+
+const KEYRING_SERVICE: &str = "net.defguard.cli";
+const PASSPHRASE_ENV: &str = "DG_PASSPHRASE";
+/// Fields of `CliInstance` which hold secrets.
+const SECRET_FIELDS: [&str; 2] = ["private_key", "token"];
+const SALT_LENGTH: usize = 16;
+
+static KEY_FILE: OnceLock<PathBuf> = OnceLock::new();
+
+/// Where instance secrets are stored.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum SecretStorage {
+    /// In the configuration file, protected by file permissions only.
+    #[default]
+    Plaintext,
+    /// In the system keyring.
+    Keyring,
+    /// In the configuration file, encrypted with a passphrase or a key file.
+    Encrypted,
+}
+
+/// Secrets encrypted with ChaCha20-Poly1305, using a key derived with Argon2.
+#[derive(Deserialize, Serialize)]
+struct EncryptedSecrets {
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+/// Set path of the key file used to encrypt secrets, instead of a passphrase.
+pub(crate) fn set_key_file(path: PathBuf) {
+    let _ = KEY_FILE.set(path);
+}
+
+/// Move secrets out of serialized configuration `config` into `storage`.
+pub(crate) fn store(config: &mut Value, storage: SecretStorage) -> Result<(), CliError> {
+    if storage == SecretStorage::Plaintext {
+        return Ok(());
+    }
+    let mut secrets = Map::new();
+    for instance in instances_mut(config) {
+        let Some(instance_id) = instance_id(instance) else {
+            continue;
+        };
+        let mut instance_secrets = Map::new();
+        for field in SECRET_FIELDS {
+            if let Some(value) = instance.remove(field) {
+                instance_secrets.insert(field.to_string(), value);
+            }
+        }
+        secrets.insert(instance_id, Value::Object(instance_secrets));
+    }
+
+    if storage == SecretStorage::Keyring {
+        for (instance_id, instance_secrets) in secrets {
+            debug!("Storing secrets of instance {instance_id} in the system keyring.");
+            keyring_entry(&instance_id)?
+                .set_password(&instance_secrets.to_string())
+                .map_err(keyring_error)?;
+        }
+    } else {
+        let encrypted = encrypt(Value::Object(secrets).to_string().as_bytes())?;
+        config["encrypted_secrets"] = serde_json::to_value(encrypted).map_err(secrets_error)?;
+    }
+
+    Ok(())
+}
+
+/// Put secrets back into serialized configuration `config`, according to its secret storage.
+pub(crate) fn restore(config: &mut Value) -> Result<(), CliError> {
+    let storage = match config.get("secret_storage") {
+        Some(storage) => {
+            serde_json::from_value::<SecretStorage>(storage.clone()).map_err(secrets_error)?
+        }
+        None => SecretStorage::Plaintext,
+    };
+    let secrets = match storage {
+        SecretStorage::Plaintext => return Ok(()),
+        SecretStorage::Keyring => None,
+        SecretStorage::Encrypted => {
+            let Some(encrypted) = config
+                .as_object_mut()
+                .and_then(|config| config.remove("encrypted_secrets"))
+            else {
+                return Err(CliError::Secrets("encrypted secrets are missing".into()));
+            };
+            let encrypted: EncryptedSecrets =
+                serde_json::from_value(encrypted).map_err(secrets_error)?;
+            let decrypted = decrypt(&encrypted)?;
+            Some(serde_json::from_slice::<Map<String, Value>>(&decrypted).map_err(secrets_error)?)
+        }
+    };
+
+    for instance in instances_mut(config) {
+        let Some(instance_id) = instance_id(instance) else {
+            continue;
+        };
+        let instance_secrets = match &secrets {
+            Some(secrets) => secrets.get(&instance_id).cloned(),
+            None => {
+                debug!("Reading secrets of instance {instance_id} from the system keyring.");
+                let password = keyring_entry(&instance_id)?
+                    .get_password()
+                    .map_err(keyring_error)?;
+                Some(serde_json::from_str(&password).map_err(secrets_error)?)
+            }
+        };
+        if let Some(Value::Object(instance_secrets)) = instance_secrets {
+            instance.extend(instance_secrets);
+        }
+    }
+
+    Ok(())
+}
+
+/// Remove secrets of an instance from the system keyring, if they're there.
+pub(crate) fn forget(instance_id: &str) {
+    match keyring_entry(instance_id).and_then(|entry| {
+        entry.delete_credential().or_else(|err| match err {
+            keyring::Error::NoEntry => Ok(()),
+            err => Err(keyring_error(err)),
+        })
+    }) {
+        Ok(()) => debug!("Secrets of instance {instance_id} have been removed from the keyring."),
+        Err(err) => warn!("Failed to remove secrets of instance {instance_id}: {err}"),
+    }
+}
+
+fn instances_mut(config: &mut Value) -> impl Iterator<Item = &mut Map<String, Value>> {
+    config
+        .get_mut("instances")
+        .and_then(Value::as_array_mut)
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_object_mut)
+}
+
+fn instance_id(instance: &Map<String, Value>) -> Option<String> {
+    instance
+        .get("instance_info")?
+        .get("id")?
+        .as_str()
+        .map(ToString::to_string)
+}
+
+fn keyring_entry(instance_id: &str) -> Result<Entry, CliError> {
+    Entry::new(KEYRING_SERVICE, instance_id).map_err(keyring_error)
+}
+
+fn keyring_error(err: keyring::Error) -> CliError {
+    CliError::Secrets(format!("system keyring error: {err}"))
+}
+
+fn secrets_error(err: impl std::fmt::Display) -> CliError {
+    CliError::Secrets(err.to_string())
+}
+
+/// Read passphrase from the key file, or the environment.
+fn passphrase() -> Result<Vec<u8>, CliError> {
+    if let Some(path) = KEY_FILE.get() {
+        return read_key_file(path);
+    }
+    match env::var(PASSPHRASE_ENV) {
+        Ok(passphrase) if !passphrase.is_empty() => Ok(passphrase.into_bytes()),
+        _ => Err(CliError::Secrets(format!(
+            "secrets are encrypted; provide a key file with \"--key-file\" or a passphrase with \
+            {PASSPHRASE_ENV}"
+        ))),
+    }
+}
+
+fn read_key_file(path: &Path) -> Result<Vec<u8>, CliError> {
+    match fs::read(path) {
+        Ok(key) if !key.is_empty() => Ok(key),
+        Ok(_) => Err(CliError::Secrets(format!("key file {path:?} is empty"))),
+        Err(err) => Err(CliError::Secrets(format!(
+            "failed to read key file {path:?}: {err}"
+        ))),
+    }
+}
+
+fn cipher(salt: &[u8]) -> Result<ChaCha20Poly1305, CliError> {
+    let mut key = [0; 32];
+    Argon2::default()
+        .hash_password_into(&passphrase()?, salt, &mut key)
+        .map_err(secrets_error)?;
+    Ok(ChaCha20Poly1305::new(&key.into()))
+}
+
+fn encrypt(plaintext: &[u8]) -> Result<EncryptedSecrets, CliError> {
+    let mut salt = [0; SALT_LENGTH];
+    OsRng.fill_bytes(&mut salt);
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = cipher(&salt)?
+        .encrypt(&nonce, plaintext)
+        .map_err(|_| CliError::Secrets("failed to encrypt secrets".into()))?;
+
+    Ok(EncryptedSecrets {
+        salt: BASE64_STANDARD.encode(salt),
+        nonce: BASE64_STANDARD.encode(nonce),
+        ciphertext: BASE64_STANDARD.encode(ciphertext),
+    })
+}
+
+fn decrypt(encrypted: &EncryptedSecrets) -> Result<Vec<u8>, CliError> {
+    let salt = BASE64_STANDARD
+        .decode(&encrypted.salt)
+        .map_err(secrets_error)?;
+    let nonce = BASE64_STANDARD
+        .decode(&encrypted.nonce)
+        .map_err(secrets_error)?;
+    if nonce.len() != 12 {
+        return Err(CliError::Secrets("invalid nonce length".into()));
+    }
+    let ciphertext = BASE64_STANDARD
+        .decode(&encrypted.ciphertext)
+        .map_err(secrets_error)?;
+    cipher(&salt)?
+        .decrypt(Nonce::from_slice(&nonce), ciphertext.as_slice())
+        .map_err(|_| {
+            CliError::Secrets("failed to decrypt secrets; check the passphrase or key file".into())
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        any::Any,
+        collections::HashMap,
+        process,
+        sync::{LazyLock, Mutex, Once},
+    };
+
+    use keyring::credential::{Credential, CredentialApi, CredentialBuilderApi};
+    use serde_json::json;
+
+    use super::*;
+
+    /// Keyring kept in memory. Unlike `keyring::mock`, entries share their credentials.
+    static KEYRING: LazyLock<Mutex<HashMap<String, Vec<u8>>>> = LazyLock::new(Mutex::default);
+
+    struct MemoryCredential(String);
+
+    impl CredentialApi for MemoryCredential {
+        fn set_secret(&self, secret: &[u8]) -> keyring::Result<()> {
+            KEYRING
+                .lock()
+                .unwrap()
+                .insert(self.0.clone(), secret.to_vec());
+            Ok(())
+        }
+
+        fn get_secret(&self) -> keyring::Result<Vec<u8>> {
+            KEYRING
+                .lock()
+                .unwrap()
+                .get(&self.0)
+                .cloned()
+                .ok_or(keyring::Error::NoEntry)
+        }
+
+        fn delete_credential(&self) -> keyring::Result<()> {
+            KEYRING
+                .lock()
+                .unwrap()
+                .remove(&self.0)
+                .map(|_| ())
+                .ok_or(keyring::Error::NoEntry)
+        }
+
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+    }
+
+    struct MemoryKeyring;
+
+    impl CredentialBuilderApi for MemoryKeyring {
+        fn build(
+            &self,
+            _target: Option<&str>,
+            _service: &str,
+            user: &str,
+        ) -> keyring::Result<Box<Credential>> {
+            Ok(Box::new(MemoryCredential(user.to_string())))
+        }
+
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+    }
+
+    fn use_memory_keyring() {
+        static INIT: Once = Once::new();
+        INIT.call_once(|| keyring::set_default_credential_builder(Box::new(MemoryKeyring)));
+    }
+
+    /// Serialized configuration with two instances, the way `CliConfig` stores it.
+    fn config(storage: &str, instance_ids: [&str; 2]) -> Value {
+        json!({
+            "secret_storage": storage,
+            "instances": instance_ids.map(|id| json!({
+                "private_key": format!("{id}-private-key"),
+                "token": format!("{id}-token"),
+                "instance_info": { "id": id, "name": id },
+            })),
+        })
+    }
+
+    fn has_secrets(config: &Value) -> bool {
+        let config = config.to_string();
+        config.contains("-private-key") || config.contains("-token")
+    }
+
+    #[test]
+    fn encrypted_round_trip() {
+        let key_file = env::temp_dir().join(format!("dg-key-{}", process::id()));
+        fs::write(&key_file, "correct horse battery staple").unwrap();
+        set_key_file(key_file.clone());
+
+        let plaintext = config("encrypted", ["first", "second"]);
+        let mut encrypted = plaintext.clone();
+        store(&mut encrypted, SecretStorage::Encrypted).unwrap();
+        assert!(!has_secrets(&encrypted));
+        assert!(encrypted.get("encrypted_secrets").is_some());
+
+        let mut restored = encrypted.clone();
+        restore(&mut restored).unwrap();
+        assert_eq!(restored, plaintext);
+
+        // Tampered secrets are rejected instead of being silently dropped.
+        let mut tampered = encrypted.clone();
+        let ciphertext = tampered["encrypted_secrets"]["ciphertext"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        let mut ciphertext = BASE64_STANDARD.decode(ciphertext).unwrap();
+        ciphertext[0] ^= 1;
+        tampered["encrypted_secrets"]["ciphertext"] = json!(BASE64_STANDARD.encode(ciphertext));
+        assert!(matches!(restore(&mut tampered), Err(CliError::Secrets(_))));
+
+        let mut missing = encrypted;
+        missing.as_object_mut().unwrap().remove("encrypted_secrets");
+        assert!(matches!(restore(&mut missing), Err(CliError::Secrets(_))));
+
+        fs::remove_file(key_file).unwrap();
+    }
+
+    #[test]
+    fn keyring_round_trip() {
+        use_memory_keyring();
+
+        let plaintext = config("keyring", ["keyring-first", "keyring-second"]);
+        let mut stored = plaintext.clone();
+        store(&mut stored, SecretStorage::Keyring).unwrap();
+        assert!(!has_secrets(&stored));
+
+        let mut restored = stored.clone();
+        restore(&mut restored).unwrap();
+        assert_eq!(restored, plaintext);
+
+        // Missing keyring entries are reported instead of leaving instances without keys.
+        forget("keyring-second");
+        let mut restored = stored;
+        assert!(matches!(restore(&mut restored), Err(CliError::Secrets(_))));
+        forget("keyring-first");
+
+        // Configurations without a secret storage keep their secrets in the file and don't
+        // touch the keyring.
+        let mut plaintext = config("plaintext", ["plaintext-first", "plaintext-second"]);
+        plaintext.as_object_mut().unwrap().remove("secret_storage");
+        let mut restored = plaintext.clone();
+        restore(&mut restored).unwrap();
+        assert_eq!(restored, plaintext);
+        store(&mut restored, SecretStorage::Plaintext).unwrap();
+        assert_eq!(restored, plaintext);
+        assert!(KEYRING.lock().unwrap().is_empty());
+    }
+}
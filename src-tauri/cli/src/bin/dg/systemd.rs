@@ -0,0 +1,64 @@
+//! Service state notifications for systemd (see `sd_notify(3)`).
+//!
+//! Notifications are only sent when `dg` runs as a systemd service with `NOTIFY_SOCKET` set;
+//! otherwise, and on platforms other than Linux, they're silently ignored.
+
+use std::time::Duration;
+
+#[cfg(target_os = "linux")]
+use sd_notify::NotifyState;
+#[cfg(target_os = "linux")]
+use tracing::debug;
+
+/// Service state reported to systemd.
+pub(crate) enum State<'a> {
+    /// Start-up or reload has finished.
+    Ready,
+    /// Configuration is being reloaded.
+    Reloading,
+    /// The service is shutting down.
+    Stopping,
+    /// Free-form status line shown by `systemctl status`.
+    Status(&'a str),
+    /// Watchdog keep-alive ping.
+    Watchdog,
+}
+
+/// Notify systemd about service state changes.
+#[cfg(target_os = "linux")]
+pub(crate) fn notify(states: &[State]) {
+    let states: Vec<NotifyState> = states
+        .iter()
+        .map(|state| match state {
+            State::Ready => NotifyState::Ready,
+            State::Reloading => NotifyState::Reloading,
+            State::Stopping => NotifyState::Stopping,
+            State::Status(status) => NotifyState::Status(status),
+            State::Watchdog => NotifyState::Watchdog,
+        })
+        .collect();
+    if let Err(err) = sd_notify::notify(false, &states) {
+        debug!("Failed to notify systemd about service state: {err}");
+    }
+}
+
+/// Dummy version of the above function for non-Linux systems.
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn notify(_states: &[State]) {}
+
+/// Watchdog timeout configured with `WatchdogSec=`, if the watchdog is enabled.
+#[cfg(target_os = "linux")]
+pub(crate) fn watchdog_timeout() -> Option<Duration> {
+    let mut usec = 0;
+    if sd_notify::watchdog_enabled(false, &mut usec) && usec > 0 {
+        Some(Duration::from_micros(usec))
+    } else {
+        None
+    }
+}
+
+/// Dummy version of the above function for non-Linux systems.
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn watchdog_timeout() -> Option<Duration> {
+    None
+}
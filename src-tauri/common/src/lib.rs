@@ -15,7 +15,15 @@ pub fn find_free_tcp_port() -> Option<u16> {
 /// Find next available interface.
 /// Search for available `wg` interface.
 #[must_use]
-pub fn get_interface_name(_name: &str) -> String {
+pub fn get_interface_name(name: &str) -> String {
+    get_interface_name_excluding::<&str>(name, &[])
+}
+
+#[cfg(not(any(windows, target_os = "macos")))]
+/// Find next available `wg` interface, skipping names listed in `excluded`.
+/// Useful when several interfaces are about to be created at once.
+#[must_use]
+pub fn get_interface_name_excluding<S: AsRef<str>>(_name: &str, excluded: &[S]) -> String {
     let base_ifname = "wg";
     if let Ok(interfaces) = nix::net::if_::if_nameindex() {
         for index in 0..=u16::MAX {
@@ -23,6 +31,7 @@ pub fn get_interface_name(_name: &str) -> String {
             if !interfaces
                 .iter()
                 .any(|interface| interface.name().to_string_lossy() == ifname)
+                && !excluded.iter().any(|name| name.as_ref() == ifname)
             {
                 return ifname;
             }
@@ -81,6 +90,26 @@ pub fn get_interface_name(name: &str) -> String {
     name.chars().filter(|c| c.is_alphanumeric()).collect()
 }
 
+/// Same as [`get_interface_name`], but appends a numeric suffix if the name is listed in
+/// `excluded`.
+#[cfg(any(windows, target_os = "macos"))]
+#[must_use]
+pub fn get_interface_name_excluding<S: AsRef<str>>(name: &str, excluded: &[S]) -> String {
+    let base_ifname = get_interface_name(name);
+    let is_excluded = |ifname: &str| excluded.iter().any(|name| name.as_ref() == ifname);
+    if !is_excluded(&base_ifname) {
+        return base_ifname;
+    }
+    for index in 1..=u16::MAX {
+        let ifname = format!("{base_ifname}{index}");
+        if !is_excluded(&ifname) {
+            return ifname;
+        }
+    }
+
+    base_ifname
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
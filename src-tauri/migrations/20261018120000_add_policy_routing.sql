@@ -0,0 +1,6 @@
+-- firewall mark and routing table of WireGuard interfaces, as in `FwMark` and `Table` keys of
+-- wg-quick configuration; NULL means none and the main routing table respectively
+ALTER TABLE location ADD COLUMN fwmark INTEGER NULL;
+ALTER TABLE location ADD COLUMN route_table INTEGER NULL;
+ALTER TABLE tunnel ADD COLUMN fwmark INTEGER NULL;
+ALTER TABLE tunnel ADD COLUMN route_table INTEGER NULL;
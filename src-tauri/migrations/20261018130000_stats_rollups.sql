@@ -0,0 +1,33 @@
+-- Aggregates of location and tunnel statistics.
+-- tier: 1 - minute, 2 - hour, 3 - day
+-- bucket: beginning of the aggregated period
+-- uptime: in seconds
+CREATE TABLE location_stats_rollup (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    location_id INTEGER NOT NULL,
+    tier INTEGER NOT NULL,
+    bucket TIMESTAMP NOT NULL,
+    upload BIGINT NOT NULL,
+    download BIGINT NOT NULL,
+    last_handshake BIGINT NOT NULL,
+    handshakes INTEGER NOT NULL,
+    uptime INTEGER NOT NULL,
+    FOREIGN KEY (location_id) REFERENCES location(id) ON DELETE CASCADE,
+    UNIQUE (location_id, tier, bucket)
+);
+
+CREATE TABLE tunnel_stats_rollup (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    tunnel_id INTEGER NOT NULL,
+    tier INTEGER NOT NULL,
+    bucket TIMESTAMP NOT NULL,
+    upload BIGINT NOT NULL,
+    download BIGINT NOT NULL,
+    last_handshake BIGINT NOT NULL,
+    handshakes INTEGER NOT NULL,
+    uptime INTEGER NOT NULL,
+    FOREIGN KEY (tunnel_id) REFERENCES tunnel(id) ON DELETE CASCADE,
+    UNIQUE (tunnel_id, tier, bucket)
+);
+
+CREATE INDEX idx_collected_tunnel ON tunnel_stats (collected_at, tunnel_id);
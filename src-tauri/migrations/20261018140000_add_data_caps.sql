@@ -0,0 +1,14 @@
+-- Data caps of locations, in bytes of upload and download combined.
+-- period: 1 - day, 2 - month
+-- alert_period, alert_level: start of the period of the latest alert, and the percentage of the
+-- cap it was about
+CREATE TABLE data_cap (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    location_id INTEGER NOT NULL UNIQUE,
+    cap BIGINT NOT NULL,
+    period INTEGER NOT NULL,
+    disconnect BOOLEAN NOT NULL,
+    alert_period TIMESTAMP,
+    alert_level INTEGER NOT NULL DEFAULT 0,
+    FOREIGN KEY (location_id) REFERENCES location(id) ON DELETE CASCADE
+);
@@ -0,0 +1,32 @@
+-- in-tunnel target of connection quality probes: IP address with an optional UDP port;
+-- NULL disables probing
+ALTER TABLE location ADD COLUMN probe_target TEXT NULL;
+ALTER TABLE tunnel ADD COLUMN probe_target TEXT NULL;
+
+-- Results of rounds of connection quality probes.
+-- rtt: average round-trip time in microseconds, NULL if no probe was answered
+-- jitter: average difference between consecutive round-trip times in microseconds, NULL if fewer
+-- than two probes were answered
+CREATE TABLE location_quality_stats (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    location_id INTEGER NOT NULL,
+    rtt INTEGER NULL,
+    jitter INTEGER NULL,
+    sent INTEGER NOT NULL,
+    received INTEGER NOT NULL,
+    collected_at TIMESTAMP NOT NULL,
+    FOREIGN KEY (location_id) REFERENCES location(id) ON DELETE CASCADE
+);
+CREATE INDEX idx_location_quality_stats ON location_quality_stats (location_id, collected_at);
+
+CREATE TABLE tunnel_quality_stats (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    tunnel_id INTEGER NOT NULL,
+    rtt INTEGER NULL,
+    jitter INTEGER NULL,
+    sent INTEGER NOT NULL,
+    received INTEGER NOT NULL,
+    collected_at TIMESTAMP NOT NULL,
+    FOREIGN KEY (tunnel_id) REFERENCES tunnel(id) ON DELETE CASCADE
+);
+CREATE INDEX idx_tunnel_quality_stats ON tunnel_quality_stats (tunnel_id, collected_at);
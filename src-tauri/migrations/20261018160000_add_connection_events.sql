@@ -0,0 +1,24 @@
+-- Why connections have ended: 1 - disconnected by the user, 2 - dropped as dead,
+-- 3 - closed on application exit, 4 - interface removed by the background service,
+-- 5 - data cap reached, 6 - disconnected to reconnect; NULL if not known.
+ALTER TABLE connection ADD COLUMN reason INTEGER NULL;
+ALTER TABLE tunnel_connection ADD COLUMN reason INTEGER NULL;
+
+-- Timeline of connections of locations and tunnels.
+-- kind: 1 - connect attempt, 2 - connected, 3 - failure, 4 - reconnect attempt, 5 - disconnected
+-- reason: as above, for disconnections only
+-- message: error text of failures
+CREATE TABLE connection_event (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    location_id INTEGER NULL,
+    tunnel_id INTEGER NULL,
+    kind INTEGER NOT NULL,
+    reason INTEGER NULL,
+    message TEXT NULL,
+    timestamp TIMESTAMP NOT NULL,
+    FOREIGN KEY (location_id) REFERENCES location(id) ON DELETE CASCADE,
+    FOREIGN KEY (tunnel_id) REFERENCES tunnel(id) ON DELETE CASCADE,
+    CHECK ((location_id IS NULL) != (tunnel_id IS NULL))
+);
+CREATE INDEX idx_connection_event_location ON connection_event (location_id, timestamp);
+CREATE INDEX idx_connection_event_tunnel ON connection_event (tunnel_id, timestamp);
@@ -4,7 +4,12 @@ use tokio::sync::Mutex;
 
 use crate::{
     database::{
-        models::{connection::ActiveConnection, instance::Instance, location::Location, Id},
+        models::{
+            connection::{ActiveConnection, DisconnectReason},
+            instance::Instance,
+            location::Location,
+            Id,
+        },
         DB_POOL,
     },
     error::Error,
@@ -34,17 +39,19 @@ pub(crate) async fn get_connection_id_by_type(connection_type: ConnectionType) -
 
 pub async fn close_all_connections() -> Result<(), Error> {
     debug!("Closing all active connections");
-    let active_connections = ACTIVE_CONNECTIONS.lock().await;
+    // Take the connections out of the list before removing their interfaces, so the interface
+    // event watcher doesn't record them as disconnected by the background service.
+    let active_connections = std::mem::take(&mut *ACTIVE_CONNECTIONS.lock().await);
     let active_connections_count = active_connections.len();
     debug!("Found {active_connections_count} active connections");
-    for connection in active_connections.iter() {
+    for connection in &active_connections {
         debug!(
             "Found active connection with location {}",
             connection.location_id
         );
         trace!("Connection: {connection:#?}");
         debug!("Removing interface {}", connection.interface_name);
-        disconnect_interface(connection).await?;
+        disconnect_interface(connection, DisconnectReason::AppExit).await?;
     }
     if active_connections_count > 0 {
         info!("All active connections ({active_connections_count}) have been closed.");
@@ -91,7 +98,10 @@ pub(crate) async fn active_connections(
         .lock()
         .await
         .iter()
-        .filter(|connection| locations.contains(&connection.location_id))
+        .filter(|connection| {
+            connection.connection_type == ConnectionType::Location
+                && locations.contains(&connection.location_id)
+        })
         .cloned()
         .collect())
 }
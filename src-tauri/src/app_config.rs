@@ -3,12 +3,15 @@ use std::{
     path::PathBuf,
 };
 
+use chrono::TimeDelta;
 use log::LevelFilter;
 use serde::{Deserialize, Serialize};
 use struct_patch::Patch;
 use strum::{AsRefStr, EnumString};
 use tauri::{AppHandle, Manager};
 
+use crate::database::models::stats_rollup::StatsRetention;
+
 #[cfg(unix)]
 use crate::set_perms;
 
@@ -72,6 +75,14 @@ pub struct AppConfig {
     pub peer_alive_period: u32,
     /// Maximal transmission unit. 0 means default value.
     mtu: u32,
+    /// In days. How long raw statistics are kept.
+    pub stats_retention_raw: u32,
+    /// In days. How long per-minute statistics rollups are kept.
+    pub stats_retention_minute: u32,
+    /// In days. How long per-hour statistics rollups are kept.
+    pub stats_retention_hour: u32,
+    /// In days. How long per-day statistics rollups are kept.
+    pub stats_retention_day: u32,
 }
 
 // Important: keep in sync with client store default in frontend
@@ -84,6 +95,10 @@ impl Default for AppConfig {
             log_level: LevelFilter::Info,
             peer_alive_period: 300,
             mtu: 0,
+            stats_retention_raw: 30,
+            stats_retention_minute: 30,
+            stats_retention_hour: 180,
+            stats_retention_day: 730,
         }
     }
 }
@@ -143,4 +158,17 @@ impl AppConfig {
             v => Some(v),
         }
     }
+
+    /// Retention of statistics and their rollups. Each tier is kept for at least a day, so
+    /// rollups can be computed before statistics are purged.
+    #[must_use]
+    pub fn stats_retention(&self) -> StatsRetention {
+        let days = |days: u32| TimeDelta::days(i64::from(days.max(1)));
+        StatsRetention {
+            raw: days(self.stats_retention_raw),
+            minute: days(self.stats_retention_minute),
+            hour: days(self.stats_retention_hour),
+            day: days(self.stats_retention_day),
+        }
+    }
 }
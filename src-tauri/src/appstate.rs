@@ -1,6 +1,7 @@
 use std::{collections::HashMap, sync::Mutex};
 
 use tauri::async_runtime::{spawn, JoinHandle};
+use tokio::select;
 use tokio_util::sync::CancellationToken;
 
 use crate::{
@@ -8,6 +9,7 @@ use crate::{
     app_config::AppConfig,
     database::models::{connection::ActiveConnection, Id},
     enterprise::provisioning::ProvisioningConfig,
+    probe::probe_handler,
     utils::stats_handler,
     ConnectionType,
 };
@@ -46,9 +48,16 @@ impl AppState {
 
         debug!("Spawning thread for network statistics for location ID {location_id}");
         #[cfg(target_os = "macos")]
-        let handle = spawn(stats_handler(location_id, connection_type));
+        let stats = stats_handler(location_id, connection_type);
         #[cfg(not(target_os = "macos"))]
-        let handle = spawn(stats_handler(ifname, connection_type));
+        let stats = stats_handler(ifname, connection_type);
+        // Connection quality is probed alongside, and stopped together with the statistics.
+        let handle = spawn(async move {
+            select! {
+                () = stats => debug!("Network statistics for location ID {location_id} stopped"),
+                () = probe_handler(location_id, connection_type) => {}
+            }
+        });
         let Some(old_handle) = self
             .stat_threads
             .lock()
@@ -8,11 +8,20 @@ use std::sync::{
     atomic::{AtomicBool, Ordering},
     Arc,
 };
-use std::{env, str::FromStr, sync::LazyLock};
+use std::{
+    env,
+    path::{Path, PathBuf},
+    process,
+    str::FromStr,
+    sync::LazyLock,
+};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use clap::Parser;
 
 #[cfg(unix)]
 use defguard_client::set_perms;
-#[cfg(windows)]
+#[cfg(any(windows, target_os = "linux"))]
 use defguard_client::utils::sync_connections;
 use defguard_client::{
     active_connections::close_all_connections,
@@ -21,15 +30,20 @@ use defguard_client::{
     commands::*,
     database::{
         handle_db_migrations,
-        models::{location_stats::LocationStats, tunnel::TunnelStats},
+        models::{
+            stats_rollup::{purge_stats, rollup_stats},
+            Id,
+        },
         DB_POOL,
     },
     enterprise::provisioning::handle_client_initialization,
+    error::Error,
+    export::{export_to_file, ExportData, ExportFormat},
     periodic::run_periodic_tasks,
     service,
     tray::{configure_tray_icon, setup_tray, show_main_window},
     utils::load_log_targets,
-    LOG_FILENAME, VERSION,
+    ConnectionType, LOG_FILENAME, VERSION,
 };
 use log::{Level, LevelFilter};
 use tauri::{AppHandle, Builder, Manager, RunEvent, WindowEvent};
@@ -44,25 +58,101 @@ const LOGGING_TARGET_IGNORE_LIST: [&str; 5] = ["tauri", "sqlx", "hyper", "h2", "
 
 static LOG_INCLUDES: LazyLock<Vec<String>> = LazyLock::new(load_log_targets);
 
+/// Unknown arguments are ignored, as the client is also started with deep links.
+#[derive(Debug, Parser)]
+#[command(version, ignore_errors = true)]
+struct Cli {
+    /// Export connection history or statistics of a location or tunnel to FILE and exit
+    #[arg(long, value_name = "FILE")]
+    export: Option<PathBuf>,
+
+    /// ID of the location to export
+    #[arg(long)]
+    location: Option<Id>,
+
+    /// ID of the tunnel to export
+    #[arg(long)]
+    tunnel: Option<Id>,
+
+    /// Data to export
+    #[arg(long, value_enum, default_value_t)]
+    data: ExportData,
+
+    /// Format of the exported file
+    #[arg(long, value_enum, default_value_t)]
+    format: ExportFormat,
+
+    /// Beginning of the exported range (RFC 3339), an hour ago by default
+    #[arg(long)]
+    from: Option<DateTime<Utc>>,
+
+    /// End of the exported range (RFC 3339), now by default
+    #[arg(long)]
+    to: Option<DateTime<Utc>>,
+}
+
+/// Export data requested with `--export` without starting the application.
+fn export(cli: &Cli, path: &Path) -> Result<(), Error> {
+    let (location_id, connection_type) = match (cli.location, cli.tunnel) {
+        (Some(location_id), None) => (location_id, ConnectionType::Location),
+        (None, Some(tunnel_id)) => (tunnel_id, ConnectionType::Tunnel),
+        _ => {
+            return Err(Error::InternalError(
+                "Either --location or --tunnel is required to export".into(),
+            ))
+        }
+    };
+    let from = cli
+        .from
+        .unwrap_or_else(|| Utc::now() - TimeDelta::hours(1))
+        .naive_utc();
+    let to = cli.to.unwrap_or_else(Utc::now).naive_utc();
+    tauri::async_runtime::block_on(async {
+        handle_db_migrations().await;
+        export_to_file(
+            path,
+            location_id,
+            connection_type,
+            cli.data,
+            cli.format,
+            from,
+            to,
+        )
+        .await
+    })
+}
+
 async fn startup(app_handle: &AppHandle) {
-    debug!("Purging old stats from the database.");
-    if let Err(err) = LocationStats::purge(&*DB_POOL).await {
-        error!("Failed to purge location stats: {err}");
-    } else {
-        debug!("Old location stats have been purged successfully.");
-    }
-    if let Err(err) = TunnelStats::purge(&*DB_POOL).await {
-        error!("Failed to purge tunnel stats: {err}");
-    } else {
-        debug!("Old tunnel stats have been purged successfully.");
+    debug!("Rolling up and purging old stats from the database.");
+    let retention = app_handle
+        .state::<AppState>()
+        .app_config
+        .lock()
+        .expect("failed to lock app state")
+        .stats_retention();
+    match DB_POOL.begin().await {
+        Ok(mut transaction) => {
+            // Stats have to be rolled up before they're purged.
+            if let Err(err) = rollup_stats(&mut *transaction).await {
+                error!("Failed to roll up stats: {err}");
+            } else if let Err(err) = purge_stats(&mut *transaction, &retention).await {
+                error!("Failed to purge stats: {err}");
+            } else if let Err(err) = transaction.commit().await {
+                error!("Failed to commit stats rollup and purging: {err}");
+            } else {
+                debug!("Old stats have been rolled up and purged successfully.");
+            }
+        }
+        Err(err) => error!("Failed to begin database transaction for stats purging: {err}"),
     }
 
-    // Sync already active connections on windows.
+    // Sync already active connections on windows and linux.
     // When windows is restarted, the app doesn't close the active connections
-    // and they are still running after the restart. We sync them here to
-    // reflect the real system's state.
+    // and they are still running after the restart. On linux, interfaces managed
+    // by the background service outlive the client if it crashes or gets restarted.
+    // We sync them here to reflect the real system's state.
     // TODO: Find a way to intercept the shutdown event and close all connections
-    #[cfg(windows)]
+    #[cfg(any(windows, target_os = "linux"))]
     {
         match sync_connections(app_handle).await {
             Ok(()) => {
@@ -152,6 +242,15 @@ async fn startup(app_handle: &AppHandle) {
 }
 
 fn main() {
+    let cli = Cli::parse();
+    if let Some(path) = &cli.export {
+        if let Err(err) = export(&cli, path) {
+            eprintln!("Failed to export to {}: {err}", path.display());
+            process::exit(1);
+        }
+        return;
+    }
+
     let app = Builder::default()
         .invoke_handler(tauri::generate_handler![
             all_locations,
@@ -161,11 +260,18 @@ fn main() {
             disconnect,
             update_instance,
             location_stats,
+            usage_totals,
+            data_cap,
+            save_data_cap,
+            delete_data_cap,
+            export_data,
             location_interface_details,
             all_connections,
+            connection_timeline,
             last_connection,
             active_connection,
             update_location_routing,
+            update_probe_target,
             delete_instance,
             parse_tunnel_config,
             save_tunnel,
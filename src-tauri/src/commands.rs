@@ -2,6 +2,7 @@ use core::fmt;
 use std::{
     collections::{HashMap, HashSet},
     env,
+    path::PathBuf,
     str::FromStr,
 };
 
@@ -19,23 +20,33 @@ use crate::{
     appstate::AppState,
     database::{
         models::{
-            connection::{ActiveConnection, Connection, ConnectionInfo},
+            connection::{ActiveConnection, Connection, ConnectionInfo, DisconnectReason},
+            connection_event::{record_event, ConnectionEvent, ConnectionEventKind},
             instance::{ClientTrafficPolicy, Instance, InstanceInfo},
             location::{Location, LocationMfaMode},
             location_stats::LocationStats,
+            quality_stats::{merge_quality, QualityStats},
+            stats_rollup::RollupTier,
             tunnel::{Tunnel, TunnelConnection, TunnelConnectionInfo, TunnelStats},
+            usage::{DataCap, UsagePeriod, UsageTotal},
             wireguard_keys::WireguardKeys,
             Id, NoId,
         },
         DB_POOL,
     },
-    enterprise::{periodic::config::poll_instance, provisioning::ProvisioningConfig},
+    enterprise::{
+        periodic::config::{poll_instance, update_connections},
+        provisioning::ProvisioningConfig,
+    },
     error::Error,
     events::EventKey,
+    export::{export_to_file, ExportData, ExportFormat},
     log_watcher::{
         global_log_watcher::{spawn_global_log_watcher_task, stop_global_log_watcher_task},
         service_log_watcher::stop_log_watcher_task,
     },
+    periodic::data_caps::ensure_within_data_cap,
+    probe::parse_probe_target,
     proto::DeviceConfigResponse,
     tray::{configure_tray_icon, reload_tray_menu},
     utils::{
@@ -54,7 +65,7 @@ use crate::{
             DeleteServiceLocationsRequest, RemoveInterfaceRequest, SaveServiceLocationsRequest,
         },
     },
-    utils::execute_command,
+    utils::{execute_command, update_active_connections},
 };
 
 /// Open new WireGuard connection.
@@ -66,14 +77,39 @@ pub async fn connect(
     handle: AppHandle,
 ) -> Result<(), Error> {
     debug!("Received a command to connect to a {connection_type} with ID {location_id}");
+    record_event(
+        location_id,
+        connection_type,
+        ConnectionEventKind::Connecting,
+        None,
+        None,
+    )
+    .await;
+    let result = open_connection(location_id, connection_type, preshared_key, &handle).await;
+    let (kind, message) = match &result {
+        Ok(()) => (ConnectionEventKind::Connected, None),
+        Err(err) => (ConnectionEventKind::Failed, Some(err.to_string())),
+    };
+    record_event(location_id, connection_type, kind, None, message).await;
+
+    result
+}
+
+async fn open_connection(
+    location_id: Id,
+    connection_type: ConnectionType,
+    preshared_key: Option<String>,
+    handle: &AppHandle,
+) -> Result<(), Error> {
     if connection_type == ConnectionType::Location {
         if let Some(location) = Location::find_by_id(&*DB_POOL, location_id).await? {
             debug!(
                 "Identified location with ID {location_id} as \"{}\", handling connection.",
                 location.name
             );
-            handle_connection_for_location(&location, preshared_key, &handle).await?;
-            reload_tray_menu(&handle).await;
+            ensure_within_data_cap(&location).await?;
+            handle_connection_for_location(&location, preshared_key, handle).await?;
+            reload_tray_menu(handle).await;
             info!("Connected to location {location}");
         } else {
             error!(
@@ -87,7 +123,7 @@ pub async fn connect(
             "Identified tunnel with ID {location_id} as \"{}\", handling connection...",
             tunnel.name
         );
-        handle_connection_for_tunnel(&tunnel, &handle).await?;
+        handle_connection_for_tunnel(&tunnel, handle).await?;
         info!("Successfully connected to tunnel {tunnel}");
     } else {
         error!("Tunnel {location_id} not found");
@@ -95,7 +131,7 @@ pub async fn connect(
     }
 
     // Update tray icon to reflect connection state.
-    configure_tray_icon(&handle).await?;
+    configure_tray_icon(handle).await?;
 
     Ok(())
 }
@@ -119,6 +155,16 @@ pub async fn disconnect(
     location_id: Id,
     connection_type: ConnectionType,
     handle: AppHandle,
+) -> Result<(), Error> {
+    disconnect_with_reason(location_id, connection_type, handle, DisconnectReason::User).await
+}
+
+/// Disconnect from a location or tunnel, recording `reason` in its connection history.
+pub(crate) async fn disconnect_with_reason(
+    location_id: Id,
+    connection_type: ConnectionType,
+    handle: AppHandle,
+    reason: DisconnectReason,
 ) -> Result<(), Error> {
     let state = handle.state::<AppState>();
     let name = get_tunnel_or_location_name(location_id, connection_type).await;
@@ -134,7 +180,7 @@ pub async fn disconnect(
             {connection_type} {name}({location_id})"
         );
         trace!("Connection: {connection:?}");
-        disconnect_interface(&connection).await?;
+        disconnect_interface(&connection, reason).await?;
         debug!(
             "Emitting the event informing the frontend about the disconnection from \
             {connection_type} {name}({location_id})"
@@ -201,8 +247,11 @@ async fn maybe_update_instance_config(location_id: Id, handle: &AppHandle) -> Re
         );
         return Err(Error::NotFound);
     };
-    poll_instance(&mut transaction, &mut instance, handle).await?;
+    let connections = poll_instance(&mut transaction, &mut instance, handle).await?;
     transaction.commit().await?;
+    if !connections.is_empty() {
+        update_connections(&instance, &connections, handle).await;
+    }
     handle.emit(EventKey::InstanceUpdate.into(), ())?;
     Ok(())
 }
@@ -418,6 +467,7 @@ pub struct LocationInfo {
     pub pubkey: String,
     pub network_id: Id,
     pub location_mfa_mode: LocationMfaMode,
+    pub probe_target: Option<String>,
 }
 
 impl LocationInfo {
@@ -470,6 +520,7 @@ pub async fn all_locations(instance_id: Id) -> Result<Vec<LocationInfo>, Error>
             pubkey: location.pubkey,
             network_id: location.network_id,
             location_mfa_mode: location.location_mfa_mode,
+            probe_target: location.probe_target,
         };
         location_info.push(info);
     }
@@ -545,8 +596,11 @@ pub(crate) async fn locations_changed(
             .into_iter()
             .map(|location| {
                 let mut new_location = Location::<NoId>::from(location);
-                // Ignore `route_all_traffic` flag as Defguard core does not have it.
+                // Ignore local settings as Defguard core does not have them.
                 new_location.route_all_traffic = false;
+                new_location.fwmark = None;
+                new_location.route_table = None;
+                new_location.probe_target = None;
                 new_location
             })
             .collect();
@@ -765,6 +819,7 @@ pub(crate) fn parse_timestamp(from: Option<String>) -> Result<DateTime<Utc>, Err
 }
 
 pub(crate) enum DateTimeAggregation {
+    Day,
     Hour,
     Second,
 }
@@ -774,19 +829,35 @@ impl DateTimeAggregation {
     #[must_use]
     pub(crate) fn fstring(&self) -> &'static str {
         match self {
+            Self::Day => "%Y-%m-%d 00:00:00",
             Self::Hour => "%Y-%m-%d %H:00:00",
             Self::Second => "%Y-%m-%d %H:%M:%S",
         }
     }
+
+    /// Returns statistics rollup tier for a given aggregation variant, or `None` if raw
+    /// statistics should be used.
+    #[must_use]
+    pub(crate) fn tier(&self) -> Option<RollupTier> {
+        match self {
+            Self::Day => Some(RollupTier::Day),
+            Self::Hour => Some(RollupTier::Hour),
+            Self::Second => None,
+        }
+    }
 }
 
-pub(crate) fn get_aggregation(from: NaiveDateTime) -> Result<DateTimeAggregation, Error> {
-    // Use hourly aggregation for longer periods
-    let aggregation = match Utc::now().naive_utc() - from {
+/// Aggregation of statistics between `from` and `to`.
+pub(crate) fn get_range_aggregation(
+    from: NaiveDateTime,
+    to: NaiveDateTime,
+) -> Result<DateTimeAggregation, Error> {
+    // Use hourly and daily aggregation for longer periods
+    let aggregation = match to - from {
+        duration if duration >= Duration::days(30) => Ok(DateTimeAggregation::Day),
         duration if duration >= Duration::hours(8) => Ok(DateTimeAggregation::Hour),
         duration if duration < Duration::zero() => Err(Error::InternalError(format!(
-            "Negative duration between dates: now ({}) and {from}",
-            Utc::now().naive_utc(),
+            "Negative duration between dates: {to} and {from}"
         ))),
         _ => Ok(DateTimeAggregation::Second),
     }?;
@@ -801,27 +872,136 @@ pub async fn location_stats(
 ) -> Result<Vec<CommonLocationStats<Id>>, Error> {
     trace!("Location stats command received");
     let from = parse_timestamp(from)?.naive_utc();
-    let aggregation = get_aggregation(from)?;
-    let stats = match connection_type {
-        ConnectionType::Location => {
-            LocationStats::all_by_location_id(&*DB_POOL, location_id, &from, &aggregation, None)
-                .await?
-                .into_iter()
-                .map(Into::into)
-                .collect()
-        }
+    let to = Utc::now().naive_utc();
+    let aggregation = get_range_aggregation(from, to)?;
+    let mut stats: Vec<CommonLocationStats<Id>> = match connection_type {
+        ConnectionType::Location => LocationStats::all_by_location_id(
+            &*DB_POOL,
+            location_id,
+            &from,
+            &to,
+            &aggregation,
+            None,
+        )
+        .await?
+        .into_iter()
+        .map(Into::into)
+        .collect(),
         ConnectionType::Tunnel => {
-            TunnelStats::all_by_tunnel_id(&*DB_POOL, location_id, &from, &aggregation)
+            TunnelStats::all_by_tunnel_id(&*DB_POOL, location_id, &from, &to, &aggregation)
                 .await?
                 .into_iter()
                 .map(Into::into)
                 .collect()
         }
     };
+    if let Some(first) = stats.first() {
+        let since = first.collected_at;
+        let quality = match connection_type {
+            ConnectionType::Location => {
+                QualityStats::all_by_location_id(&*DB_POOL, location_id, &since).await?
+            }
+            ConnectionType::Tunnel => {
+                QualityStats::all_by_tunnel_id(&*DB_POOL, location_id, &since).await?
+            }
+        };
+        merge_quality(&mut stats, &quality);
+    }
 
     Ok(stats)
 }
 
+#[tauri::command(async)]
+pub async fn usage_totals(
+    location_id: Id,
+    connection_type: ConnectionType,
+    period: UsagePeriod,
+    from: Option<String>,
+) -> Result<Vec<UsageTotal>, Error> {
+    trace!("Usage totals command received");
+    let from = match from {
+        Some(from) => parse_timestamp(Some(from))?.naive_utc(),
+        // Last 30 days or 12 months by default.
+        None => match period {
+            UsagePeriod::Day => Utc::now() - Duration::days(29),
+            UsagePeriod::Month => Utc::now() - Duration::days(335),
+        }
+        .naive_utc(),
+    };
+    let totals = match connection_type {
+        ConnectionType::Location => {
+            UsageTotal::all_by_location_id(&*DB_POOL, location_id, period, &from).await?
+        }
+        ConnectionType::Tunnel => {
+            UsageTotal::all_by_tunnel_id(&*DB_POOL, location_id, period, &from).await?
+        }
+    };
+
+    Ok(totals)
+}
+
+#[tauri::command(async)]
+pub async fn data_cap(location_id: Id) -> Result<Option<DataCap<Id>>, Error> {
+    debug!("Retrieving data cap of location {location_id}");
+    DataCap::find_by_location_id(&*DB_POOL, location_id).await
+}
+
+#[tauri::command(async)]
+pub async fn save_data_cap(
+    location_id: Id,
+    cap: i64,
+    period: UsagePeriod,
+    disconnect: bool,
+) -> Result<(), Error> {
+    debug!("Received data cap of location {location_id}: {cap} bytes per {period:?}");
+    if cap <= 0 {
+        return Err(Error::InternalError(format!(
+            "Data cap has to be positive, got {cap}"
+        )));
+    }
+    DataCap {
+        id: NoId,
+        location_id,
+        cap,
+        period,
+        disconnect,
+        alert_period: None,
+        alert_level: 0,
+    }
+    .save(&*DB_POOL)
+    .await?;
+    info!("Data cap of location {location_id} has been saved");
+    Ok(())
+}
+
+#[tauri::command(async)]
+pub async fn delete_data_cap(location_id: Id) -> Result<(), Error> {
+    debug!("Deleting data cap of location {location_id}");
+    DataCap::delete_by_location_id(&*DB_POOL, location_id).await?;
+    info!("Data cap of location {location_id} has been deleted");
+    Ok(())
+}
+
+#[tauri::command(async)]
+pub async fn export_data(
+    location_id: Id,
+    connection_type: ConnectionType,
+    data: ExportData,
+    format: ExportFormat,
+    from: Option<String>,
+    to: Option<String>,
+    path: PathBuf,
+) -> Result<(), Error> {
+    debug!("Received request to export {data:?} of {connection_type} {location_id}");
+    let from = parse_timestamp(from)?.naive_utc();
+    let to = match to {
+        Some(to) => parse_timestamp(Some(to))?,
+        None => Utc::now(),
+    }
+    .naive_utc();
+    export_to_file(&path, location_id, connection_type, data, format, from, to).await
+}
+
 #[tauri::command(async)]
 pub async fn all_connections(
     location_id: Id,
@@ -845,6 +1025,25 @@ pub async fn all_connections(
     Ok(connections)
 }
 
+/// Timeline of connect attempts, failures, reconnects and disconnections of a location or tunnel
+/// since `from`, ordered from the latest event. All events are returned if `from` is not set.
+#[tauri::command(async)]
+pub async fn connection_timeline(
+    location_id: Id,
+    connection_type: ConnectionType,
+    from: Option<String>,
+) -> Result<Vec<ConnectionEvent<Id>>, Error> {
+    debug!("Retrieving connection timeline of {connection_type} {location_id}");
+    let from = match from {
+        Some(from) => parse_timestamp(Some(from))?.naive_utc(),
+        None => DateTime::UNIX_EPOCH.naive_utc(),
+    };
+    let events = ConnectionEvent::all(&*DB_POOL, location_id, connection_type, &from).await?;
+    debug!("Connection events retrieved({})", events.len());
+    trace!("Connection events found:\n{events:#?}");
+    Ok(events)
+}
+
 #[tauri::command(async)]
 pub async fn all_tunnel_connections(location_id: Id) -> Result<Vec<TunnelConnectionInfo>, Error> {
     debug!("Retrieving connections for location {location_id}");
@@ -978,6 +1177,41 @@ pub async fn update_location_routing(
     }
 }
 
+#[tauri::command(async)]
+pub async fn update_probe_target(
+    location_id: Id,
+    connection_type: ConnectionType,
+    probe_target: Option<String>,
+    handle: AppHandle,
+) -> Result<(), Error> {
+    debug!("Updating probe target of {connection_type} {location_id} to {probe_target:?}");
+    let probe_target = probe_target.filter(|target| !target.trim().is_empty());
+    if let Some(target) = &probe_target {
+        parse_probe_target(target)?;
+    }
+
+    match connection_type {
+        ConnectionType::Location => {
+            let mut location = Location::find_by_id(&*DB_POOL, location_id)
+                .await?
+                .ok_or(Error::NotFound)?;
+            location.probe_target = probe_target;
+            location.save(&*DB_POOL).await?;
+            info!("Probe target of location {location} has been updated");
+        }
+        ConnectionType::Tunnel => {
+            let mut tunnel = Tunnel::find_by_id(&*DB_POOL, location_id)
+                .await?
+                .ok_or(Error::NotFound)?;
+            tunnel.probe_target = probe_target;
+            tunnel.save(&*DB_POOL).await?;
+            info!("Probe target of tunnel {tunnel} has been updated");
+        }
+    }
+    handle.emit(EventKey::LocationUpdate.into(), ())?;
+    Ok(())
+}
+
 #[cfg(target_os = "macos")]
 #[tauri::command(async)]
 pub async fn delete_instance(instance_id: Id, handle: AppHandle) -> Result<(), Error> {
@@ -1054,6 +1288,7 @@ pub async fn delete_instance(instance_id: Id, handle: AppHandle) -> Result<(), E
             let request = RemoveInterfaceRequest {
                 interface_name: connection.interface_name.clone(),
                 endpoint: location.endpoint.clone(),
+                reconnect: false,
             };
             client.remove_interface(request).await.map_err(|status| {
                 error!(
@@ -1116,6 +1351,19 @@ pub async fn update_tunnel(mut tunnel: Tunnel<Id>, handle: AppHandle) -> Result<
     tunnel.save(&*DB_POOL).await?;
     info!("The tunnel {tunnel} configuration has been updated.");
     handle.emit(EventKey::LocationUpdate.into(), ())?;
+    #[cfg(not(target_os = "macos"))]
+    if let Some(connection) = find_connection(tunnel.id, ConnectionType::Tunnel).await {
+        debug!("Applying new configuration of tunnel {tunnel} to its active connection");
+        let mtu = handle
+            .state::<AppState>()
+            .app_config
+            .lock()
+            .expect("failed to lock app state")
+            .mtu();
+        if let Some(err) = update_active_connections(&[connection], mtu).await.pop() {
+            return Err(err);
+        }
+    }
     Ok(())
 }
 
@@ -1137,6 +1385,7 @@ pub struct TunnelInfo<I = NoId> {
     pub active: bool,
     pub route_all_traffic: bool,
     pub connection_type: ConnectionType,
+    pub probe_target: Option<String>,
 }
 
 #[tauri::command(async)]
@@ -1157,6 +1406,7 @@ pub async fn all_tunnels() -> Result<Vec<TunnelInfo<Id>>, Error> {
             route_all_traffic: tunnel.route_all_traffic,
             active: active_tunnel_ids.contains(&tunnel.id),
             connection_type: ConnectionType::Tunnel,
+            probe_target: tunnel.probe_target,
         });
     }
 
@@ -1227,6 +1477,7 @@ pub async fn delete_tunnel(tunnel_id: Id, handle: AppHandle) -> Result<(), Error
             let request = RemoveInterfaceRequest {
                 interface_name: connection.interface_name.clone(),
                 endpoint: tunnel.endpoint.clone(),
+                reconnect: false,
             };
             DAEMON_CLIENT
                 .clone()
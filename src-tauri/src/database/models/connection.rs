@@ -1,16 +1,35 @@
 use chrono::{NaiveDateTime, Utc};
-use serde::Serialize;
-use sqlx::{query_as, query_scalar, SqliteExecutor};
+use serde::{Deserialize, Serialize};
+use sqlx::{query_as, query_scalar, SqliteExecutor, Type};
 
 use super::{Id, NoId};
 use crate::{error::Error, CommonConnection, CommonConnectionInfo, ConnectionType};
 
+/// Why a connection has ended.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize, Type)]
+#[repr(u32)]
+pub enum DisconnectReason {
+    /// Disconnected by the user.
+    User = 1,
+    /// Dropped by verification of active connections, as there was no traffic.
+    Dropped = 2,
+    /// Closed on exit of the application.
+    AppExit = 3,
+    /// Interface has been removed by the background service.
+    Service = 4,
+    /// Location has reached its data cap.
+    DataCap = 5,
+    /// Disconnected in order to reconnect.
+    Reconnect = 6,
+}
+
 #[derive(Debug, Serialize, Clone)]
 pub struct Connection<I = NoId> {
     pub id: I,
     pub location_id: Id,
     pub start: NaiveDateTime,
     pub end: NaiveDateTime,
+    pub reason: Option<DisconnectReason>,
 }
 
 impl Connection<NoId> {
@@ -19,11 +38,12 @@ impl Connection<NoId> {
         E: SqliteExecutor<'e>,
     {
         let id = query_scalar!(
-            "INSERT INTO connection (location_id, start, end) \
-            VALUES ($1, $2, $3) RETURNING id \"id!\"",
+            "INSERT INTO connection (location_id, start, end, reason) \
+            VALUES ($1, $2, $3, $4) RETURNING id \"id!\"",
             self.location_id,
             self.start,
             self.end,
+            self.reason,
         )
         .fetch_one(executor)
         .await?;
@@ -33,6 +53,7 @@ impl Connection<NoId> {
             location_id: self.location_id,
             start: self.start,
             end: self.end,
+            reason: self.reason,
         })
     }
 
@@ -45,7 +66,7 @@ impl Connection<NoId> {
     {
         let connection = query_as!(
             Connection,
-            "SELECT id, location_id, start, end \
+            "SELECT id, location_id, start, end, reason \"reason: DisconnectReason\" \
             FROM connection WHERE location_id = $1 \
             ORDER BY end DESC LIMIT 1",
             location_id
@@ -65,6 +86,7 @@ pub struct ConnectionInfo {
     pub end: NaiveDateTime,
     pub upload: Option<i32>,
     pub download: Option<i32>,
+    pub reason: Option<DisconnectReason>,
 }
 
 impl From<ConnectionInfo> for CommonConnectionInfo {
@@ -76,6 +98,7 @@ impl From<ConnectionInfo> for CommonConnectionInfo {
             end: val.end,
             upload: val.upload,
             download: val.download,
+            reason: val.reason,
         }
     }
 }
@@ -93,7 +116,7 @@ impl ConnectionInfo {
         // FIXME: Optimize query
         let connections = query_as!(
             ConnectionInfo,
-            "SELECT c.id, c.location_id, c.start, c.end, \
+            "SELECT c.id, c.location_id, c.start, c.end, c.reason \"reason: DisconnectReason\", \
             COALESCE((\
                 SELECT ls.upload \
                 FROM location_stats ls \
@@ -152,6 +175,7 @@ impl From<&ActiveConnection> for Connection<NoId> {
             location_id: active_connection.location_id,
             start: active_connection.start,
             end: Utc::now().naive_utc(),
+            reason: None,
         }
     }
 }
@@ -164,6 +188,51 @@ impl From<Connection<Id>> for CommonConnection<Id> {
             start: connection.start,
             end: connection.end,
             connection_type: ConnectionType::Location,
+            reason: connection.reason,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeDelta;
+    use sqlx::SqlitePool;
+
+    use super::*;
+    use crate::database::models::fixtures;
+
+    #[sqlx::test]
+    async fn save_reason(pool: SqlitePool) {
+        let location = fixtures::location(&pool).await;
+        let start = "2025-01-01T10:00:00".parse::<NaiveDateTime>().unwrap();
+        let save_connection = |hour, reason| {
+            Connection {
+                id: NoId,
+                location_id: location.id,
+                start: start + TimeDelta::hours(hour),
+                end: start + TimeDelta::hours(hour + 1),
+                reason,
+            }
+            .save(&pool)
+        };
+
+        // The reason of a connection may be unknown.
+        save_connection(1, None).await.unwrap();
+        save_connection(2, Some(DisconnectReason::DataCap))
+            .await
+            .unwrap();
+        let latest = Connection::latest_by_location_id(&pool, location.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(latest.reason, Some(DisconnectReason::DataCap));
+
+        let reasons = ConnectionInfo::all_by_location_id(&pool, location.id)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|connection| connection.reason)
+            .collect::<Vec<_>>();
+        assert_eq!(reasons, [Some(DisconnectReason::DataCap), None]);
+    }
+}
@@ -0,0 +1,235 @@
+//! Timeline of connections of locations and tunnels: connect attempts, failures, reconnects and
+//! disconnections.
+
+use chrono::{NaiveDateTime, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{query, query_as, query_scalar, SqliteExecutor, Type};
+
+use super::{connection::DisconnectReason, Id, NoId};
+use crate::{database::DB_POOL, error::Error, ConnectionType};
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize, Type)]
+#[repr(u32)]
+pub enum ConnectionEventKind {
+    Connecting = 1,
+    Connected = 2,
+    Failed = 3,
+    Reconnecting = 4,
+    Disconnected = 5,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ConnectionEvent<I = NoId> {
+    pub id: I,
+    pub kind: ConnectionEventKind,
+    /// Set for disconnections only.
+    pub reason: Option<DisconnectReason>,
+    /// Error text of failures.
+    pub message: Option<String>,
+    pub timestamp: NaiveDateTime,
+}
+
+impl ConnectionEvent {
+    #[must_use]
+    pub(crate) fn new(
+        kind: ConnectionEventKind,
+        reason: Option<DisconnectReason>,
+        message: Option<String>,
+    ) -> Self {
+        Self {
+            id: NoId,
+            kind,
+            reason,
+            message,
+            timestamp: Utc::now().naive_utc(),
+        }
+    }
+
+    pub(crate) async fn save<'e, E>(
+        self,
+        executor: E,
+        id: Id,
+        connection_type: ConnectionType,
+    ) -> Result<ConnectionEvent<Id>, Error>
+    where
+        E: SqliteExecutor<'e>,
+    {
+        let (location_id, tunnel_id) = match connection_type {
+            ConnectionType::Location => (Some(id), None),
+            ConnectionType::Tunnel => (None, Some(id)),
+        };
+        let id = query_scalar!(
+            "INSERT INTO connection_event (location_id, tunnel_id, kind, reason, message, timestamp) \
+            VALUES ($1, $2, $3, $4, $5, $6) RETURNING id \"id!\"",
+            location_id,
+            tunnel_id,
+            self.kind,
+            self.reason,
+            self.message,
+            self.timestamp,
+        )
+        .fetch_one(executor)
+        .await?;
+
+        Ok(ConnectionEvent::<Id> {
+            id,
+            kind: self.kind,
+            reason: self.reason,
+            message: self.message,
+            timestamp: self.timestamp,
+        })
+    }
+}
+
+impl ConnectionEvent<Id> {
+    /// Events of a location or tunnel since `from`, ordered from the latest one.
+    pub(crate) async fn all<'e, E>(
+        executor: E,
+        id: Id,
+        connection_type: ConnectionType,
+        from: &NaiveDateTime,
+    ) -> Result<Vec<Self>, Error>
+    where
+        E: SqliteExecutor<'e>,
+    {
+        let events = match connection_type {
+            ConnectionType::Location => {
+                query_as!(
+                    Self,
+                    "SELECT id, kind \"kind: ConnectionEventKind\", \
+                    reason \"reason: DisconnectReason\", message, timestamp \
+                    FROM connection_event WHERE location_id = $1 AND timestamp >= $2 \
+                    ORDER BY timestamp DESC, id DESC",
+                    id,
+                    from
+                )
+                .fetch_all(executor)
+                .await?
+            }
+            ConnectionType::Tunnel => {
+                query_as!(
+                    Self,
+                    "SELECT id, kind \"kind: ConnectionEventKind\", \
+                    reason \"reason: DisconnectReason\", message, timestamp \
+                    FROM connection_event WHERE tunnel_id = $1 AND timestamp >= $2 \
+                    ORDER BY timestamp DESC, id DESC",
+                    id,
+                    from
+                )
+                .fetch_all(executor)
+                .await?
+            }
+        };
+
+        Ok(events)
+    }
+
+    /// Purge events older than `retention`.
+    pub(crate) async fn purge<'e, E>(executor: E, retention: TimeDelta) -> Result<(), Error>
+    where
+        E: SqliteExecutor<'e>,
+    {
+        debug!("Purging connection events.");
+
+        let past = (Utc::now() - retention).naive_utc();
+        query!("DELETE FROM connection_event WHERE timestamp < $1", past)
+            .execute(executor)
+            .await?;
+
+        Ok(())
+    }
+}
+
+/// Record an event of a location or tunnel. Failures are only logged, as the timeline mustn't get
+/// in the way of connecting or disconnecting.
+pub(crate) async fn record_event(
+    id: Id,
+    connection_type: ConnectionType,
+    kind: ConnectionEventKind,
+    reason: Option<DisconnectReason>,
+    message: Option<String>,
+) {
+    let event = ConnectionEvent::new(kind, reason, message);
+    match event.save(&*DB_POOL, id, connection_type).await {
+        Ok(event) => trace!("Recorded event of {connection_type} {id}: {event:?}"),
+        Err(err) => error!("Failed to record {kind:?} event of {connection_type} {id}: {err}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::SqlitePool;
+
+    use super::*;
+    use crate::database::models::fixtures;
+
+    #[sqlx::test]
+    async fn save_events(pool: SqlitePool) {
+        let tunnel_id = fixtures::tunnel(&pool).await.id;
+        let now = Utc::now().naive_utc();
+        let save_event = |kind, reason, message: Option<&str>, age| {
+            let mut event = ConnectionEvent::new(kind, reason, message.map(Into::into));
+            event.timestamp = now - TimeDelta::minutes(age);
+            event.save(&pool, tunnel_id, ConnectionType::Tunnel)
+        };
+        save_event(ConnectionEventKind::Connecting, None, None, 60 * 24 * 60)
+            .await
+            .unwrap();
+        save_event(ConnectionEventKind::Failed, None, Some("timeout"), 3)
+            .await
+            .unwrap();
+        save_event(ConnectionEventKind::Connected, None, None, 2)
+            .await
+            .unwrap();
+        let disconnected = save_event(
+            ConnectionEventKind::Disconnected,
+            Some(DisconnectReason::User),
+            None,
+            1,
+        )
+        .await
+        .unwrap();
+
+        let events = |from| {
+            let pool = pool.clone();
+            async move {
+                ConnectionEvent::all(&pool, tunnel_id, ConnectionType::Tunnel, &from)
+                    .await
+                    .unwrap()
+                    .into_iter()
+                    .map(|event| (event.kind, event.reason, event.message))
+                    .collect::<Vec<_>>()
+            }
+        };
+        // The latest events come first.
+        let from = now - TimeDelta::hours(1);
+        assert_eq!(
+            events(from).await,
+            [
+                (
+                    ConnectionEventKind::Disconnected,
+                    Some(DisconnectReason::User),
+                    None
+                ),
+                (ConnectionEventKind::Connected, None, None),
+                (ConnectionEventKind::Failed, None, Some("timeout".into())),
+            ]
+        );
+        let latest = ConnectionEvent::all(&pool, tunnel_id, ConnectionType::Tunnel, &from)
+            .await
+            .unwrap();
+        assert_eq!(latest[0].id, disconnected.id);
+        // Events are looked up by the connection type.
+        assert!(
+            ConnectionEvent::all(&pool, tunnel_id, ConnectionType::Location, &from)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+
+        ConnectionEvent::purge(&pool, TimeDelta::days(30))
+            .await
+            .unwrap();
+        assert_eq!(events(now - TimeDelta::days(90)).await.len(), 3);
+    }
+}
@@ -0,0 +1,66 @@
+//! Database records shared by tests.
+
+use sqlx::SqlitePool;
+
+use super::{
+    instance::Instance,
+    location::{Location, LocationMfaMode, ServiceLocationMode},
+    tunnel::Tunnel,
+    Id, NoId,
+};
+use crate::proto;
+
+/// Location named "test" of a new instance, saved in the database.
+pub(crate) async fn location(pool: &SqlitePool) -> Location<Id> {
+    let instance = Instance::from(proto::InstanceInfo::default())
+        .save(pool)
+        .await
+        .unwrap();
+    Location {
+        id: NoId,
+        instance_id: instance.id,
+        network_id: 1,
+        name: "test".into(),
+        address: String::new(),
+        pubkey: String::new(),
+        endpoint: String::new(),
+        allowed_ips: String::new(),
+        dns: None,
+        route_all_traffic: false,
+        keepalive_interval: 25,
+        location_mfa_mode: LocationMfaMode::Disabled,
+        service_location_mode: ServiceLocationMode::Disabled,
+        fwmark: None,
+        route_table: None,
+        probe_target: None,
+    }
+    .save(pool)
+    .await
+    .unwrap()
+}
+
+/// Tunnel named "test", saved in the database.
+pub(crate) async fn tunnel(pool: &SqlitePool) -> Tunnel<Id> {
+    Tunnel::new(
+        "test".into(),
+        String::new(),
+        String::new(),
+        String::new(),
+        String::new(),
+        None,
+        None,
+        String::new(),
+        None,
+        0,
+        false,
+        None,
+        None,
+        None,
+        None,
+        None,
+        None,
+    )
+    .save(pool)
+    .await
+    .unwrap()
+}
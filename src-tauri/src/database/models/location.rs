@@ -5,16 +5,15 @@ use std::str::FromStr;
 #[cfg(not(target_os = "macos"))]
 use defguard_wireguard_rs::{key::Key, net::IpAddrMask, peer::Peer, InterfaceConfiguration};
 use serde::{Deserialize, Serialize};
+#[cfg(not(target_os = "macos"))]
+use sqlx::SqliteConnection;
 use sqlx::{prelude::Type, query, query_as, query_scalar, Error as SqlxError, SqliteExecutor};
 
 #[cfg(not(target_os = "macos"))]
 use super::wireguard_keys::WireguardKeys;
 use super::{Id, NoId};
 #[cfg(not(target_os = "macos"))]
-use crate::{
-    database::DbPool,
-    utils::{DEFAULT_ROUTE_IPV4, DEFAULT_ROUTE_IPV6},
-};
+use crate::utils::{DEFAULT_ROUTE_IPV4, DEFAULT_ROUTE_IPV6};
 use crate::{
     error::Error,
     proto::{
@@ -80,6 +79,11 @@ pub struct Location<I = NoId> {
     pub keepalive_interval: i64,
     pub location_mfa_mode: LocationMfaMode,
     pub service_location_mode: ServiceLocationMode,
+    // Local policy routing settings, not provided by Defguard core.
+    pub fwmark: Option<i64>,
+    pub route_table: Option<i64>,
+    // In-tunnel target of connection quality probes, also not provided by Defguard core.
+    pub probe_target: Option<String>,
 }
 
 impl fmt::Display for Location<Id> {
@@ -110,8 +114,8 @@ impl Location<Id> {
           Self,
             "SELECT id, instance_id, name, address, pubkey, endpoint, allowed_ips, dns, network_id,\
             route_all_traffic, keepalive_interval, \
-            location_mfa_mode \"location_mfa_mode: LocationMfaMode\", service_location_mode \"service_location_mode: ServiceLocationMode\" \
-            FROM location WHERE service_location_mode <= $1 \
+            location_mfa_mode \"location_mfa_mode: LocationMfaMode\", service_location_mode \"service_location_mode: ServiceLocationMode\", \
+            fwmark, route_table, probe_target FROM location WHERE service_location_mode <= $1 \
             ORDER BY name ASC;",
             max_service_location_mode
       )
@@ -127,7 +131,8 @@ impl Location<Id> {
         query!(
             "UPDATE location SET instance_id = $1, name = $2, address = $3, pubkey = $4, \
             endpoint = $5, allowed_ips = $6, dns = $7, network_id = $8, route_all_traffic = $9, \
-            keepalive_interval = $10, location_mfa_mode = $11, service_location_mode = $12 WHERE id = $13",
+            keepalive_interval = $10, location_mfa_mode = $11, service_location_mode = $12, \
+            fwmark = $13, route_table = $14, probe_target = $15 WHERE id = $16",
             self.instance_id,
             self.name,
             self.address,
@@ -140,6 +145,9 @@ impl Location<Id> {
             self.keepalive_interval,
             self.location_mfa_mode,
             self.service_location_mode,
+            self.fwmark,
+            self.route_table,
+            self.probe_target,
             self.id,
         )
         .execute(executor)
@@ -159,8 +167,8 @@ impl Location<Id> {
             Self,
             "SELECT id \"id: _\", instance_id, name, address, pubkey, endpoint, allowed_ips, dns, \
             network_id, route_all_traffic,  keepalive_interval, \
-            location_mfa_mode \"location_mfa_mode: LocationMfaMode\", service_location_mode \"service_location_mode: ServiceLocationMode\" \
-            FROM location WHERE id = $1",
+            location_mfa_mode \"location_mfa_mode: LocationMfaMode\", service_location_mode \"service_location_mode: ServiceLocationMode\", \
+            fwmark, route_table, probe_target FROM location WHERE id = $1",
             location_id
         )
         .fetch_optional(executor)
@@ -180,8 +188,8 @@ impl Location<Id> {
         query_as!(
             Self,
             "SELECT id \"id: _\", instance_id, name, address, pubkey, endpoint, allowed_ips, dns, \
-            network_id, route_all_traffic, keepalive_interval, location_mfa_mode \"location_mfa_mode: LocationMfaMode\", service_location_mode \"service_location_mode: ServiceLocationMode\" \
-            FROM location WHERE instance_id = $1 AND service_location_mode <= $2 \
+            network_id, route_all_traffic, keepalive_interval, location_mfa_mode \"location_mfa_mode: LocationMfaMode\", service_location_mode \"service_location_mode: ServiceLocationMode\", \
+            fwmark, route_table, probe_target FROM location WHERE instance_id = $1 AND service_location_mode <= $2 \
             ORDER BY name ASC",
             instance_id,
             max_service_location_mode
@@ -200,8 +208,8 @@ impl Location<Id> {
         query_as!(
             Self,
             "SELECT id \"id: _\", instance_id, name, address, pubkey, endpoint, allowed_ips, dns, \
-            network_id, route_all_traffic, keepalive_interval, location_mfa_mode \"location_mfa_mode: LocationMfaMode\", service_location_mode \"service_location_mode: ServiceLocationMode\" \
-            FROM location WHERE pubkey = $1;",
+            network_id, route_all_traffic, keepalive_interval, location_mfa_mode \"location_mfa_mode: LocationMfaMode\", service_location_mode \"service_location_mode: ServiceLocationMode\", \
+            fwmark, route_table, probe_target FROM location WHERE pubkey = $1;",
             pubkey
         )
         .fetch_one(executor)
@@ -245,7 +253,7 @@ impl Location<Id> {
     #[cfg(not(target_os = "macos"))]
     pub(crate) async fn interface_configuration(
         &self,
-        pool: &DbPool,
+        conn: &mut SqliteConnection,
         interface_name: String,
         preshared_key: Option<String>,
         mtu: Option<u32>,
@@ -253,7 +261,8 @@ impl Location<Id> {
         use crate::database::models::instance::{ClientTrafficPolicy, Instance};
 
         debug!("Looking for WireGuard keys for location {self} instance");
-        let Some(keys) = WireguardKeys::find_by_instance_id(pool, self.instance_id).await? else {
+        let Some(keys) = WireguardKeys::find_by_instance_id(&mut *conn, self.instance_id).await?
+        else {
             error!("No keys found for instance: {}", self.instance_id);
             return Err(Error::InternalError(
                 "No keys found for instance".to_string(),
@@ -280,7 +289,7 @@ impl Location<Id> {
         }
 
         debug!("Parsing location {self} allowed IPs: {}", self.allowed_ips);
-        let Some(instance) = Instance::find_by_id(pool, self.instance_id).await? else {
+        let Some(instance) = Instance::find_by_id(&mut *conn, self.instance_id).await? else {
             error!("Instance {} not found", self.instance_id);
             return Err(Error::InternalError(format!(
                 "Instance {} not found",
@@ -339,7 +348,7 @@ impl Location<Id> {
             port: 0,
             peers: vec![peer],
             mtu,
-            fwmark: None, // TODO: add
+            fwmark: self.fwmark.and_then(|fwmark| u32::try_from(fwmark).ok()),
         };
 
         Ok(interface_config)
@@ -364,8 +373,9 @@ impl Location<NoId> {
         // Insert a new record when there is no ID
         let id = query_scalar!(
             "INSERT INTO location (instance_id, name, address, pubkey, endpoint, allowed_ips, \
-            dns, network_id, route_all_traffic, keepalive_interval, location_mfa_mode, service_location_mode) \
-            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12) \
+            dns, network_id, route_all_traffic, keepalive_interval, location_mfa_mode, service_location_mode, \
+            fwmark, route_table, probe_target) \
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15) \
             RETURNING id \"id!\"",
             self.instance_id,
             self.name,
@@ -379,6 +389,9 @@ impl Location<NoId> {
             self.keepalive_interval,
             self.location_mfa_mode,
             self.service_location_mode,
+            self.fwmark,
+            self.route_table,
+            self.probe_target,
         )
         .fetch_one(executor)
         .await?;
@@ -397,6 +410,9 @@ impl Location<NoId> {
             keepalive_interval: self.keepalive_interval,
             location_mfa_mode: self.location_mfa_mode,
             service_location_mode: self.service_location_mode,
+            fwmark: self.fwmark,
+            route_table: self.route_table,
+            probe_target: self.probe_target,
         })
     }
 }
@@ -424,6 +440,9 @@ impl From<Location<Id>> for Location {
             keepalive_interval: location.keepalive_interval,
             location_mfa_mode: location.location_mfa_mode,
             service_location_mode: location.service_location_mode,
+            fwmark: location.fwmark,
+            route_table: location.route_table,
+            probe_target: location.probe_target,
         }
     }
 }
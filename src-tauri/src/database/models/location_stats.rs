@@ -1,11 +1,11 @@
 use std::time::SystemTime;
 
-use chrono::{NaiveDateTime, Utc};
+use chrono::{NaiveDateTime, TimeDelta, Utc};
 use defguard_wireguard_rs::peer::Peer;
 use serde::{Deserialize, Serialize};
 use sqlx::{query, query_as, query_scalar, SqliteExecutor};
 
-use super::{location::Location, Id, NoId, PURGE_DURATION};
+use super::{location::Location, Id, NoId};
 use crate::{commands::DateTimeAggregation, error::Error, CommonLocationStats, ConnectionType};
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -32,6 +32,9 @@ impl From<LocationStats<Id>> for CommonLocationStats<Id> {
             listen_port: location_stats.listen_port,
             persistent_keepalive_interval: location_stats.persistent_keepalive_interval,
             connection_type: ConnectionType::Location,
+            rtt: None,
+            jitter: None,
+            loss: None,
         }
     }
 }
@@ -130,16 +133,40 @@ impl LocationStats<Id> {
         executor: E,
         location_id: Id,
         from: &NaiveDateTime,
+        to: &NaiveDateTime,
         aggregation: &DateTimeAggregation,
         limit: Option<i32>,
     ) -> Result<Vec<Self>, Error>
     where
         E: SqliteExecutor<'e>,
     {
-        let aggregation = aggregation.fstring();
         // SQLite: If the LIMIT expression evaluates to a negative value,
         // then there is no upper bound on the number of rows returned
         let query_limit = limit.unwrap_or(-1);
+        // Longer periods are read from rollups, as raw statistics may have been purged.
+        if let Some(tier) = aggregation.tier() {
+            let fstring = tier.fstring();
+            let stats = query_as!(
+                LocationStats,
+                "SELECT id, location_id, upload, download, last_handshake, \
+                bucket \"collected_at!: NaiveDateTime\", 0 \"listen_port!: u32\", \
+                NULL \"persistent_keepalive_interval?: u16\" \
+                FROM location_stats_rollup \
+                WHERE location_id = $1 AND tier = $2 AND bucket >= strftime($3, $4) \
+                AND bucket <= $5 ORDER BY bucket LIMIT $6",
+                location_id,
+                tier,
+                fstring,
+                from,
+                to,
+                query_limit
+            )
+            .fetch_all(executor)
+            .await?;
+            return Ok(stats);
+        }
+
+        let aggregation = aggregation.fstring();
         let stats = query_as!(
             LocationStats,
             "WITH cte AS (\
@@ -155,11 +182,12 @@ impl LocationStats<Id> {
            	collected_at \"collected_at!: NaiveDateTime\", \
            	listen_port \"listen_port!: u32\", \
            	persistent_keepalive_interval \"persistent_keepalive_interval?: u16\" \
-            FROM cte WHERE location_id = $2 AND collected_at >= $3 \
-            GROUP BY collected_at ORDER BY collected_at LIMIT $4",
+            FROM cte WHERE location_id = $2 AND collected_at >= $3 AND collected_at <= $4 \
+            GROUP BY collected_at ORDER BY collected_at LIMIT $5",
             aggregation,
             location_id,
             from,
+            to,
             query_limit
         )
         .fetch_all(executor)
@@ -204,14 +232,14 @@ impl LocationStats<Id> {
         Ok(res)
     }
 
-    /// Purge old statistics.
-    pub async fn purge<'e, E>(executor: E) -> Result<(), Error>
+    /// Purge statistics older than `retention`.
+    pub async fn purge<'e, E>(executor: E, retention: TimeDelta) -> Result<(), Error>
     where
         E: SqliteExecutor<'e>,
     {
         debug!("Purging location statistics.");
 
-        let past = (Utc::now() - PURGE_DURATION).naive_utc();
+        let past = (Utc::now() - retention).naive_utc();
         query!("DELETE FROM location_stats WHERE collected_at < $1", past)
             .execute(executor)
             .await?;
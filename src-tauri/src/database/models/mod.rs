@@ -1,15 +1,19 @@
 use serde::{Deserialize, Serialize};
 
 pub mod connection;
+pub mod connection_event;
+#[cfg(test)]
+pub(crate) mod fixtures;
 pub mod instance;
 pub mod location;
 pub mod location_stats;
+pub mod quality_stats;
+pub mod stats_rollup;
 pub mod tunnel;
+pub mod usage;
 pub mod wireguard_keys;
 
 // Typestate structs to make working with optional IDs easier
 pub type Id = i64;
 #[derive(Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
 pub struct NoId;
-
-const PURGE_DURATION: chrono::Duration = chrono::Duration::days(30);
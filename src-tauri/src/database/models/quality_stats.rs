@@ -0,0 +1,253 @@
+//! Connection quality of locations and tunnels, measured with probes sent through the tunnel.
+//!
+//! Results are kept as long as raw statistics, so quality of longer ranges may be incomplete.
+
+use std::time::Duration;
+
+use chrono::{NaiveDateTime, TimeDelta, Utc};
+use serde::Serialize;
+use sqlx::{query, query_as, SqliteConnection, SqliteExecutor};
+
+use super::Id;
+use crate::{error::Error, CommonLocationStats};
+
+/// Results of a round of connection quality probes.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct QualityStats {
+    /// Average round-trip time in microseconds, `None` if no probe was answered.
+    pub rtt: Option<i64>,
+    /// Average difference between consecutive round-trip times in microseconds, `None` if fewer
+    /// than two probes were answered.
+    pub jitter: Option<i64>,
+    pub sent: i64,
+    pub received: i64,
+    pub collected_at: NaiveDateTime,
+}
+
+impl QualityStats {
+    /// Summarise a round of `sent` probes, `rtts` of which were answered.
+    #[must_use]
+    pub(crate) fn new(sent: usize, rtts: &[Duration]) -> Self {
+        let micros = |duration: &Duration| i64::try_from(duration.as_micros()).unwrap_or(i64::MAX);
+        let received = rtts.len();
+        let rtt = (received > 0).then(|| rtts.iter().map(micros).sum::<i64>() / received as i64);
+        let jitter = (received > 1).then(|| {
+            rtts.windows(2)
+                .map(|pair| (micros(&pair[1]) - micros(&pair[0])).abs())
+                .sum::<i64>()
+                / (received - 1) as i64
+        });
+
+        Self {
+            rtt,
+            jitter,
+            sent: sent as i64,
+            received: received as i64,
+            collected_at: Utc::now().naive_utc(),
+        }
+    }
+
+    pub(crate) async fn save_for_location<'e, E>(
+        &self,
+        executor: E,
+        location_id: Id,
+    ) -> Result<(), Error>
+    where
+        E: SqliteExecutor<'e>,
+    {
+        query!(
+            "INSERT INTO location_quality_stats \
+            (location_id, rtt, jitter, sent, received, collected_at) \
+            VALUES ($1, $2, $3, $4, $5, $6)",
+            location_id,
+            self.rtt,
+            self.jitter,
+            self.sent,
+            self.received,
+            self.collected_at,
+        )
+        .execute(executor)
+        .await?;
+
+        Ok(())
+    }
+
+    pub(crate) async fn save_for_tunnel<'e, E>(
+        &self,
+        executor: E,
+        tunnel_id: Id,
+    ) -> Result<(), Error>
+    where
+        E: SqliteExecutor<'e>,
+    {
+        query!(
+            "INSERT INTO tunnel_quality_stats \
+            (tunnel_id, rtt, jitter, sent, received, collected_at) \
+            VALUES ($1, $2, $3, $4, $5, $6)",
+            tunnel_id,
+            self.rtt,
+            self.jitter,
+            self.sent,
+            self.received,
+            self.collected_at,
+        )
+        .execute(executor)
+        .await?;
+
+        Ok(())
+    }
+
+    pub(crate) async fn all_by_location_id<'e, E>(
+        executor: E,
+        location_id: Id,
+        from: &NaiveDateTime,
+    ) -> Result<Vec<Self>, Error>
+    where
+        E: SqliteExecutor<'e>,
+    {
+        let stats = query_as!(
+            Self,
+            "SELECT rtt, jitter, sent, received, collected_at \
+            FROM location_quality_stats WHERE location_id = $1 AND collected_at >= $2 \
+            ORDER BY collected_at",
+            location_id,
+            from
+        )
+        .fetch_all(executor)
+        .await?;
+        Ok(stats)
+    }
+
+    pub(crate) async fn all_by_tunnel_id<'e, E>(
+        executor: E,
+        tunnel_id: Id,
+        from: &NaiveDateTime,
+    ) -> Result<Vec<Self>, Error>
+    where
+        E: SqliteExecutor<'e>,
+    {
+        let stats = query_as!(
+            Self,
+            "SELECT rtt, jitter, sent, received, collected_at \
+            FROM tunnel_quality_stats WHERE tunnel_id = $1 AND collected_at >= $2 \
+            ORDER BY collected_at",
+            tunnel_id,
+            from
+        )
+        .fetch_all(executor)
+        .await?;
+        Ok(stats)
+    }
+
+    /// Purge connection quality of locations and tunnels older than `retention`.
+    pub async fn purge(conn: &mut SqliteConnection, retention: TimeDelta) -> Result<(), Error> {
+        debug!("Purging connection quality statistics.");
+
+        let past = (Utc::now() - retention).naive_utc();
+        query!(
+            "DELETE FROM location_quality_stats WHERE collected_at < $1",
+            past
+        )
+        .execute(&mut *conn)
+        .await?;
+        query!(
+            "DELETE FROM tunnel_quality_stats WHERE collected_at < $1",
+            past
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(())
+    }
+}
+
+/// Fill in connection quality of `stats`, ordered by time of collection. Each of them gets the
+/// results of probes from its own time of collection until the next one's.
+pub(crate) fn merge_quality(stats: &mut [CommonLocationStats<Id>], quality: &[QualityStats]) {
+    let mut quality = quality.iter().peekable();
+    for index in 0..stats.len() {
+        let start = stats[index].collected_at;
+        let end = stats.get(index + 1).map(|next| next.collected_at);
+        let (mut sent, mut received, mut rtt_sum) = (0, 0, 0);
+        let (mut jitters, mut jitter_sum) = (0_u32, 0);
+        while let Some(round) =
+            quality.next_if(|round| end.is_none_or(|end| round.collected_at < end))
+        {
+            if round.collected_at < start {
+                continue;
+            }
+            sent += round.sent;
+            received += round.received;
+            if let Some(rtt) = round.rtt {
+                rtt_sum += rtt * round.received;
+            }
+            if let Some(jitter) = round.jitter {
+                jitters += 1;
+                jitter_sum += jitter;
+            }
+        }
+
+        let stats = &mut stats[index];
+        // Round-trip times are in milliseconds and loss is a fraction.
+        stats.rtt = (received > 0).then(|| rtt_sum as f64 / received as f64 / 1000.0);
+        stats.jitter = (jitters > 0).then(|| jitter_sum as f64 / f64::from(jitters) / 1000.0);
+        stats.loss = (sent > 0).then(|| 1.0 - received as f64 / sent as f64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ConnectionType;
+
+    #[test]
+    fn merge_quality_into_stats() {
+        let start = "2025-01-01T10:00:00".parse::<NaiveDateTime>().unwrap();
+        let mut stats: Vec<_> = [0, 10, 20]
+            .into_iter()
+            .map(|offset| CommonLocationStats {
+                id: 1,
+                location_id: 1,
+                upload: 0,
+                download: 0,
+                last_handshake: 0,
+                collected_at: start + TimeDelta::seconds(offset),
+                listen_port: 0,
+                persistent_keepalive_interval: None,
+                connection_type: ConnectionType::Location,
+                rtt: None,
+                jitter: None,
+                loss: None,
+            })
+            .collect();
+
+        let mut quality = QualityStats::new(
+            4,
+            &[
+                Duration::from_millis(10),
+                Duration::from_millis(14),
+                Duration::from_millis(12),
+            ],
+        );
+        assert_eq!((quality.rtt, quality.jitter), (Some(12_000), Some(3_000)));
+        quality.collected_at = start + TimeDelta::seconds(5);
+        let mut lost = QualityStats::new(4, &[]);
+        lost.collected_at = start + TimeDelta::seconds(8);
+        let mut late = QualityStats::new(1, &[Duration::from_millis(20)]);
+        late.collected_at = start + TimeDelta::seconds(25);
+
+        merge_quality(&mut stats, &[quality, lost, late]);
+        let quality: Vec<_> = stats
+            .iter()
+            .map(|stats| (stats.rtt, stats.jitter, stats.loss))
+            .collect();
+        assert_eq!(
+            quality,
+            [
+                (Some(12.0), Some(3.0), Some(0.625)),
+                (None, None, None),
+                (Some(20.0), None, Some(0.0)),
+            ]
+        );
+    }
+}
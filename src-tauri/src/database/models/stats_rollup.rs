@@ -0,0 +1,371 @@
+//! Rollups of location and tunnel statistics.
+//!
+//! Raw statistics are compacted into per-minute aggregates, per-minute aggregates into per-hour
+//! ones, and per-hour aggregates into per-day ones. Each tier is kept for its own retention
+//! period, so traffic over long ranges remains available after raw statistics are purged.
+
+use chrono::{NaiveDateTime, TimeDelta, Utc};
+use sqlx::{query, query_scalar, SqliteConnection, Type};
+
+use super::{
+    connection_event::ConnectionEvent, location_stats::LocationStats, quality_stats::QualityStats,
+    tunnel::TunnelStats,
+};
+use crate::error::Error;
+
+// In seconds. Consecutive samples further apart don't count as uptime, as the connection was
+// most likely down in between.
+const MAX_SAMPLE_GAP: i64 = 60;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Type)]
+#[repr(u32)]
+pub enum RollupTier {
+    Minute = 1,
+    Hour = 2,
+    Day = 3,
+}
+
+impl RollupTier {
+    /// Tiers in order of compaction.
+    const ALL: [Self; 3] = [Self::Minute, Self::Hour, Self::Day];
+
+    /// Returns database format string of buckets of this tier.
+    #[must_use]
+    pub(crate) fn fstring(self) -> &'static str {
+        match self {
+            Self::Minute => "%Y-%m-%d %H:%M:00",
+            Self::Hour => "%Y-%m-%d %H:00:00",
+            Self::Day => "%Y-%m-%d 00:00:00",
+        }
+    }
+
+    /// Tier which is compacted into this one, or `None` for raw statistics.
+    fn source(self) -> Option<Self> {
+        match self {
+            Self::Minute => None,
+            Self::Hour => Some(Self::Minute),
+            Self::Day => Some(Self::Hour),
+        }
+    }
+}
+
+/// How long statistics of each tier are kept.
+#[derive(Debug)]
+pub struct StatsRetention {
+    pub raw: TimeDelta,
+    pub minute: TimeDelta,
+    pub hour: TimeDelta,
+    pub day: TimeDelta,
+}
+
+impl StatsRetention {
+    fn tier(&self, tier: RollupTier) -> TimeDelta {
+        match tier {
+            RollupTier::Minute => self.minute,
+            RollupTier::Hour => self.hour,
+            RollupTier::Day => self.day,
+        }
+    }
+}
+
+/// Compact new location and tunnel statistics into all rollup tiers.
+///
+/// Buckets starting with the latest one of each tier are recomputed, so partial buckets get
+/// completed by subsequent runs. Statistics must be rolled up before they're purged.
+pub async fn rollup_stats(conn: &mut SqliteConnection) -> Result<(), Error> {
+    debug!("Rolling up location and tunnel statistics.");
+    for tier in RollupTier::ALL {
+        rollup_location_stats(conn, tier).await?;
+        rollup_tunnel_stats(conn, tier).await?;
+    }
+
+    Ok(())
+}
+
+async fn rollup_location_stats(conn: &mut SqliteConnection, tier: RollupTier) -> Result<(), Error> {
+    let since = query_scalar!(
+        "SELECT MAX(bucket) \"bucket?: NaiveDateTime\" FROM location_stats_rollup WHERE tier = $1",
+        tier
+    )
+    .fetch_one(&mut *conn)
+    .await?
+    .unwrap_or_default();
+    let fstring = tier.fstring();
+    if let Some(source) = tier.source() {
+        query!(
+            "INSERT INTO location_stats_rollup \
+            (location_id, tier, bucket, upload, download, last_handshake, handshakes, uptime) \
+            SELECT location_id, $1, strftime($2, bucket) rollup_bucket, SUM(upload), \
+            SUM(download), MAX(last_handshake), SUM(handshakes), SUM(uptime) \
+            FROM location_stats_rollup WHERE tier = $3 AND bucket >= $4 \
+            GROUP BY location_id, rollup_bucket \
+            ON CONFLICT (location_id, tier, bucket) DO UPDATE SET upload = excluded.upload, \
+            download = excluded.download, last_handshake = excluded.last_handshake, \
+            handshakes = excluded.handshakes, uptime = excluded.uptime",
+            tier,
+            fstring,
+            source,
+            since,
+        )
+        .execute(&mut *conn)
+        .await?;
+    } else {
+        // Include the sample preceding the buckets, however old, so deltas can be computed for
+        // all samples in the buckets. Gaps between samples only limit uptime. Counters which went
+        // down have been reset, e.g. by recreating the interface, so they count from zero.
+        query!(
+            "INSERT INTO location_stats_rollup \
+            (location_id, tier, bucket, upload, download, last_handshake, handshakes, uptime) \
+            SELECT location_id, $1, bucket, SUM(upload), SUM(download), \
+            MAX(last_handshake), SUM(handshake), SUM(CASE WHEN gap <= $2 THEN gap ELSE 0 END) \
+            FROM (SELECT location_id, strftime($3, collected_at) bucket, \
+            COALESCE(CASE WHEN upload < LAG(upload) OVER w THEN upload \
+            ELSE upload - LAG(upload) OVER w END, 0) upload, \
+            COALESCE(CASE WHEN download < LAG(download) OVER w THEN download \
+            ELSE download - LAG(download) OVER w END, 0) download, \
+            last_handshake, \
+            last_handshake != 0 AND last_handshake IS NOT LAG(last_handshake) OVER w handshake, \
+            strftime('%s', collected_at) - strftime('%s', LAG(collected_at) OVER w) gap \
+            FROM location_stats s WHERE collected_at >= COALESCE((SELECT MAX(collected_at) \
+            FROM location_stats p WHERE p.location_id = s.location_id AND p.collected_at < $4), $4) \
+            WINDOW w AS (PARTITION BY location_id ORDER BY collected_at)) \
+            WHERE bucket >= $4 \
+            GROUP BY location_id, bucket \
+            ON CONFLICT (location_id, tier, bucket) DO UPDATE SET upload = excluded.upload, \
+            download = excluded.download, last_handshake = excluded.last_handshake, \
+            handshakes = excluded.handshakes, uptime = excluded.uptime",
+            tier,
+            MAX_SAMPLE_GAP,
+            fstring,
+            since,
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    Ok(())
+}
+
+async fn rollup_tunnel_stats(conn: &mut SqliteConnection, tier: RollupTier) -> Result<(), Error> {
+    let since = query_scalar!(
+        "SELECT MAX(bucket) \"bucket?: NaiveDateTime\" FROM tunnel_stats_rollup WHERE tier = $1",
+        tier
+    )
+    .fetch_one(&mut *conn)
+    .await?
+    .unwrap_or_default();
+    let fstring = tier.fstring();
+    if let Some(source) = tier.source() {
+        query!(
+            "INSERT INTO tunnel_stats_rollup \
+            (tunnel_id, tier, bucket, upload, download, last_handshake, handshakes, uptime) \
+            SELECT tunnel_id, $1, strftime($2, bucket) rollup_bucket, SUM(upload), \
+            SUM(download), MAX(last_handshake), SUM(handshakes), SUM(uptime) \
+            FROM tunnel_stats_rollup WHERE tier = $3 AND bucket >= $4 \
+            GROUP BY tunnel_id, rollup_bucket \
+            ON CONFLICT (tunnel_id, tier, bucket) DO UPDATE SET upload = excluded.upload, \
+            download = excluded.download, last_handshake = excluded.last_handshake, \
+            handshakes = excluded.handshakes, uptime = excluded.uptime",
+            tier,
+            fstring,
+            source,
+            since,
+        )
+        .execute(&mut *conn)
+        .await?;
+    } else {
+        // Include the sample preceding the buckets, however old, so deltas can be computed for
+        // all samples in the buckets. Gaps between samples only limit uptime. Counters which went
+        // down have been reset, e.g. by recreating the interface, so they count from zero.
+        query!(
+            "INSERT INTO tunnel_stats_rollup \
+            (tunnel_id, tier, bucket, upload, download, last_handshake, handshakes, uptime) \
+            SELECT tunnel_id, $1, bucket, SUM(upload), SUM(download), \
+            MAX(last_handshake), SUM(handshake), SUM(CASE WHEN gap <= $2 THEN gap ELSE 0 END) \
+            FROM (SELECT tunnel_id, strftime($3, collected_at) bucket, \
+            COALESCE(CASE WHEN upload < LAG(upload) OVER w THEN upload \
+            ELSE upload - LAG(upload) OVER w END, 0) upload, \
+            COALESCE(CASE WHEN download < LAG(download) OVER w THEN download \
+            ELSE download - LAG(download) OVER w END, 0) download, \
+            last_handshake, \
+            last_handshake != 0 AND last_handshake IS NOT LAG(last_handshake) OVER w handshake, \
+            strftime('%s', collected_at) - strftime('%s', LAG(collected_at) OVER w) gap \
+            FROM tunnel_stats s WHERE collected_at >= COALESCE((SELECT MAX(collected_at) \
+            FROM tunnel_stats p WHERE p.tunnel_id = s.tunnel_id AND p.collected_at < $4), $4) \
+            WINDOW w AS (PARTITION BY tunnel_id ORDER BY collected_at)) \
+            WHERE bucket >= $4 \
+            GROUP BY tunnel_id, bucket \
+            ON CONFLICT (tunnel_id, tier, bucket) DO UPDATE SET upload = excluded.upload, \
+            download = excluded.download, last_handshake = excluded.last_handshake, \
+            handshakes = excluded.handshakes, uptime = excluded.uptime",
+            tier,
+            MAX_SAMPLE_GAP,
+            fstring,
+            since,
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Purge raw statistics, connection quality and rollups older than their retention. Connection
+/// events are kept as long as raw statistics.
+pub async fn purge_stats(
+    conn: &mut SqliteConnection,
+    retention: &StatsRetention,
+) -> Result<(), Error> {
+    LocationStats::purge(&mut *conn, retention.raw).await?;
+    TunnelStats::purge(&mut *conn, retention.raw).await?;
+    QualityStats::purge(&mut *conn, retention.raw).await?;
+    ConnectionEvent::purge(&mut *conn, retention.raw).await?;
+    for tier in RollupTier::ALL {
+        purge_rollups(&mut *conn, tier, retention.tier(tier)).await?;
+    }
+
+    Ok(())
+}
+
+async fn purge_rollups(
+    conn: &mut SqliteConnection,
+    tier: RollupTier,
+    retention: TimeDelta,
+) -> Result<(), Error> {
+    debug!("Purging {tier:?} statistics rollups.");
+
+    let past = (Utc::now() - retention).naive_utc();
+    query!(
+        "DELETE FROM location_stats_rollup WHERE tier = $1 AND bucket < $2",
+        tier,
+        past
+    )
+    .execute(&mut *conn)
+    .await?;
+    query!(
+        "DELETE FROM tunnel_stats_rollup WHERE tier = $1 AND bucket < $2",
+        tier,
+        past
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::{query_as, SqlitePool};
+
+    use super::*;
+    use crate::database::models::fixtures;
+
+    async fn rollups(pool: &SqlitePool, tier: RollupTier) -> Vec<(NaiveDateTime, i64, i64, i64)> {
+        query_as(
+            "SELECT bucket, upload, handshakes, uptime FROM tunnel_stats_rollup \
+            WHERE tier = $1 ORDER BY bucket",
+        )
+        .bind(tier)
+        .fetch_all(pool)
+        .await
+        .unwrap()
+    }
+
+    async fn location_rollups(
+        pool: &SqlitePool,
+        tier: RollupTier,
+    ) -> Vec<(NaiveDateTime, i64, i64, i64)> {
+        query_as(
+            "SELECT bucket, upload, handshakes, uptime FROM location_stats_rollup \
+            WHERE tier = $1 ORDER BY bucket",
+        )
+        .bind(tier)
+        .fetch_all(pool)
+        .await
+        .unwrap()
+    }
+
+    #[sqlx::test]
+    async fn rollup_tunnel_stats(pool: SqlitePool) {
+        let tunnel = fixtures::tunnel(&pool).await;
+
+        // Counters are reset by the last sample.
+        let minute = "2025-01-01T10:00:00".parse::<NaiveDateTime>().unwrap();
+        for (offset, upload) in [(10, 0), (20, 100), (30, 300), (70, 400), (80, 50)] {
+            TunnelStats::new(
+                tunnel.id,
+                upload,
+                0,
+                1,
+                minute + TimeDelta::seconds(offset),
+                0,
+                0,
+            )
+            .save(&pool)
+            .await
+            .unwrap();
+        }
+
+        // Rollups are recomputed, not accumulated.
+        for _ in 0..2 {
+            let mut conn = pool.acquire().await.unwrap();
+            rollup_stats(&mut conn).await.unwrap();
+        }
+
+        assert_eq!(
+            rollups(&pool, RollupTier::Minute).await,
+            [
+                (minute, 300, 1, 20),
+                (minute + TimeDelta::minutes(1), 150, 0, 50)
+            ]
+        );
+        let hour = minute;
+        assert_eq!(rollups(&pool, RollupTier::Hour).await, [(hour, 450, 1, 70)]);
+        let day = "2025-01-01T00:00:00".parse::<NaiveDateTime>().unwrap();
+        assert_eq!(rollups(&pool, RollupTier::Day).await, [(day, 450, 1, 70)]);
+    }
+
+    #[sqlx::test]
+    async fn rollup_location_stats(pool: SqlitePool) {
+        let location = fixtures::location(&pool).await;
+        let save_sample = |upload, collected_at| {
+            let mut stats = LocationStats::new(location.id, upload, 0, 1, 0, None);
+            stats.collected_at = collected_at;
+            stats.save(&pool)
+        };
+
+        // The first sample of a minute follows a longer gap.
+        let minute = "2025-01-01T10:00:00".parse::<NaiveDateTime>().unwrap();
+        save_sample(0, minute - TimeDelta::seconds(90))
+            .await
+            .unwrap();
+        save_sample(100, minute + TimeDelta::seconds(10))
+            .await
+            .unwrap();
+        let mut conn = pool.acquire().await.unwrap();
+        rollup_stats(&mut conn).await.unwrap();
+
+        // Recomputing the minute still counts traffic since the preceding sample.
+        save_sample(150, minute + TimeDelta::seconds(20))
+            .await
+            .unwrap();
+        save_sample(400, minute + TimeDelta::minutes(5))
+            .await
+            .unwrap();
+        rollup_stats(&mut conn).await.unwrap();
+
+        assert_eq!(
+            location_rollups(&pool, RollupTier::Minute).await,
+            [
+                (minute - TimeDelta::minutes(2), 0, 1, 0),
+                (minute, 150, 0, 10),
+                (minute + TimeDelta::minutes(5), 250, 0, 0)
+            ]
+        );
+        let hour = "2025-01-01T09:00:00".parse::<NaiveDateTime>().unwrap();
+        assert_eq!(
+            location_rollups(&pool, RollupTier::Hour).await,
+            [(hour, 0, 1, 0), (minute, 400, 0, 10)]
+        );
+    }
+}
@@ -1,12 +1,15 @@
 use std::{fmt, time::SystemTime};
 
-use chrono::{NaiveDateTime, Utc};
+use chrono::{NaiveDateTime, TimeDelta, Utc};
 use defguard_wireguard_rs::peer::Peer;
 use serde::{Deserialize, Serialize};
 use serde_with::{serde_as, NoneAsEmptyString};
 use sqlx::{query, query_as, query_scalar, Error as SqlxError, SqliteExecutor};
 
-use super::{connection::ActiveConnection, Id, NoId, PURGE_DURATION};
+use super::{
+    connection::{ActiveConnection, DisconnectReason},
+    Id, NoId,
+};
 use crate::{
     commands::DateTimeAggregation, error::Error, CommonConnection, CommonConnectionInfo,
     CommonLocationStats, ConnectionType,
@@ -42,6 +45,14 @@ pub struct Tunnel<I = NoId> {
     pub pre_down: Option<String>,
     #[serde_as(as = "NoneAsEmptyString")]
     pub post_down: Option<String>,
+    // policy routing
+    #[serde(default)]
+    pub fwmark: Option<i64>,
+    #[serde(default)]
+    pub route_table: Option<i64>,
+    // in-tunnel target of connection quality probes
+    #[serde(default)]
+    pub probe_target: Option<String>,
 }
 
 impl fmt::Display for Tunnel<Id> {
@@ -65,8 +76,8 @@ impl Tunnel<Id> {
             "UPDATE tunnel SET name = $1, pubkey = $2, prvkey = $3, address = $4, \
             server_pubkey = $5, preshared_key = $6, allowed_ips = $7, endpoint = $8, dns = $9, \
             persistent_keep_alive = $10, route_all_traffic = $11, pre_up = $12, post_up = $13, \
-            pre_down = $14, post_down = $15 \
-            WHERE id = $16;",
+            pre_down = $14, post_down = $15, fwmark = $16, route_table = $17, \
+            probe_target = $18 WHERE id = $19;",
             self.name,
             self.pubkey,
             self.prvkey,
@@ -82,6 +93,9 @@ impl Tunnel<Id> {
             self.post_up,
             self.pre_down,
             self.post_down,
+            self.fwmark,
+            self.route_table,
+            self.probe_target,
             self.id,
         )
         .execute(executor)
@@ -109,7 +123,7 @@ impl Tunnel<Id> {
             Self,
             "SELECT id \"id: _\", name, pubkey, prvkey, address, server_pubkey, preshared_key, \
             allowed_ips, endpoint, dns, persistent_keep_alive, route_all_traffic, pre_up, \
-            post_up, pre_down, post_down FROM tunnel WHERE id = $1;",
+            post_up, pre_down, post_down, fwmark, route_table, probe_target FROM tunnel WHERE id = $1;",
             tunnel_id
         )
         .fetch_optional(executor)
@@ -124,7 +138,7 @@ impl Tunnel<Id> {
             Self,
             "SELECT id \"id: _\", name, pubkey, prvkey, address, server_pubkey, preshared_key, \
             allowed_ips, endpoint, dns, persistent_keep_alive, route_all_traffic, pre_up, \
-            post_up, pre_down, post_down \
+            post_up, pre_down, post_down, fwmark, route_table, probe_target \
             FROM tunnel ORDER BY name ASC;"
         )
         .fetch_all(executor)
@@ -143,7 +157,7 @@ impl Tunnel<Id> {
             Self,
             "SELECT id \"id: _\", name, pubkey, prvkey, address, server_pubkey, preshared_key, \
             allowed_ips, endpoint, dns, persistent_keep_alive, route_all_traffic, pre_up, \
-            post_up, pre_down, post_down \
+            post_up, pre_down, post_down, fwmark, route_table, probe_target \
             FROM tunnel WHERE server_pubkey = $1;",
             pubkey
         )
@@ -182,6 +196,8 @@ impl Tunnel<NoId> {
         post_up: Option<String>,
         pre_down: Option<String>,
         post_down: Option<String>,
+        fwmark: Option<i64>,
+        route_table: Option<i64>,
     ) -> Self {
         Tunnel {
             id: NoId,
@@ -200,6 +216,9 @@ impl Tunnel<NoId> {
             post_up,
             pre_down,
             post_down,
+            fwmark,
+            route_table,
+            probe_target: None,
         }
     }
 
@@ -210,8 +229,9 @@ impl Tunnel<NoId> {
         // Insert a new record when there is no ID
         let result = query!(
             "INSERT INTO tunnel (name, pubkey, prvkey, address, server_pubkey, allowed_ips, preshared_key, \
-            endpoint, dns, persistent_keep_alive, route_all_traffic, pre_up, post_up, pre_down, post_down) \
-            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15) RETURNING id;",
+            endpoint, dns, persistent_keep_alive, route_all_traffic, pre_up, post_up, pre_down, post_down, \
+            fwmark, route_table, probe_target) \
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18) RETURNING id;",
             self.name,
             self.pubkey,
             self.prvkey,
@@ -227,6 +247,9 @@ impl Tunnel<NoId> {
             self.post_up,
             self.pre_down,
             self.post_down,
+            self.fwmark,
+            self.route_table,
+            self.probe_target,
         )
         .fetch_one(executor)
         .await?;
@@ -248,6 +271,9 @@ impl Tunnel<NoId> {
             post_up: self.post_up,
             pre_down: self.pre_down,
             post_down: self.post_down,
+            fwmark: self.fwmark,
+            route_table: self.route_table,
+            probe_target: self.probe_target,
         })
     }
 }
@@ -335,11 +361,34 @@ impl TunnelStats<Id> {
         executor: E,
         tunnel_id: Id,
         from: &NaiveDateTime,
+        to: &NaiveDateTime,
         aggregation: &DateTimeAggregation,
     ) -> Result<Vec<Self>, SqlxError>
     where
         E: SqliteExecutor<'e>,
     {
+        // Longer periods are read from rollups, as raw statistics may have been purged.
+        if let Some(tier) = aggregation.tier() {
+            let fstring = tier.fstring();
+            let stats = query_as!(
+                TunnelStats,
+                "SELECT id, tunnel_id, upload, download, last_handshake, \
+                bucket \"collected_at!: NaiveDateTime\", 0 \"listen_port!: u32\", \
+                0 \"persistent_keepalive_interval!: u16\" \
+                FROM tunnel_stats_rollup \
+                WHERE tunnel_id = $1 AND tier = $2 AND bucket >= strftime($3, $4) \
+                AND bucket <= $5 ORDER BY bucket",
+                tunnel_id,
+                tier,
+                fstring,
+                from,
+                to
+            )
+            .fetch_all(executor)
+            .await?;
+            return Ok(stats);
+        }
+
         let aggregation = aggregation.fstring();
         let stats = query_as!(
             TunnelStats,
@@ -355,11 +404,12 @@ impl TunnelStats<Id> {
             last_handshake, collected_at \"collected_at!: NaiveDateTime\", \
             listen_port \"listen_port!: u32\", \
             persistent_keepalive_interval \"persistent_keepalive_interval!: u16\" \
-            FROM cte WHERE tunnel_id = $2 AND collected_at >= $3 \
+            FROM cte WHERE tunnel_id = $2 AND collected_at >= $3 AND collected_at <= $4 \
             GROUP BY collected_at ORDER BY collected_at",
             aggregation,
             tunnel_id,
-            from
+            from,
+            to
         )
         .fetch_all(executor)
         .await?;
@@ -403,14 +453,14 @@ impl TunnelStats<Id> {
         Ok(res)
     }
 
-    /// Purge old statistics.
-    pub async fn purge<'e, E>(executor: E) -> Result<(), Error>
+    /// Purge statistics older than `retention`.
+    pub async fn purge<'e, E>(executor: E, retention: TimeDelta) -> Result<(), Error>
     where
         E: SqliteExecutor<'e>,
     {
         debug!("Purging tunnel statistics.");
 
-        let past = (Utc::now() - PURGE_DURATION).naive_utc();
+        let past = (Utc::now() - retention).naive_utc();
         query!("DELETE FROM tunnel_stats WHERE collected_at < $1", past)
             .execute(executor)
             .await?;
@@ -449,6 +499,7 @@ pub struct TunnelConnection<I = NoId> {
     pub tunnel_id: Id,
     pub start: NaiveDateTime,
     pub end: NaiveDateTime,
+    pub reason: Option<DisconnectReason>,
 }
 
 impl From<TunnelConnectionInfo> for CommonConnectionInfo {
@@ -460,6 +511,7 @@ impl From<TunnelConnectionInfo> for CommonConnectionInfo {
             end: val.end,
             upload: val.upload,
             download: val.download,
+            reason: val.reason,
         }
     }
 }
@@ -474,7 +526,7 @@ impl TunnelConnection<Id> {
     {
         let connections = query_as!(
             TunnelConnection,
-            "SELECT id, tunnel_id, start, end \
+            "SELECT id, tunnel_id, start, end, reason \"reason: DisconnectReason\" \
             FROM tunnel_connection WHERE tunnel_id = $1",
             tunnel_id
         )
@@ -492,7 +544,7 @@ impl TunnelConnection<Id> {
     {
         let connection = query_as!(
             TunnelConnection,
-            "SELECT id, tunnel_id, start, end \
+            "SELECT id, tunnel_id, start, end, reason \"reason: DisconnectReason\" \
             FROM tunnel_connection WHERE tunnel_id = $1 \
             ORDER BY end DESC LIMIT 1",
             tunnel_id
@@ -509,11 +561,12 @@ impl TunnelConnection<NoId> {
         E: SqliteExecutor<'e>,
     {
         let id = query_scalar!(
-            "INSERT INTO tunnel_connection (tunnel_id, start, end) \
-            VALUES ($1, $2, $3) RETURNING id \"id!\"",
+            "INSERT INTO tunnel_connection (tunnel_id, start, end, reason) \
+            VALUES ($1, $2, $3, $4) RETURNING id \"id!\"",
             self.tunnel_id,
             self.start,
             self.end,
+            self.reason,
         )
         .fetch_one(executor)
         .await?;
@@ -523,6 +576,7 @@ impl TunnelConnection<NoId> {
             tunnel_id: self.tunnel_id,
             start: self.start,
             end: self.end,
+            reason: self.reason,
         })
     }
 }
@@ -536,6 +590,7 @@ pub struct TunnelConnectionInfo {
     pub end: NaiveDateTime,
     pub upload: Option<i32>,
     pub download: Option<i32>,
+    pub reason: Option<DisconnectReason>,
 }
 
 impl TunnelConnectionInfo {
@@ -548,7 +603,7 @@ impl TunnelConnectionInfo {
         // FIXME: Optimize query
         let connections = query_as!(
             TunnelConnectionInfo,
-            "SELECT c.id, c.tunnel_id, c.start, c.end, \
+            "SELECT c.id, c.tunnel_id, c.start, c.end, c.reason \"reason: DisconnectReason\", \
             COALESCE((\
                 SELECT ls.upload \
                 FROM tunnel_stats ls \
@@ -581,6 +636,7 @@ impl From<&ActiveConnection> for TunnelConnection<NoId> {
             tunnel_id: active_connection.location_id,
             start: active_connection.start,
             end: Utc::now().naive_utc(),
+            reason: None,
         }
     }
 }
@@ -593,6 +649,7 @@ impl From<TunnelConnection<Id>> for CommonConnection<Id> {
             start: tunnel_connection.start,
             end: tunnel_connection.end,
             connection_type: ConnectionType::Tunnel, // You need to set the connection_type appropriately based on your logic,
+            reason: tunnel_connection.reason,
         }
     }
 }
@@ -609,6 +666,9 @@ impl From<TunnelStats<Id>> for CommonLocationStats<Id> {
             listen_port: tunnel_stats.listen_port,
             persistent_keepalive_interval: Some(tunnel_stats.persistent_keepalive_interval), // Set the appropriate value
             connection_type: ConnectionType::Tunnel,
+            rtt: None,
+            jitter: None,
+            loss: None,
         }
     }
 }
@@ -619,6 +679,7 @@ mod tests {
     use sqlx::SqlitePool;
 
     use super::*;
+    use crate::database::models::fixtures;
 
     impl TunnelStats<Id> {
         async fn count<'e, E>(executor: E) -> Result<i64, Error>
@@ -634,29 +695,11 @@ mod tests {
 
     #[sqlx::test]
     async fn purge_stats(pool: SqlitePool) {
-        let tunnel = Tunnel::new(
-            "test".into(),
-            String::new(),
-            String::new(),
-            String::new(),
-            String::new(),
-            None,
-            None,
-            String::new(),
-            None,
-            0,
-            false,
-            None,
-            None,
-            None,
-            None,
-        )
-        .save(&pool)
-        .await
-        .unwrap();
+        let tunnel = fixtures::tunnel(&pool).await;
 
+        let retention = Duration::days(30);
         let delta = Duration::days(60);
-        assert!(delta > PURGE_DURATION);
+        assert!(delta > retention);
 
         let now = Utc::now();
         TunnelStats::new(tunnel.id, 0, 0, 0, now.naive_utc(), 0, 0)
@@ -675,7 +718,7 @@ mod tests {
         let count = TunnelStats::<Id>::count(&pool).await.unwrap();
         assert_eq!(count, 3);
 
-        TunnelStats::purge(&pool).await.unwrap();
+        TunnelStats::purge(&pool, retention).await.unwrap();
 
         let count = TunnelStats::<Id>::count(&pool).await.unwrap();
         assert_eq!(count, 2);
@@ -0,0 +1,376 @@
+//! Traffic totals of locations and tunnels, and data caps of locations.
+//!
+//! Totals are computed from daily statistics rollups, so days and months are in UTC.
+
+use chrono::{NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{query, query_as, query_scalar, SqliteExecutor, Type};
+
+use super::{stats_rollup::RollupTier, Id, NoId};
+use crate::error::Error;
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize, Type)]
+#[repr(u32)]
+#[serde(rename_all = "lowercase")]
+pub enum UsagePeriod {
+    Day = 1,
+    Month = 2,
+}
+
+impl UsagePeriod {
+    /// Returns database format string of the beginning of the period.
+    #[must_use]
+    pub(crate) fn fstring(self) -> &'static str {
+        match self {
+            Self::Day => "%Y-%m-%d 00:00:00",
+            Self::Month => "%Y-%m-01 00:00:00",
+        }
+    }
+}
+
+/// Traffic of a location or tunnel in a period.
+#[derive(Debug, Serialize)]
+pub struct UsageTotal {
+    pub period_start: NaiveDateTime,
+    pub upload: i64,
+    pub download: i64,
+}
+
+impl UsageTotal {
+    /// Totals of location in periods since the one containing `from`.
+    pub(crate) async fn all_by_location_id<'e, E>(
+        executor: E,
+        location_id: Id,
+        period: UsagePeriod,
+        from: &NaiveDateTime,
+    ) -> Result<Vec<Self>, Error>
+    where
+        E: SqliteExecutor<'e>,
+    {
+        let fstring = period.fstring();
+        let totals = query_as!(
+            Self,
+            "SELECT strftime($1, bucket) \"period_start!: NaiveDateTime\", \
+            SUM(upload) \"upload!: i64\", SUM(download) \"download!: i64\" \
+            FROM location_stats_rollup \
+            WHERE location_id = $2 AND tier = $3 AND bucket >= strftime($1, $4) \
+            GROUP BY 1 ORDER BY 1",
+            fstring,
+            location_id,
+            RollupTier::Day,
+            from
+        )
+        .fetch_all(executor)
+        .await?;
+        Ok(totals)
+    }
+
+    /// Totals of tunnel in periods since the one containing `from`.
+    pub(crate) async fn all_by_tunnel_id<'e, E>(
+        executor: E,
+        tunnel_id: Id,
+        period: UsagePeriod,
+        from: &NaiveDateTime,
+    ) -> Result<Vec<Self>, Error>
+    where
+        E: SqliteExecutor<'e>,
+    {
+        let fstring = period.fstring();
+        let totals = query_as!(
+            Self,
+            "SELECT strftime($1, bucket) \"period_start!: NaiveDateTime\", \
+            SUM(upload) \"upload!: i64\", SUM(download) \"download!: i64\" \
+            FROM tunnel_stats_rollup \
+            WHERE tunnel_id = $2 AND tier = $3 AND bucket >= strftime($1, $4) \
+            GROUP BY 1 ORDER BY 1",
+            fstring,
+            tunnel_id,
+            RollupTier::Day,
+            from
+        )
+        .fetch_all(executor)
+        .await?;
+        Ok(totals)
+    }
+
+    /// Total of location in the current period, if there was any traffic.
+    pub(crate) async fn current_by_location_id<'e, E>(
+        executor: E,
+        location_id: Id,
+        period: UsagePeriod,
+    ) -> Result<Option<Self>, Error>
+    where
+        E: SqliteExecutor<'e>,
+    {
+        let now = Utc::now().naive_utc();
+        Ok(
+            Self::all_by_location_id(executor, location_id, period, &now)
+                .await?
+                .pop(),
+        )
+    }
+}
+
+// Percentages of data caps to alert about, in descending order.
+const ALERT_LEVELS: [i64; 2] = [100, 80];
+
+/// Data cap of a location.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct DataCap<I = NoId> {
+    pub id: I,
+    pub location_id: Id,
+    /// In bytes, upload and download combined.
+    pub cap: i64,
+    pub period: UsagePeriod,
+    /// Keep the location disconnected while the cap is reached.
+    pub disconnect: bool,
+    // Start of the period of the latest alert.
+    #[serde(skip)]
+    pub(crate) alert_period: Option<NaiveDateTime>,
+    // Percentage of the cap the latest alert was about.
+    #[serde(skip)]
+    pub(crate) alert_level: i64,
+}
+
+impl DataCap<NoId> {
+    /// Set data cap of the location, replacing the current one.
+    pub(crate) async fn save<'e, E>(self, executor: E) -> Result<DataCap<Id>, Error>
+    where
+        E: SqliteExecutor<'e>,
+    {
+        let id = query_scalar!(
+            "INSERT INTO data_cap (location_id, cap, period, disconnect) VALUES ($1, $2, $3, $4) \
+            ON CONFLICT (location_id) DO UPDATE SET cap = excluded.cap, \
+            period = excluded.period, disconnect = excluded.disconnect, alert_period = NULL, \
+            alert_level = 0 \
+            RETURNING id \"id!\"",
+            self.location_id,
+            self.cap,
+            self.period,
+            self.disconnect
+        )
+        .fetch_one(executor)
+        .await?;
+
+        Ok(DataCap::<Id> {
+            id,
+            location_id: self.location_id,
+            cap: self.cap,
+            period: self.period,
+            disconnect: self.disconnect,
+            alert_period: None,
+            alert_level: 0,
+        })
+    }
+}
+
+impl DataCap<Id> {
+    /// Percentage of the cap used by `usage`.
+    #[must_use]
+    pub(crate) fn used(&self, usage: &UsageTotal) -> i64 {
+        (usage.upload + usage.download).saturating_mul(100) / self.cap.max(1)
+    }
+
+    /// Update the latest alert by `usage` of the current period. Returns the highest alert level
+    /// reached, unless it has already been alerted about in this period.
+    pub(crate) fn update_alert(&mut self, usage: &UsageTotal) -> Option<i64> {
+        if self.alert_period != Some(usage.period_start) {
+            self.alert_period = Some(usage.period_start);
+            self.alert_level = 0;
+        }
+        let used = self.used(usage);
+        let level = ALERT_LEVELS.into_iter().find(|level| used >= *level)?;
+        if level <= self.alert_level {
+            return None;
+        }
+        self.alert_level = level;
+        Some(level)
+    }
+
+    pub(crate) async fn all<'e, E>(executor: E) -> Result<Vec<Self>, Error>
+    where
+        E: SqliteExecutor<'e>,
+    {
+        let caps = query_as!(
+            Self,
+            "SELECT id, location_id, cap, period \"period: UsagePeriod\", disconnect, \
+            alert_period \"alert_period?: NaiveDateTime\", alert_level FROM data_cap"
+        )
+        .fetch_all(executor)
+        .await?;
+        Ok(caps)
+    }
+
+    pub(crate) async fn find_by_location_id<'e, E>(
+        executor: E,
+        location_id: Id,
+    ) -> Result<Option<Self>, Error>
+    where
+        E: SqliteExecutor<'e>,
+    {
+        let cap = query_as!(
+            Self,
+            "SELECT id, location_id, cap, period \"period: UsagePeriod\", disconnect, \
+            alert_period \"alert_period?: NaiveDateTime\", alert_level FROM data_cap \
+            WHERE location_id = $1",
+            location_id
+        )
+        .fetch_optional(executor)
+        .await?;
+        Ok(cap)
+    }
+
+    /// Remember the latest alert, so it isn't repeated.
+    pub(crate) async fn save_alert<'e, E>(&self, executor: E) -> Result<(), Error>
+    where
+        E: SqliteExecutor<'e>,
+    {
+        query!(
+            "UPDATE data_cap SET alert_period = $1, alert_level = $2 WHERE id = $3",
+            self.alert_period,
+            self.alert_level,
+            self.id
+        )
+        .execute(executor)
+        .await?;
+        Ok(())
+    }
+
+    pub(crate) async fn delete_by_location_id<'e, E>(
+        executor: E,
+        location_id: Id,
+    ) -> Result<(), Error>
+    where
+        E: SqliteExecutor<'e>,
+    {
+        query!("DELETE FROM data_cap WHERE location_id = $1", location_id)
+            .execute(executor)
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::SqlitePool;
+
+    use super::*;
+    use crate::database::models::fixtures;
+
+    fn time(time: &str) -> NaiveDateTime {
+        time.parse().unwrap()
+    }
+
+    #[sqlx::test]
+    async fn usage_totals(pool: SqlitePool) {
+        let location_id = fixtures::location(&pool).await.id;
+        for (tier, bucket, upload) in [
+            (RollupTier::Day, "2025-01-30T00:00:00", 1),
+            (RollupTier::Day, "2025-01-31T00:00:00", 2),
+            (RollupTier::Day, "2025-02-01T00:00:00", 4),
+            (RollupTier::Day, "2025-02-02T00:00:00", 8),
+            // Other tiers don't count.
+            (RollupTier::Hour, "2025-02-02T10:00:00", 16),
+        ] {
+            query(
+                "INSERT INTO location_stats_rollup \
+                (location_id, tier, bucket, upload, download, last_handshake, handshakes, uptime) \
+                VALUES ($1, $2, $3, $4, $5, 0, 0, 0)",
+            )
+            .bind(location_id)
+            .bind(tier)
+            .bind(time(bucket))
+            .bind(upload)
+            .bind(upload * 100)
+            .execute(&pool)
+            .await
+            .unwrap();
+        }
+
+        let totals = |period, from| {
+            let pool = pool.clone();
+            async move {
+                UsageTotal::all_by_location_id(&pool, location_id, period, &time(from))
+                    .await
+                    .unwrap()
+                    .into_iter()
+                    .map(|total| (total.period_start, total.upload, total.download))
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        // Periods are grouped from the start of the one containing `from`.
+        assert_eq!(
+            totals(UsagePeriod::Month, "2025-01-31T12:00:00").await,
+            [
+                (time("2025-01-01T00:00:00"), 3, 300),
+                (time("2025-02-01T00:00:00"), 12, 1200)
+            ]
+        );
+        assert_eq!(
+            totals(UsagePeriod::Day, "2025-01-31T12:00:00").await,
+            [
+                (time("2025-01-31T00:00:00"), 2, 200),
+                (time("2025-02-01T00:00:00"), 4, 400),
+                (time("2025-02-02T00:00:00"), 8, 800)
+            ]
+        );
+    }
+
+    #[sqlx::test]
+    async fn data_cap_alerts(pool: SqlitePool) {
+        let location_id = fixtures::location(&pool).await.id;
+        DataCap {
+            id: NoId,
+            location_id,
+            cap: 1000,
+            period: UsagePeriod::Month,
+            disconnect: true,
+            alert_period: None,
+            alert_level: 0,
+        }
+        .save(&pool)
+        .await
+        .unwrap();
+        let usage = |period_start: &str, total| UsageTotal {
+            period_start: time(period_start),
+            upload: total / 2,
+            download: total - total / 2,
+        };
+        // Alerts are persisted between checks.
+        let check = |usage: UsageTotal| {
+            let pool = pool.clone();
+            async move {
+                let mut data_cap = DataCap::find_by_location_id(&pool, location_id)
+                    .await
+                    .unwrap()
+                    .unwrap();
+                let level = data_cap.update_alert(&usage);
+                if level.is_some() {
+                    data_cap.save_alert(&pool).await.unwrap();
+                }
+                (level, data_cap.used(&usage))
+            }
+        };
+
+        assert_eq!(check(usage("2025-01-01T00:00:00", 500)).await, (None, 50));
+        assert_eq!(
+            check(usage("2025-01-01T00:00:00", 850)).await,
+            (Some(80), 85)
+        );
+        assert_eq!(check(usage("2025-01-01T00:00:00", 900)).await, (None, 90));
+        assert_eq!(
+            check(usage("2025-01-01T00:00:00", 1000)).await,
+            (Some(100), 100)
+        );
+        assert_eq!(check(usage("2025-01-01T00:00:00", 1200)).await, (None, 120));
+
+        // Alerts start over in the next period.
+        assert_eq!(check(usage("2025-02-01T00:00:00", 100)).await, (None, 10));
+        assert_eq!(
+            check(usage("2025-02-01T00:00:00", 1100)).await,
+            (Some(100), 110)
+        );
+        assert_eq!(check(usage("2025-02-01T00:00:00", 1100)).await, (None, 110));
+    }
+}
@@ -9,6 +9,8 @@ use std::{
 use reqwest::{Client, StatusCode};
 use serde::Serialize;
 use sqlx::{Sqlite, Transaction};
+#[cfg(not(target_os = "macos"))]
+use tauri::Manager;
 use tauri::{AppHandle, Emitter, Url};
 use tokio::time::sleep;
 
@@ -16,7 +18,7 @@ use crate::{
     active_connections::active_connections,
     commands::{do_update_instance, locations_changed},
     database::{
-        models::{instance::Instance, Id},
+        models::{connection::ActiveConnection, instance::Instance, Id},
         DB_POOL,
     },
     error::Error,
@@ -26,14 +28,16 @@ use crate::{
     CLIENT_PLATFORM_HEADER, CLIENT_VERSION_HEADER, MIN_CORE_VERSION, MIN_PROXY_VERSION,
     PKG_VERSION,
 };
+#[cfg(not(target_os = "macos"))]
+use crate::{appstate::AppState, utils::update_active_connections};
 
 const INTERVAL_SECONDS: Duration = Duration::from_secs(30);
 const HTTP_REQ_TIMEOUT: Duration = Duration::from_secs(5);
 static POLLING_ENDPOINT: &str = "/api/v1/poll";
 
 /// Periodically retrieves and updates configuration for all [`Instance`]s.
-/// Updates are only performed if no connections are established to the [`Instance`],
-/// otherwise event is emmited and UI message is displayed.
+/// Updates are applied to connections established to the [`Instance`] in place; if that's not
+/// possible, event is emmited and UI message is displayed.
 pub async fn poll_config(handle: AppHandle) {
     debug!("Starting the configuration polling loop.");
     // Polling starts sooner than app's frontend may load in dev builds, causing events (toasts) to be lost,
@@ -62,10 +66,21 @@ pub async fn poll_config(handle: AppHandle) {
             instances.len()
         );
         let mut config_retrieved = 0;
-        for instance in &mut instances {
+        let mut pending_updates = Vec::new();
+        for (index, instance) in instances.iter_mut().enumerate() {
             if instance.token.is_some() {
-                if let Err(err) = poll_instance(&mut transaction, instance, &handle).await {
-                    match err {
+                match poll_instance(&mut transaction, instance, &handle).await {
+                    Ok(connections) => {
+                        config_retrieved += 1;
+                        if !connections.is_empty() {
+                            pending_updates.push((index, connections));
+                        }
+                        debug!(
+                            "Finished processing configuration polling request for instance \
+                            {instance}"
+                        );
+                    }
+                    Err(err) => match err {
                         Error::CoreNotEnterprise => {
                             debug!(
                                 "Tried to contact core for instance {instance} config but it's not \
@@ -83,12 +98,7 @@ pub async fn poll_config(handle: AppHandle) {
                                 "Failed to retrieve instance {instance} config from core: {err}"
                             );
                         }
-                    }
-                } else {
-                    config_retrieved += 1;
-                    debug!(
-                        "Finished processing configuration polling request for instance {instance}"
-                    );
+                    },
                 }
             }
         }
@@ -97,6 +107,10 @@ pub async fn poll_config(handle: AppHandle) {
                 "Failed to commit config polling transaction, configuration won't be updated: \
                 {err}"
             );
+        } else {
+            for (index, connections) in pending_updates {
+                update_connections(&instances[index], &connections, &handle).await;
+            }
         }
         if let Err(err) = handle.emit(EventKey::InstanceUpdate.into(), ()) {
             error!("Failed to emit instance update event to the frontend: {err}");
@@ -118,13 +132,15 @@ pub async fn poll_config(handle: AppHandle) {
     }
 }
 
-/// Retrieves configuration for given [`Instance`].
-/// Updates the instance if there aren't any active connections, otherwise displays UI message.
+/// Retrieves configuration for given [`Instance`] and updates the instance.
+/// Returns active connections of the instance, if its configuration has changed. New configuration
+/// should be applied to them with [`update_connections`] once the transaction is committed, so that
+/// the background service isn't called while the database is locked.
 pub async fn poll_instance(
     transaction: &mut Transaction<'_, Sqlite>,
     instance: &mut Instance<Id>,
     handle: &AppHandle,
-) -> Result<(), Error> {
+) -> Result<Vec<ActiveConnection>, Error> {
     debug!("Getting config from core for instance {}", instance.name);
     // Query proxy api
     let request = build_request(instance)?;
@@ -216,7 +232,7 @@ pub async fn poll_instance(
             "Config for instance {}({}) didn't change",
             instance.name, instance.id
         );
-        return Ok(());
+        return Ok(Vec::new());
     }
 
     debug!(
@@ -225,9 +241,9 @@ pub async fn poll_instance(
     );
 
     // Config changed. If there are no active connections for this instance, update the database.
-    // Otherwise just display a message to reconnect.
-    //
-    if active_connections(instance).await?.is_empty() {
+    // Otherwise active connections have to be updated too, after the transaction is committed.
+    let connections = active_connections(instance).await?;
+    if connections.is_empty() {
         debug!(
             "Updating instance {}({}) configuration: {device_config:?}",
             instance.name, instance.id,
@@ -238,18 +254,72 @@ pub async fn poll_instance(
             instance.name, instance.id
         );
     } else {
-        debug!(
-            "Emitting config-changed event for instance {}({})",
-            instance.name, instance.id,
-        );
-        let _ = handle.emit(EventKey::ConfigChanged.into(), &instance.name);
-        info!(
-            "Emitted config-changed event for instance {}({})",
-            instance.name, instance.id,
+        // Configuration of live connections can't be updated on macOS, they need to be
+        // reconnected first.
+        #[cfg(not(target_os = "macos"))]
+        {
+            debug!(
+                "Updating instance {}({}) configuration, which has {} active connections: \
+                {device_config:?}",
+                instance.name,
+                instance.id,
+                connections.len()
+            );
+            do_update_instance(transaction, instance, device_config.clone()).await?;
+            info!(
+                "Updated instance {}({}) configuration based on core's response",
+                instance.name, instance.id
+            );
+        }
+    }
+
+    Ok(connections)
+}
+
+/// Apply new configuration of `instance` to its active connections. Connections which can't be
+/// updated in place need to be reconnected, so the UI displays a message about it.
+pub async fn update_connections(
+    instance: &Instance<Id>,
+    connections: &[ActiveConnection],
+    handle: &AppHandle,
+) {
+    #[cfg(not(target_os = "macos"))]
+    {
+        let mtu = handle
+            .state::<AppState>()
+            .app_config
+            .lock()
+            .expect("failed to lock app state")
+            .mtu();
+        let errors = update_active_connections(connections, mtu).await;
+        if errors.is_empty() {
+            info!(
+                "Applied new configuration of instance {}({}) to its active connections",
+                instance.name, instance.id
+            );
+            return;
+        }
+        warn!(
+            "Failed to apply new configuration of instance {}({}) to {} of its {} active \
+            connections, they need to be reconnected",
+            instance.name,
+            instance.id,
+            errors.len(),
+            connections.len()
         );
     }
+    #[cfg(target_os = "macos")]
+    let _ = connections;
 
-    Ok(())
+    debug!(
+        "Emitting config-changed event for instance {}({})",
+        instance.name, instance.id,
+    );
+    let _ = handle.emit(EventKey::ConfigChanged.into(), &instance.name);
+    info!(
+        "Emitted config-changed event for instance {}({})",
+        instance.name, instance.id,
+    );
 }
 
 async fn config_changed(
@@ -46,6 +46,8 @@ pub enum Error {
     ConversionError(String),
     #[error("JSON error: {0}")]
     JsonError(#[from] serde_json::Error),
+    #[error("Data cap of the location has been reached")]
+    DataCapReached,
 }
 
 // we must manually implement serde::Serialize
@@ -2,7 +2,11 @@ use serde::Serialize;
 use tauri::{AppHandle, Emitter, Url};
 use tauri_plugin_notification::NotificationExt;
 
-use crate::{tray::show_main_window, ConnectionType};
+use crate::{
+    database::models::{usage::UsagePeriod, Id},
+    tray::show_main_window,
+    ConnectionType,
+};
 
 // Match src/pages/client/types.ts.
 #[non_exhaustive]
@@ -19,6 +23,7 @@ pub enum EventKey {
     MfaTrigger,
     VersionMismatch,
     UuidMismatch,
+    DataCapReached,
 }
 
 impl From<EventKey> for &'static str {
@@ -36,6 +41,7 @@ impl From<EventKey> for &'static str {
             EventKey::MfaTrigger => "mfa-trigger",
             EventKey::VersionMismatch => "version-mismatch",
             EventKey::UuidMismatch => "uuid-mismatch",
+            EventKey::DataCapReached => "data-cap-reached",
         }
     }
 }
@@ -94,6 +100,52 @@ impl DeadConnReconnected {
     }
 }
 
+/// Used as payload for [`DATA_CAP_REACHED`] event
+#[derive(Clone, Serialize)]
+pub struct DataCapReached {
+    pub(crate) name: String,
+    pub(crate) location_id: Id,
+    /// Percentage of the cap which has been used.
+    pub(crate) level: i64,
+    pub(crate) usage: i64,
+    pub(crate) cap: i64,
+    pub(crate) period: UsagePeriod,
+    pub(crate) disconnected: bool,
+}
+
+impl DataCapReached {
+    /// Emits [`DATA_CAP_REACHED`] event with corresponding side effects.
+    pub(crate) fn emit(self, app_handle: &AppHandle) {
+        let period = match self.period {
+            UsagePeriod::Day => "today",
+            UsagePeriod::Month => "this month",
+        };
+        let mut body = format!(
+            "{} MB of {} MB has been used {period}.",
+            self.usage / 1_000_000,
+            self.cap / 1_000_000
+        );
+        if self.disconnected {
+            body.push_str(" The location has been disconnected.");
+        }
+        if let Err(err) = app_handle
+            .notification()
+            .builder()
+            .title(format!(
+                "Location {} has reached {}% of its data cap",
+                self.name, self.level
+            ))
+            .body(body)
+            .show()
+        {
+            warn!("Data cap notification not shown. Reason: {err}");
+        }
+        if let Err(err) = app_handle.emit(EventKey::DataCapReached.into(), self) {
+            error!("Event Data Cap Reached was not emitted. Reason: {err}");
+        }
+    }
+}
+
 #[derive(Clone, Serialize)]
 pub struct AddInstancePayload<'a> {
     pub token: &'a str,
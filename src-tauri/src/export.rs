@@ -0,0 +1,359 @@
+//! Export of connection history and statistics of locations and tunnels to CSV or JSON.
+
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::{
+    fs::{self, OpenOptions},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use chrono::NaiveDateTime;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    commands::get_range_aggregation,
+    database::{
+        models::{
+            connection::{ConnectionInfo, DisconnectReason},
+            location_stats::LocationStats,
+            tunnel::{TunnelConnectionInfo, TunnelStats},
+            Id,
+        },
+        DB_POOL,
+    },
+    error::Error,
+    CommonConnectionInfo, CommonLocationStats, ConnectionType,
+};
+
+// Same as serde format of `NaiveDateTime`, so both formats contain the same timestamps.
+const TIMESTAMP_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.f";
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    Json,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportData {
+    /// Connections which overlap with the exported range.
+    #[default]
+    History,
+    /// Traffic per interval, aggregated the same way as in the statistics chart.
+    Stats,
+}
+
+trait CsvRecord {
+    const HEADER: &'static str;
+
+    fn write_csv<W: Write>(&self, writer: &mut W) -> std::io::Result<()>;
+}
+
+#[derive(Serialize)]
+struct ConnectionRecord {
+    id: Id,
+    start: NaiveDateTime,
+    end: NaiveDateTime,
+    upload: Option<i32>,
+    download: Option<i32>,
+    reason: Option<DisconnectReason>,
+}
+
+impl From<CommonConnectionInfo> for ConnectionRecord {
+    fn from(connection: CommonConnectionInfo) -> Self {
+        Self {
+            id: connection.id,
+            start: connection.start,
+            end: connection.end,
+            upload: connection.upload,
+            download: connection.download,
+            reason: connection.reason,
+        }
+    }
+}
+
+impl CsvRecord for ConnectionRecord {
+    const HEADER: &'static str = "id,start,end,upload,download,reason";
+
+    fn write_csv<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        writeln!(
+            writer,
+            "{},{},{},{},{},{}",
+            self.id,
+            self.start.format(TIMESTAMP_FORMAT),
+            self.end.format(TIMESTAMP_FORMAT),
+            self.upload
+                .map(|upload| upload.to_string())
+                .unwrap_or_default(),
+            self.download
+                .map(|download| download.to_string())
+                .unwrap_or_default(),
+            reason_field(self.reason)?,
+        )
+    }
+}
+
+// Same as serde format of `DisconnectReason`, so both formats contain the same reasons.
+fn reason_field(reason: Option<DisconnectReason>) -> std::io::Result<String> {
+    match serde_json::to_value(reason)? {
+        serde_json::Value::String(reason) => Ok(reason),
+        _ => Ok(String::new()),
+    }
+}
+
+/// Traffic in an interval; `upload` and `download` are deltas, not counters of the interface.
+#[derive(Serialize)]
+struct StatsRecord {
+    collected_at: NaiveDateTime,
+    upload: i64,
+    download: i64,
+    last_handshake: i64,
+}
+
+impl From<CommonLocationStats<Id>> for StatsRecord {
+    fn from(stats: CommonLocationStats<Id>) -> Self {
+        Self {
+            collected_at: stats.collected_at,
+            upload: stats.upload,
+            download: stats.download,
+            last_handshake: stats.last_handshake,
+        }
+    }
+}
+
+impl CsvRecord for StatsRecord {
+    const HEADER: &'static str = "collected_at,upload,download,last_handshake";
+
+    fn write_csv<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        writeln!(
+            writer,
+            "{},{},{},{}",
+            self.collected_at.format(TIMESTAMP_FORMAT),
+            self.upload,
+            self.download,
+            self.last_handshake,
+        )
+    }
+}
+
+fn write_records<R, W>(records: &[R], format: ExportFormat, mut writer: W) -> Result<(), Error>
+where
+    R: CsvRecord + Serialize,
+    W: Write,
+{
+    match format {
+        ExportFormat::Csv => {
+            writeln!(writer, "{}", R::HEADER)?;
+            for record in records {
+                record.write_csv(&mut writer)?;
+            }
+        }
+        ExportFormat::Json => {
+            serde_json::to_writer_pretty(&mut writer, records)?;
+            writeln!(writer)?;
+        }
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Export connection history or statistics of a location or tunnel between `from` and `to`.
+pub async fn export<W: Write>(
+    location_id: Id,
+    connection_type: ConnectionType,
+    data: ExportData,
+    format: ExportFormat,
+    from: NaiveDateTime,
+    to: NaiveDateTime,
+    writer: W,
+) -> Result<(), Error> {
+    debug!(
+        "Exporting {data:?} of {connection_type} {location_id} from {from} to {to} as {format:?}"
+    );
+    match data {
+        ExportData::History => {
+            let connections: Vec<CommonConnectionInfo> = match connection_type {
+                ConnectionType::Location => {
+                    ConnectionInfo::all_by_location_id(&*DB_POOL, location_id)
+                        .await?
+                        .into_iter()
+                        .map(Into::into)
+                        .collect()
+                }
+                ConnectionType::Tunnel => {
+                    TunnelConnectionInfo::all_by_tunnel_id(&*DB_POOL, location_id)
+                        .await?
+                        .into_iter()
+                        .map(Into::into)
+                        .collect()
+                }
+            };
+            // Connections are ordered from the latest one; export them chronologically.
+            let records: Vec<ConnectionRecord> = connections
+                .into_iter()
+                .rev()
+                .filter(|connection| connection.end >= from && connection.start <= to)
+                .map(Into::into)
+                .collect();
+            write_records(&records, format, writer)
+        }
+        ExportData::Stats => {
+            // Intervals depend on the length of the range, not on how long ago it was.
+            let aggregation = get_range_aggregation(from, to)?;
+            let stats: Vec<CommonLocationStats<Id>> = match connection_type {
+                ConnectionType::Location => LocationStats::all_by_location_id(
+                    &*DB_POOL,
+                    location_id,
+                    &from,
+                    &to,
+                    &aggregation,
+                    None,
+                )
+                .await?
+                .into_iter()
+                .map(Into::into)
+                .collect(),
+                ConnectionType::Tunnel => {
+                    TunnelStats::all_by_tunnel_id(&*DB_POOL, location_id, &from, &to, &aggregation)
+                        .await?
+                        .into_iter()
+                        .map(Into::into)
+                        .collect()
+                }
+            };
+            let records: Vec<StatsRecord> = stats.into_iter().map(Into::into).collect();
+            write_records(&records, format, writer)
+        }
+    }
+}
+
+/// Export connection history or statistics to file at `path`, which is only accessible by the
+/// user, as it's a copy of the client's database.
+///
+/// Data is written to a temporary file next to `path`, which is renamed into place only once the
+/// export succeeds, so a failed export leaves an existing file at `path` untouched.
+pub async fn export_to_file(
+    path: &Path,
+    location_id: Id,
+    connection_type: ConnectionType,
+    data: ExportData,
+    format: ExportFormat,
+    from: NaiveDateTime,
+    to: NaiveDateTime,
+) -> Result<(), Error> {
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    let temp_path = PathBuf::from(temp_path);
+    // Leftover from an interrupted export; `create_new` below refuses to reuse it.
+    let _ = fs::remove_file(&temp_path);
+
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let file = options.open(&temp_path)?;
+
+    let result = export(
+        location_id,
+        connection_type,
+        data,
+        format,
+        from,
+        to,
+        BufWriter::new(file),
+    )
+    .await
+    .and_then(|()| fs::rename(&temp_path, path).map_err(Error::from));
+    if let Err(err) = result {
+        let _ = fs::remove_file(&temp_path);
+        return Err(err);
+    }
+    info!(
+        "Exported {data:?} of {connection_type} {location_id} to {}",
+        path.display()
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_connections() {
+        let start = "2025-01-01T10:00:00".parse::<NaiveDateTime>().unwrap();
+        let end = "2025-01-01T11:00:00".parse::<NaiveDateTime>().unwrap();
+        let records = [
+            ConnectionRecord {
+                id: 1,
+                start,
+                end,
+                upload: Some(100),
+                download: Some(200),
+                reason: Some(DisconnectReason::DataCap),
+            },
+            ConnectionRecord {
+                id: 2,
+                start,
+                end,
+                upload: None,
+                download: None,
+                reason: None,
+            },
+        ];
+
+        let mut csv = Vec::new();
+        write_records(&records, ExportFormat::Csv, &mut csv).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "id,start,end,upload,download,reason\n\
+            1,2025-01-01T10:00:00,2025-01-01T11:00:00,100,200,DataCap\n\
+            2,2025-01-01T10:00:00,2025-01-01T11:00:00,,,\n"
+        );
+
+        let mut json = Vec::new();
+        write_records(&records, ExportFormat::Json, &mut json).unwrap();
+        let value: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(value[0]["reason"], "DataCap");
+        assert_eq!(value[1]["reason"], serde_json::Value::Null);
+    }
+
+    #[test]
+    fn write_stats() {
+        let collected_at = "2025-01-01T10:00:00".parse::<NaiveDateTime>().unwrap();
+        let records = [StatsRecord {
+            collected_at,
+            upload: 100,
+            download: 200,
+            last_handshake: 1_735_725_600,
+        }];
+
+        let mut csv = Vec::new();
+        write_records(&records, ExportFormat::Csv, &mut csv).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "collected_at,upload,download,last_handshake\n\
+            2025-01-01T10:00:00,100,200,1735725600\n"
+        );
+
+        let mut json = Vec::new();
+        write_records(&records, ExportFormat::Json, &mut json).unwrap();
+        let value: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!([{
+                "collected_at": "2025-01-01T10:00:00",
+                "upload": 100,
+                "download": 200,
+                "last_handshake": 1_735_725_600,
+            }])
+        );
+    }
+}
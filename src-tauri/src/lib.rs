@@ -13,7 +13,7 @@ use chrono::NaiveDateTime;
 use semver::Version;
 use serde::{Deserialize, Serialize};
 
-use self::database::models::{Id, NoId};
+use self::database::models::{connection::DisconnectReason, Id, NoId};
 
 pub mod active_connections;
 pub mod app_config;
@@ -25,8 +25,10 @@ pub mod database;
 pub mod enterprise;
 pub mod error;
 pub mod events;
+pub mod export;
 pub mod log_watcher;
 pub mod periodic;
+pub mod probe;
 pub mod proto;
 pub mod service;
 pub mod tray;
@@ -105,6 +107,7 @@ pub struct CommonConnection<I = NoId> {
     pub start: NaiveDateTime,
     pub end: NaiveDateTime,
     pub connection_type: ConnectionType,
+    pub reason: Option<DisconnectReason>,
 }
 
 // Common fields for LocationStats and TunnelStats due to shared command
@@ -119,6 +122,10 @@ pub struct CommonLocationStats<I = NoId> {
     pub listen_port: u32,
     pub persistent_keepalive_interval: Option<u16>,
     pub connection_type: ConnectionType,
+    // Connection quality: round-trip time and jitter in milliseconds, and loss as a fraction.
+    pub rtt: Option<f64>,
+    pub jitter: Option<f64>,
+    pub loss: Option<f64>,
 }
 
 // Common fields for ConnectionInfo and TunnelConnectionInfo due to shared command
@@ -130,4 +137,5 @@ pub struct CommonConnectionInfo {
     pub end: NaiveDateTime,
     pub upload: Option<i32>,
     pub download: Option<i32>,
+    pub reason: Option<DisconnectReason>,
 }
@@ -1,15 +1,21 @@
-use std::time::Duration;
+use std::{sync::LazyLock, time::Duration};
 
 use chrono::{NaiveDateTime, TimeDelta, Utc};
+#[cfg(not(target_os = "macos"))]
+use tauri::Emitter;
 use tauri::{AppHandle, Manager};
-use tokio::time::interval;
+#[cfg(not(target_os = "macos"))]
+use tokio::time::sleep;
+use tokio::{select, sync::Notify, time::interval};
 
 use crate::{
     active_connections::ACTIVE_CONNECTIONS,
     appstate::AppState,
-    commands::{connect, disconnect},
+    commands::{connect, disconnect_with_reason},
     database::{
         models::{
+            connection::DisconnectReason,
+            connection_event::{record_event, ConnectionEventKind},
             location::Location,
             location_stats::LocationStats,
             tunnel::{Tunnel, TunnelStats},
@@ -20,8 +26,22 @@ use crate::{
     events::{DeadConnDroppedOut, DeadConnReconnected},
     ConnectionType,
 };
+#[cfg(not(target_os = "macos"))]
+use crate::{
+    events::EventKey,
+    service::{
+        client::DAEMON_CLIENT,
+        proto::{InterfaceEvent, InterfaceEventKind},
+    },
+    utils::save_connection_history,
+};
 
 const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+#[cfg(not(target_os = "macos"))]
+const WATCH_RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Used to verify active connections ahead of schedule.
+static VERIFY_CONNECTIONS: LazyLock<Notify> = LazyLock::new(Notify::new);
 
 /// Returns true if connection is valid
 fn check_last_active_connection(last: NaiveDateTime, peer_alive_period: TimeDelta) -> bool {
@@ -40,7 +60,22 @@ async fn reconnect(
     peer_alive_period: &TimeDelta,
 ) {
     debug!("Starting attempt to reconnect {con_interface_name} {con_type}({con_id})...");
-    match disconnect(con_id, con_type, app_handle.clone()).await {
+    record_event(
+        con_id,
+        con_type,
+        ConnectionEventKind::Reconnecting,
+        None,
+        None,
+    )
+    .await;
+    match disconnect_with_reason(
+        con_id,
+        con_type,
+        app_handle.clone(),
+        DisconnectReason::Reconnect,
+    )
+    .await
+    {
         Ok(()) => {
             debug!("Connection for {con_type} {con_interface_name}({con_id}) disconnected successfully in path of reconnection.");
             let payload = DeadConnReconnected {
@@ -75,7 +110,14 @@ async fn disconnect_dead_connection(
 ) {
     debug!(
         "Attempting to disconnect dead connection for interface {con_interface_name}, {con_type}: {con_id}");
-    match disconnect(con_id, con_type, app_handle.clone()).await {
+    match disconnect_with_reason(
+        con_id,
+        con_type,
+        app_handle.clone(),
+        DisconnectReason::Dropped,
+    )
+    .await
+    {
         Ok(()) => {
             info!("Connection verification: interface {con_interface_name}, {con_type}({con_id}): disconnected due to timeout.");
             let event_payload = DeadConnDroppedOut {
@@ -111,7 +153,12 @@ pub async fn verify_active_connections(app_handle: AppHandle) {
     let mut interval = interval(CHECK_INTERVAL);
 
     loop {
-        interval.tick().await;
+        select! {
+            _ = interval.tick() => {}
+            () = VERIFY_CONNECTIONS.notified() => {
+                debug!("Active connections verification has been requested.");
+            }
+        }
         let connections = ACTIVE_CONNECTIONS.lock().await;
         let connection_count = connections.len();
         if connection_count == 0 {
@@ -383,3 +430,115 @@ pub async fn verify_active_connections(app_handle: AppHandle) {
         }
     }
 }
+
+/// Watch interface events published by the background service, so that connection state changes
+/// are reflected immediately instead of on the next statistics or verification round.
+#[cfg(not(target_os = "macos"))]
+pub async fn watch_interface_events(app_handle: AppHandle) {
+    loop {
+        match DAEMON_CLIENT.clone().watch_events(()).await {
+            Ok(response) => {
+                debug!("Watching interface events published by the background service.");
+                let mut stream = response.into_inner();
+                loop {
+                    match stream.message().await {
+                        Ok(Some(event)) => handle_interface_event(&app_handle, event).await,
+                        Ok(None) => {
+                            debug!("Interface event stream has been closed.");
+                            break;
+                        }
+                        Err(err) => {
+                            warn!("Interface event stream has failed: {err}");
+                            break;
+                        }
+                    }
+                }
+            }
+            Err(err) => {
+                debug!("Failed to watch interface events of the background service: {err}");
+            }
+        }
+        sleep(WATCH_RETRY_INTERVAL).await;
+    }
+}
+
+#[cfg(not(target_os = "macos"))]
+async fn handle_interface_event(app_handle: &AppHandle, event: InterfaceEvent) {
+    trace!("Received interface event: {event:?}");
+    let connection = ACTIVE_CONNECTIONS
+        .lock()
+        .await
+        .iter()
+        .find(|con| con.interface_name == event.interface_name)
+        .cloned();
+    let Some(con) = connection else {
+        debug!(
+            "Ignoring event of interface {} without an active connection.",
+            event.interface_name
+        );
+        return;
+    };
+
+    match event.kind() {
+        InterfaceEventKind::FirstHandshake => {
+            info!(
+                "Interface {} of {} {} has completed its first handshake.",
+                con.interface_name, con.connection_type, con.location_id
+            );
+        }
+        InterfaceEventKind::HandshakeStale => {
+            info!(
+                "Handshake on interface {} of {} {} is stale, verifying active connections.",
+                con.interface_name, con.connection_type, con.location_id
+            );
+            VERIFY_CONNECTIONS.notify_one();
+        }
+        InterfaceEventKind::EndpointChanged => {
+            debug!(
+                "Endpoint of interface {} peer has changed to {:?}.",
+                con.interface_name, event.endpoint
+            );
+        }
+        InterfaceEventKind::ConfigurationFailed => {
+            warn!(
+                "Configuration of interface {} of {} {} has failed: {}",
+                con.interface_name,
+                con.connection_type,
+                con.location_id,
+                event.message.as_deref().unwrap_or_default()
+            );
+            record_event(
+                con.location_id,
+                con.connection_type,
+                ConnectionEventKind::Failed,
+                None,
+                event.message,
+            )
+            .await;
+        }
+        InterfaceEventKind::Removed => {
+            info!(
+                "Interface {} of {} {} has been removed by the background service, marking it \
+                as disconnected.",
+                con.interface_name, con.connection_type, con.location_id
+            );
+            let app_state = app_handle.state::<AppState>();
+            if let Some(con) = app_state
+                .remove_connection(con.location_id, con.connection_type)
+                .await
+            {
+                if let Err(err) = save_connection_history(&con, DisconnectReason::Service).await {
+                    error!(
+                        "Failed to save connection history of {} {}: {err}",
+                        con.connection_type, con.location_id
+                    );
+                }
+            }
+        }
+        InterfaceEventKind::Created | InterfaceEventKind::Updated => {}
+    }
+
+    if let Err(err) = app_handle.emit(EventKey::ConnectionChanged.into(), ()) {
+        error!("Failed to emit connection change event to the frontend: {err}");
+    }
+}
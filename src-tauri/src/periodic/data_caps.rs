@@ -0,0 +1,109 @@
+use tauri::AppHandle;
+
+use crate::{
+    active_connections::find_connection,
+    commands::disconnect_with_reason,
+    database::{
+        models::{
+            connection::DisconnectReason,
+            location::Location,
+            usage::{DataCap, UsageTotal},
+            Id,
+        },
+        DB_POOL,
+    },
+    error::Error,
+    events::DataCapReached,
+    ConnectionType,
+};
+
+/// Alert about locations which have used most of their data caps in the current period, once per
+/// level and period. Locations which have reached their caps are disconnected if requested, also
+/// whenever they are connected again in the same period.
+pub(crate) async fn check_data_caps(app_handle: &AppHandle) -> Result<(), Error> {
+    for mut data_cap in DataCap::all(&*DB_POOL).await? {
+        let Some(usage) =
+            UsageTotal::current_by_location_id(&*DB_POOL, data_cap.location_id, data_cap.period)
+                .await?
+        else {
+            continue;
+        };
+        let alert = data_cap.update_alert(&usage);
+        if alert.is_some() {
+            data_cap.save_alert(&*DB_POOL).await?;
+        }
+        let used = data_cap.used(&usage);
+        let connected = find_connection(data_cap.location_id, ConnectionType::Location)
+            .await
+            .is_some();
+        let enforce = used >= 100 && data_cap.disconnect && connected;
+        if alert.is_none() && !enforce {
+            continue;
+        }
+
+        let Some(location) = Location::find_by_id(&*DB_POOL, data_cap.location_id).await? else {
+            continue;
+        };
+        let total = usage.upload + usage.download;
+        info!(
+            "Location {location} has used {used}% of its data cap ({total} of {} bytes)",
+            data_cap.cap
+        );
+        let mut disconnected = false;
+        if enforce {
+            match disconnect_with_reason(
+                location.id,
+                ConnectionType::Location,
+                app_handle.clone(),
+                DisconnectReason::DataCap,
+            )
+            .await
+            {
+                Ok(()) => {
+                    info!("Location {location} has been disconnected as it reached its data cap");
+                    disconnected = true;
+                }
+                Err(err) => {
+                    error!("Failed to disconnect location {location} over its data cap: {err}");
+                }
+            }
+        }
+        // Repeated disconnections are reported too, so the user knows why the location went down.
+        if alert.is_some() || disconnected {
+            DataCapReached {
+                name: location.name.clone(),
+                location_id: location.id,
+                level: alert.unwrap_or(data_cap.alert_level),
+                usage: total,
+                cap: data_cap.cap,
+                period: data_cap.period,
+                disconnected,
+            }
+            .emit(app_handle);
+        }
+    }
+
+    Ok(())
+}
+
+/// Refuse to connect the location if it has reached its data cap in the current period and is
+/// meant to stay disconnected. Otherwise it would only be disconnected by the next check.
+pub(crate) async fn ensure_within_data_cap(location: &Location<Id>) -> Result<(), Error> {
+    let Some(data_cap) = DataCap::find_by_location_id(&*DB_POOL, location.id).await? else {
+        return Ok(());
+    };
+    if !data_cap.disconnect {
+        return Ok(());
+    }
+    let Some(usage) =
+        UsageTotal::current_by_location_id(&*DB_POOL, location.id, data_cap.period).await?
+    else {
+        return Ok(());
+    };
+    if data_cap.used(&usage) >= 100 {
+        warn!("Refusing to connect location {location}, as it has reached its data cap");
+        return Err(Error::DataCapReached);
+    }
+
+    Ok(())
+}
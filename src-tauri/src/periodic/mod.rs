@@ -1,12 +1,15 @@
 use tauri::AppHandle;
 use tokio::select;
 
+#[cfg(not(target_os = "macos"))]
+use self::connection::watch_interface_events;
 use self::{
     connection::verify_active_connections, purge_stats::purge_stats, version::poll_version,
 };
 use crate::enterprise::periodic::config::poll_config;
 
 pub mod connection;
+pub mod data_caps;
 pub mod purge_stats;
 pub mod version;
 
@@ -15,6 +18,10 @@ pub async fn run_periodic_tasks(app_handle: &AppHandle) {
     debug!(
         "Starting periodic tasks (config, version polling, stats purging and active connection verification)..."
     );
+    // Interface events are watched in the background; the task keeps reconnecting to the
+    // background service, so it never finishes.
+    #[cfg(not(target_os = "macos"))]
+    tauri::async_runtime::spawn(watch_interface_events(app_handle.clone()));
     select! {
         () = poll_version(app_handle.clone()) => {
             error!("Version polling task has stopped unexpectedly");
@@ -25,7 +32,7 @@ pub async fn run_periodic_tasks(app_handle: &AppHandle) {
         () = verify_active_connections(app_handle.clone()) => {
             error!("Active connection verification task has stopped unexpectedly");
         }
-        () = purge_stats() => {
+        () = purge_stats(app_handle.clone()) => {
             error!("Stats purging task has stopped unexpectedly");
         }
     };
@@ -1,54 +1,73 @@
 use std::time::Duration;
 
-use tokio::time::interval;
+use tauri::{AppHandle, Manager};
+use tokio::{select, time::interval};
 
-use crate::database::{
-    models::{location_stats::LocationStats, tunnel::TunnelStats},
-    DB_POOL,
+use super::data_caps::check_data_caps;
+use crate::{
+    appstate::AppState,
+    database::{
+        models::stats_rollup::{purge_stats as purge_old_stats, rollup_stats},
+        DB_POOL,
+    },
 };
 
+// 5 minutes
+const ROLLUP_INTERVAL: Duration = Duration::from_secs(5 * 60);
 // 12 hours
 const PURGE_INTERVAL: Duration = Duration::from_secs(12 * 60 * 60);
 
-/// Periodically purges location and tunnel stats.
+/// Periodically rolls up and purges location and tunnel stats, and checks data caps of locations.
 ///
-/// By design this happens infrequently to not overload the DB connection.
+/// By design purging happens infrequently to not overload the DB connection.
 /// There is a separate purge done at client startup.
-pub async fn purge_stats() {
-    debug!("Starting the stats purging loop.");
-    let mut interval = interval(PURGE_INTERVAL);
+pub async fn purge_stats(app_handle: AppHandle) {
+    debug!("Starting the stats rollup and purging loop.");
+    let mut rollup_interval = interval(ROLLUP_INTERVAL);
+    let mut purge_interval = interval(PURGE_INTERVAL);
 
     loop {
         // wait for next iteration
-        interval.tick().await;
+        let purge = select! {
+            _ = rollup_interval.tick() => false,
+            _ = purge_interval.tick() => true,
+        };
 
         // begin transaction
         let Ok(mut transaction) = DB_POOL.begin().await else {
-            error!(
-                "Failed to begin database transaction for stats purging, retrying in {}h",
-                PURGE_INTERVAL.as_secs() / 3600
-            );
+            error!("Failed to begin database transaction for stats rollup and purging");
             continue;
         };
 
-        debug!("Purging old stats from the database...");
-        if let Err(err) = LocationStats::purge(&mut *transaction).await {
-            error!("Failed to purge location stats: {err}");
-        } else {
-            debug!("Old location stats have been purged successfully.");
+        // Stats are always rolled up before purging, so none are lost.
+        if let Err(err) = rollup_stats(&mut *transaction).await {
+            error!("Failed to roll up stats: {err}");
+            continue;
         }
-        if let Err(err) = TunnelStats::purge(&mut *transaction).await {
-            error!("Failed to purge tunnel stats: {err}");
-        } else {
-            debug!("Old tunnel stats have been purged successfully.");
+        if purge {
+            debug!("Purging old stats from the database...");
+            let retention = app_handle
+                .state::<AppState>()
+                .app_config
+                .lock()
+                .unwrap()
+                .stats_retention();
+            if let Err(err) = purge_old_stats(&mut *transaction, &retention).await {
+                error!("Failed to purge stats: {err}");
+            } else {
+                debug!("Old stats have been purged successfully.");
+            }
         }
 
         // commit transaction
         if let Err(err) = transaction.commit().await {
-            error!(
-                "Failed to commit database transaction for stats purging: {err}. Retrying in {}h",
-                PURGE_INTERVAL.as_secs() / 3600
-            );
+            error!("Failed to commit database transaction for stats rollup and purging: {err}");
+            continue;
+        }
+
+        // Usage is computed from rollups, so check it once they're up to date.
+        if let Err(err) = check_data_caps(&app_handle).await {
+            error!("Failed to check data caps: {err}");
         }
     }
 }
@@ -0,0 +1,154 @@
+//! Connection quality probes.
+//!
+//! Probes are UDP datagrams sent through the tunnel to the configured target of a location or
+//! tunnel. Either a reply or an ICMP port unreachable error counts as an answer, so the target
+//! doesn't have to run any service, and probing doesn't require raw sockets.
+
+use std::{
+    io::{self, ErrorKind},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+
+use tokio::{
+    net::UdpSocket,
+    time::{interval, sleep, timeout, Instant},
+};
+
+use crate::{
+    database::{
+        models::{location::Location, quality_stats::QualityStats, tunnel::Tunnel, Id},
+        DB_POOL,
+    },
+    error::Error,
+    ConnectionType,
+};
+
+// Same as the default statistics period of the background service.
+const PROBE_INTERVAL: Duration = Duration::from_secs(10);
+// Number of probes in each round.
+const PROBE_COUNT: usize = 5;
+const PROBE_SPACING: Duration = Duration::from_millis(200);
+const PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+// First port used by traceroute, which is unlikely to be in use.
+const DEFAULT_PROBE_PORT: u16 = 33434;
+const PROBE_PAYLOAD: &[u8] = b"defguard-probe";
+
+/// Parse probe target, which is an IP address with an optional UDP port.
+pub(crate) fn parse_probe_target(target: &str) -> Result<SocketAddr, Error> {
+    let target = target.trim();
+    target
+        .parse::<SocketAddr>()
+        .or_else(|_| {
+            target
+                .parse::<IpAddr>()
+                .map(|ip| SocketAddr::new(ip, DEFAULT_PROBE_PORT))
+        })
+        .map_err(|_| {
+            Error::InternalError(format!(
+                "Invalid probe target {target}, expected an IP address with an optional port"
+            ))
+        })
+}
+
+/// Send a single probe, returning its round-trip time if it was answered.
+async fn probe_once(target: SocketAddr) -> io::Result<Option<Duration>> {
+    let unspecified = if target.is_ipv4() {
+        IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+    } else {
+        IpAddr::V6(Ipv6Addr::UNSPECIFIED)
+    };
+    // A new socket for every probe, so late answers aren't mistaken for answers to later probes.
+    let socket = UdpSocket::bind((unspecified, 0)).await?;
+    socket.connect(target).await?;
+
+    let start = Instant::now();
+    if let Err(err) = socket.send(PROBE_PAYLOAD).await {
+        debug!("Failed to send probe to {target}: {err}");
+        return Ok(None);
+    }
+    let mut buf = [0; 64];
+    match timeout(PROBE_TIMEOUT, socket.recv(&mut buf)).await {
+        Ok(Ok(_)) => Ok(Some(start.elapsed())),
+        // ICMP port unreachable is reported as refused connection, or reset one on Windows.
+        Ok(Err(err))
+            if matches!(
+                err.kind(),
+                ErrorKind::ConnectionRefused | ErrorKind::ConnectionReset
+            ) =>
+        {
+            Ok(Some(start.elapsed()))
+        }
+        Ok(Err(_)) | Err(_) => Ok(None),
+    }
+}
+
+/// Send a round of probes to `target`.
+async fn probe_round(target: SocketAddr) -> io::Result<QualityStats> {
+    let mut rtts = Vec::with_capacity(PROBE_COUNT);
+    for index in 0..PROBE_COUNT {
+        if index > 0 {
+            sleep(PROBE_SPACING).await;
+        }
+        if let Some(rtt) = probe_once(target).await? {
+            rtts.push(rtt);
+        }
+    }
+
+    Ok(QualityStats::new(PROBE_COUNT, &rtts))
+}
+
+async fn probe_target(id: Id, connection_type: ConnectionType) -> Result<Option<String>, Error> {
+    let target = match connection_type {
+        ConnectionType::Location => Location::find_by_id(&*DB_POOL, id)
+            .await?
+            .and_then(|location| location.probe_target),
+        ConnectionType::Tunnel => Tunnel::find_by_id(&*DB_POOL, id)
+            .await?
+            .and_then(|tunnel| tunnel.probe_target),
+    };
+    Ok(target)
+}
+
+/// Periodically measure connection quality of a connected location or tunnel, if it has a probe
+/// target. The target is read on every round, so changes apply without reconnecting.
+pub(crate) async fn probe_handler(id: Id, connection_type: ConnectionType) {
+    debug!("Starting connection quality probes for {connection_type} {id}");
+    let mut interval = interval(PROBE_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        let target = match probe_target(id, connection_type).await {
+            Ok(Some(target)) => target,
+            Ok(None) => continue,
+            Err(err) => {
+                error!("Failed to read probe target of {connection_type} {id}: {err}");
+                continue;
+            }
+        };
+        let target = match parse_probe_target(&target) {
+            Ok(target) => target,
+            Err(err) => {
+                warn!("Not probing {connection_type} {id}: {err}");
+                continue;
+            }
+        };
+
+        let quality = match probe_round(target).await {
+            Ok(quality) => quality,
+            Err(err) => {
+                error!("Failed to probe {target} through {connection_type} {id}: {err}");
+                continue;
+            }
+        };
+        trace!("Connection quality of {connection_type} {id}: {quality:?}");
+        let result = match connection_type {
+            ConnectionType::Location => quality.save_for_location(&*DB_POOL, id).await,
+            ConnectionType::Tunnel => quality.save_for_tunnel(&*DB_POOL, id).await,
+        };
+        if let Err(err) = result {
+            error!("Failed to save connection quality of {connection_type} {id}: {err}");
+        }
+    }
+}
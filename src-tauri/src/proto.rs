@@ -41,6 +41,9 @@ impl DeviceConfig {
             keepalive_interval: self.keepalive_interval.into(),
             location_mfa_mode,
             service_location_mode,
+            fwmark: None,
+            route_table: None,
+            probe_target: None,
         }
     }
 }
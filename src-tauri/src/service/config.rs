@@ -1,10 +1,20 @@
+use std::net::SocketAddr;
+
 use clap::Parser;
 
+#[cfg(not(target_os = "macos"))]
+use super::state::RestorePolicy;
+
 #[cfg(windows)]
 pub const DEFAULT_LOG_DIR: &str = "/Logs/defguard-service";
 #[cfg(not(windows))]
 pub const DEFAULT_LOG_DIR: &str = "/var/log/defguard-service";
 
+#[cfg(windows)]
+pub const DEFAULT_STATE_FILE: &str = "/ProgramData/defguard-service/interfaces.json";
+#[cfg(not(windows))]
+pub const DEFAULT_STATE_FILE: &str = "/var/lib/defguard-service/interfaces.json";
+
 #[derive(Debug, Parser, Clone)]
 #[clap(about = "Defguard VPN client interface management service")]
 #[command(version)]
@@ -20,4 +30,24 @@ pub struct Config {
     /// Defines how often (in seconds) interface statistics are sent to defguard client
     #[arg(long, short = 'p', env = "DEFGUARD_STATS_PERIOD", default_value = "10")]
     pub stats_period: u64,
+
+    /// File in which state of managed interfaces is kept, to restore them after a restart
+    #[arg(long, env = "DEFGUARD_STATE_FILE", default_value = DEFAULT_STATE_FILE)]
+    pub state_file: String,
+
+    /// Defines what to do with interfaces left over from a previous run of the service
+    #[cfg(not(target_os = "macos"))]
+    #[arg(long, env = "DEFGUARD_RESTORE_POLICY", value_enum, default_value_t)]
+    pub restore_policy: RestorePolicy,
+
+    /// Address on which metrics are served in OpenMetrics format (e.g. 127.0.0.1:9189);
+    /// metrics are disabled if not set
+    #[arg(long, env = "DEFGUARD_METRICS_ADDRESS")]
+    pub metrics_address: Option<SocketAddr>,
+
+    /// Lets clients block traffic outside the tunnel while connections which route all traffic
+    /// are active; requires nftables
+    #[cfg(target_os = "linux")]
+    #[arg(long, env = "DEFGUARD_KILL_SWITCH")]
+    pub kill_switch: bool,
 }
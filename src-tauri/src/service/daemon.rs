@@ -1,37 +1,58 @@
+#[cfg(target_os = "linux")]
+use std::time::Instant;
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
+    fmt,
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
     pin::Pin,
     sync::{Arc, Mutex, RwLock},
-    time::{Duration, SystemTime},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 #[cfg(unix)]
 use std::{fs, os::unix::fs::PermissionsExt, path::Path};
 
 use common::dns_borrow;
+#[cfg(target_os = "linux")]
+use defguard_wireguard_rs::net::IpAddrMask;
 use defguard_wireguard_rs::{
-    error::WireguardInterfaceError, InterfaceConfiguration, Kernel, WGApi, WireguardInterfaceApi,
+    error::WireguardInterfaceError, host::Host, key::Key, peer::Peer, InterfaceConfiguration,
+    Kernel, WGApi, WireguardInterfaceApi,
 };
 #[cfg(unix)]
 use nix::unistd::{chown, Group};
 #[cfg(unix)]
 use tokio::net::UnixListener;
-use tokio::{sync::mpsc, task::JoinHandle, time::interval};
+use tokio::{
+    select,
+    sync::{broadcast, mpsc},
+    task::JoinHandle,
+    time::interval,
+};
 #[cfg(unix)]
 use tokio_stream::wrappers::UnixListenerStream;
+#[cfg(unix)]
+use tonic::transport::server::UdsConnectInfo;
 use tonic::{
     codegen::tokio_stream::{wrappers::ReceiverStream, Stream},
     transport::Server,
     Code, Response, Status,
 };
-use tracing::{debug, error, info, info_span, Instrument};
+use tracing::{debug, error, info, info_span, warn, Instrument};
 
 use super::{
     config::Config,
+    metrics::{self, Metrics, RpcMetricsLayer},
     proto::{
         desktop_daemon_service_server::{DesktopDaemonService, DesktopDaemonServiceServer},
-        CreateInterfaceRequest, InterfaceData, ReadInterfaceDataRequest, RemoveInterfaceRequest,
+        CreateInterfaceRequest, DnsMode, InterfaceData, InterfaceEvent, InterfaceEventKind,
+        ListInterfacesResponse, ManagedInterface, ReadInterfaceDataRequest, RemoveInterfaceRequest,
+        UpdateInterfaceRequest,
     },
+    state::{self, InterfaceState, RestorePolicy},
 };
+#[cfg(target_os = "linux")]
+use super::{kill_switch, resolved, routing};
 #[cfg(windows)]
 use crate::enterprise::service_locations::ServiceLocationManager;
 #[cfg(windows)]
@@ -48,6 +69,21 @@ pub(super) const DAEMON_SOCKET_PATH: &str = "/var/run/defguard.socket";
 #[cfg(target_os = "linux")]
 pub(super) const DAEMON_SOCKET_GROUP: &str = "defguard";
 
+// Log target of audit records of RPC calls.
+const AUDIT_TARGET: &str = "audit";
+
+// How often interfaces are checked for handshake and endpoint changes.
+const EVENT_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+// Handshake older than this means that the peer is unreachable (WireGuard's `REJECT_AFTER_TIME`).
+const HANDSHAKE_STALE_AFTER: Duration = Duration::from_secs(180);
+const EVENT_CHANNEL_CAPACITY: usize = 64;
+// Subscribers only need the latest statistics, so there's no point in buffering more.
+const STATS_CHANNEL_CAPACITY: usize = 4;
+// How long the kill-switch keeps protecting an interface removed in order to reconnect. Clients
+// reconnect by removing and re-creating interfaces, and traffic mustn't leak in the meantime.
+#[cfg(target_os = "linux")]
+const KILL_SWITCH_RELEASE_DELAY: Duration = Duration::from_secs(30);
+
 #[derive(Debug, thiserror::Error)]
 pub enum DaemonError {
     #[error(transparent)]
@@ -69,12 +105,27 @@ type WG = WGApi<Kernel>;
 #[cfg(target_os = "macos")]
 type WG = WGApi<Userspace>;
 
-#[derive(Default)]
+// Interface event, along with UID of the interface owner.
+type OwnedEvent = (Option<u32>, InterfaceEvent);
+
 pub(crate) struct DaemonService {
     // Map of running `WGApi`s; key is interface name.
     wgapis: Arc<RwLock<HashMap<IfName, WG>>>,
+    // State of managed interfaces; key is interface name.
+    interfaces: Arc<RwLock<HashMap<IfName, InterfaceState>>>,
+    // File in which `interfaces` are persisted.
+    state_file: PathBuf,
     stats_period: Duration,
-    stat_tasks: Arc<Mutex<HashMap<IfName, JoinHandle<()>>>>,
+    // Statistics collectors shared by subscribers; key is interface name.
+    collectors: Arc<Mutex<HashMap<IfName, StatsCollector>>>,
+    events: broadcast::Sender<OwnedEvent>,
+    metrics: Arc<Metrics>,
+    // Whether clients may enable the kill-switch for their full-tunnel interfaces.
+    #[cfg(target_os = "linux")]
+    kill_switch: bool,
+    // Removed interfaces which the kill-switch still protects, until the given time.
+    #[cfg(target_os = "linux")]
+    released: Arc<Mutex<HashMap<IfName, (InterfaceState, Instant)>>>,
     #[cfg(windows)]
     service_location_manager: Arc<RwLock<ServiceLocationManager>>,
 }
@@ -87,12 +138,495 @@ impl DaemonService {
     ) -> Self {
         Self {
             wgapis: Arc::new(RwLock::new(HashMap::new())),
+            interfaces: Arc::new(RwLock::new(HashMap::new())),
+            state_file: PathBuf::from(&config.state_file),
             stats_period: Duration::from_secs(config.stats_period),
-            stat_tasks: Arc::new(Mutex::new(HashMap::new())),
+            collectors: Arc::new(Mutex::new(HashMap::new())),
+            events: broadcast::channel(EVENT_CHANNEL_CAPACITY).0,
+            metrics: Arc::new(Metrics::default()),
+            #[cfg(target_os = "linux")]
+            kill_switch: config.kill_switch,
+            #[cfg(target_os = "linux")]
+            released: Arc::new(Mutex::new(HashMap::new())),
             #[cfg(windows)]
             service_location_manager,
         }
     }
+
+    /// Publish event to clients watching events of interface owned by `owner`.
+    fn emit(&self, owner: Option<u32>, event: InterfaceEvent) {
+        debug!("Publishing interface event: {event:?}");
+        // Sending only fails if nobody is watching.
+        let _ = self.events.send((owner, event));
+    }
+
+    /// Watch managed interfaces for handshake and endpoint changes, and publish them as events.
+    pub(crate) fn spawn_event_monitor(&self) {
+        let wgapis = Arc::clone(&self.wgapis);
+        let interfaces = Arc::clone(&self.interfaces);
+        let events = self.events.clone();
+        tokio::spawn(async move {
+            let mut interval = interval(EVENT_CHECK_INTERVAL);
+            // Last known state of peers; key is interface name and peer public key.
+            let mut peers: HashMap<(IfName, Key), PeerState> = HashMap::new();
+            // Peers found during the first check have been connected before the daemon started.
+            let mut first_check = true;
+            loop {
+                interval.tick().await;
+                let hosts: Vec<_> = {
+                    let (Ok(wgapis_map), Ok(interfaces)) = (wgapis.read(), interfaces.read())
+                    else {
+                        error!("Failed to acquire read-write lock for WGApis");
+                        break;
+                    };
+                    wgapis_map
+                        .iter()
+                        .filter_map(|(ifname, wgapi)| {
+                            let owner = interfaces.get(ifname).and_then(|state| state.owner);
+                            wgapi
+                                .read_interface_data()
+                                .ok()
+                                .map(|host| (ifname.clone(), owner, host))
+                        })
+                        .collect()
+                };
+
+                let now = SystemTime::now();
+                let mut seen = HashSet::new();
+                for (ifname, owner, host) in hosts {
+                    for (public_key, peer) in host.peers {
+                        let state = PeerState::new(&peer, now);
+                        let key = (ifname.clone(), public_key);
+                        let previous = match peers.insert(key.clone(), state) {
+                            Some(previous) => previous,
+                            None if first_check => state,
+                            None => PeerState::default(),
+                        };
+                        for kind in state.changes(&previous) {
+                            let mut event = interface_event(&ifname, kind);
+                            event.peer_public_key = Some(key.1.to_lower_hex());
+                            event.endpoint = state.endpoint.map(|addr| addr.to_string());
+                            let _ = events.send((owner, event));
+                        }
+                        seen.insert(key);
+                    }
+                }
+                peers.retain(|key, _| seen.contains(key));
+                first_check = false;
+            }
+            error!("Interface event monitor has stopped");
+        });
+    }
+
+    /// Serve metrics of managed interfaces on `address`.
+    pub(crate) async fn spawn_metrics_server(&self, address: SocketAddr) -> std::io::Result<()> {
+        let listener = metrics::bind(address).await?;
+        let wgapis = Arc::clone(&self.wgapis);
+        let metrics = Arc::clone(&self.metrics);
+        tokio::spawn(metrics::serve(listener, move || {
+            let Ok(wgapis_map) = wgapis.read() else {
+                error!("Failed to acquire read-write lock for WGApis");
+                return metrics.render(0, &[]);
+            };
+            let hosts: Vec<_> = wgapis_map
+                .iter()
+                .filter_map(|(ifname, wgapi)| match wgapi.read_interface_data() {
+                    Ok(host) => Some((ifname.clone(), host)),
+                    Err(err) => {
+                        warn!("Failed to read metrics of interface {ifname}: {err}");
+                        None
+                    }
+                })
+                .collect();
+            metrics.render(wgapis_map.len(), &hosts)
+        }));
+        Ok(())
+    }
+
+    /// Layer counting gRPC calls handled by this service.
+    pub(crate) fn metrics_layer(&self) -> RpcMetricsLayer {
+        RpcMetricsLayer::new(Arc::clone(&self.metrics))
+    }
+
+    /// Subscribe to statistics of interface `ifname`, spawning its collector if there's none.
+    fn subscribe_stats(&self, ifname: &str) -> Result<broadcast::Receiver<Host>, Status> {
+        // Collectors check for subscribers with the lock held, so they can't leave a new one
+        // without statistics.
+        let Ok(mut collectors) = self.collectors.lock() else {
+            error!("Failed to acquire lock for statistics collectors");
+            return Err(Status::new(Code::Internal, "lock error"));
+        };
+        if let Some(collector) = collectors.get(ifname) {
+            if !collector.handle.is_finished() {
+                debug!("Subscribing to running statistics collector for interface {ifname}");
+                return Ok(collector.sender.subscribe());
+            }
+        }
+
+        info!("Spawning statistics collector task for interface {ifname}");
+        let (sender, receiver) = broadcast::channel(STATS_CHANNEL_CAPACITY);
+        let handle = tokio::spawn(
+            collect_stats(
+                ifname.to_string(),
+                Arc::clone(&self.wgapis),
+                Arc::clone(&self.collectors),
+                sender.clone(),
+                self.stats_period,
+            )
+            .instrument(info_span!("collect_stats", interface_name = ifname)),
+        );
+        collectors.insert(ifname.to_string(), StatsCollector { sender, handle });
+
+        Ok(receiver)
+    }
+
+    /// Read current statistics of interface `ifname`, outside of its collector.
+    fn read_stats(&self, ifname: &str) -> Option<Host> {
+        let Ok(wgapis_map) = self.wgapis.read() else {
+            error!("Failed to acquire read-write lock for WGApis");
+            return None;
+        };
+        match wgapis_map.get(ifname)?.read_interface_data() {
+            Ok(host) => Some(host),
+            Err(err) => {
+                warn!("Failed to retrieve network usage stats for interface {ifname}: {err}");
+                None
+            }
+        }
+    }
+
+    /// Make the kill-switch protect interfaces which route all traffic and have requested it.
+    #[cfg(target_os = "linux")]
+    fn apply_kill_switch(
+        &self,
+        interfaces: &HashMap<IfName, InterfaceState>,
+    ) -> Result<(), Status> {
+        if !self.kill_switch {
+            return Ok(());
+        }
+        apply_kill_switch(interfaces, &self.released)
+    }
+
+    /// Keep the kill-switch protecting interface `ifname`, which has just been removed, for
+    /// [`KILL_SWITCH_RELEASE_DELAY`]. The kill-switch is re-applied once the delay passes.
+    #[cfg(target_os = "linux")]
+    fn release_kill_switch(&self, ifname: &str, state: InterfaceState) {
+        if !self.kill_switch || !state.request.kill_switch {
+            return;
+        }
+        let Ok(mut released) = self.released.lock() else {
+            error!("Failed to acquire lock for interfaces released by the kill-switch");
+            return;
+        };
+        debug!(
+            "Interface {ifname} stays protected by the kill-switch for {}s",
+            KILL_SWITCH_RELEASE_DELAY.as_secs()
+        );
+        released.insert(
+            ifname.to_string(),
+            (state, Instant::now() + KILL_SWITCH_RELEASE_DELAY),
+        );
+
+        let interfaces = Arc::clone(&self.interfaces);
+        let released = Arc::clone(&self.released);
+        tokio::spawn(async move {
+            tokio::time::sleep(KILL_SWITCH_RELEASE_DELAY).await;
+            let Ok(interfaces) = interfaces.read() else {
+                error!("Failed to acquire read-write lock for interfaces");
+                return;
+            };
+            // Errors are logged; there's nothing more to do about them here.
+            let _ = apply_kill_switch(&interfaces, &released);
+        });
+    }
+
+    /// Dummy version of the above function for non-Linux systems.
+    #[cfg(not(target_os = "linux"))]
+    fn apply_kill_switch(
+        &self,
+        _interfaces: &HashMap<IfName, InterfaceState>,
+    ) -> Result<(), Status> {
+        Ok(())
+    }
+
+    /// Replace kill-switch rules left over from a previous run with ones protecting `interfaces`.
+    /// The table is removed if none of them is protected, or if the kill-switch has been disabled
+    /// since.
+    #[cfg(target_os = "linux")]
+    fn reset_kill_switch(&self, interfaces: &HashMap<IfName, InterfaceState>) {
+        if self.kill_switch {
+            // Errors are logged; there's nothing more to do about them here.
+            let _ = self.apply_kill_switch(interfaces);
+        } else if let Err(err) = kill_switch::clear() {
+            error!("Failed to remove kill-switch rules: {err}");
+        }
+    }
+
+    /// Dummy version of the above function for non-Linux systems.
+    #[cfg(not(target_os = "linux"))]
+    fn release_kill_switch(&self, _ifname: &str, _state: InterfaceState) {}
+
+    /// Dummy version of the above function for non-Linux systems.
+    #[cfg(not(target_os = "linux"))]
+    fn reset_kill_switch(&self, _interfaces: &HashMap<IfName, InterfaceState>) {}
+
+    /// Apply `link` configuration of a new or updated interface. DNS is configured globally if
+    /// split DNS fails.
+    #[cfg(target_os = "linux")]
+    async fn configure_link(&self, link: LinkSetup) -> Result<(), Status> {
+        let ifname = link.ifname.clone();
+        let fallback = tokio::task::spawn_blocking(move || link.apply())
+            .await
+            .map_err(|err| {
+                let msg = format!("Failed to configure interface {ifname}: {err}");
+                error!("{msg}");
+                Status::new(Code::Internal, msg)
+            })??;
+        let Some((dns, search_domains)) = fallback else {
+            return Ok(());
+        };
+
+        let Ok(mut wgapis_map) = self.wgapis.write() else {
+            error!("Failed to acquire read-write lock for WGApis");
+            return Err(Status::new(Code::Internal, "read-write lock error"));
+        };
+        let Some(wgapi) = wgapis_map.get_mut(&ifname) else {
+            error!("Interface {ifname} has been removed while being configured");
+            return Err(Status::new(Code::Internal, "unknown interface"));
+        };
+        let search_domains: Vec<_> = search_domains.iter().map(String::as_str).collect();
+        configure_global_dns(&ifname, wgapi, &dns, &search_domains)
+    }
+
+    /// Remove new interface `ifname`, which has failed to be set up with `err`, along with its
+    /// `WGApi`.
+    fn discard_interface(
+        &self,
+        ifname: &str,
+        owner: Option<u32>,
+        err: &Status,
+    ) -> Result<(), Status> {
+        let mut event = interface_event(ifname, InterfaceEventKind::ConfigurationFailed);
+        event.message = Some(err.message().to_string());
+        self.emit(owner, event);
+
+        let wgapi = match self.wgapis.write() {
+            Ok(mut wgapis_map) => wgapis_map.remove(ifname),
+            Err(_) => {
+                error!("Failed to acquire read-write lock for WGApis");
+                return Err(Status::new(Code::Internal, "read-write lock error"));
+            }
+        };
+        let Some(mut wgapi) = wgapi else {
+            return Ok(());
+        };
+        wgapi.remove_interface().map_err(|err| {
+            let msg = format!("Failed to remove WireGuard interface {ifname}: {err}");
+            error!("{msg}");
+            Status::new(Code::Internal, msg)
+        })
+    }
+
+    /// Persist state of managed interfaces.
+    fn save_state(&self, interfaces: &HashMap<IfName, InterfaceState>) {
+        if let Err(err) = state::save(&self.state_file, interfaces) {
+            error!(
+                "Failed to save state of managed interfaces to {:?}: {err}",
+                self.state_file
+            );
+        }
+    }
+
+    /// Deal with interfaces created before the daemon has been restarted, according to `policy`.
+    /// Interfaces which no longer exist are forgotten.
+    pub(crate) fn restore_interfaces(&self, policy: RestorePolicy) {
+        let saved = state::load(&self.state_file);
+        if saved.is_empty() {
+            // Rules of an interface removed just before the restart may still be in place.
+            self.reset_kill_switch(&HashMap::new());
+            return;
+        }
+        info!(
+            "Found {} interfaces left over from a previous run, restore policy: {policy:?}",
+            saved.len()
+        );
+
+        let (Ok(mut wgapis_map), Ok(mut interfaces)) =
+            (self.wgapis.write(), self.interfaces.write())
+        else {
+            error!("Failed to acquire read-write lock for WGApis");
+            return;
+        };
+        for (ifname, mut interface) in saved {
+            let Ok(mut wgapi) = setup_wgapi(&ifname) else {
+                continue;
+            };
+            // Interfaces are gone after a reboot.
+            let host = match wgapi.read_interface_data() {
+                Ok(host) => host,
+                Err(err) => {
+                    debug!("Interface {ifname} no longer exists, forgetting it: {err}");
+                    continue;
+                }
+            };
+            match policy {
+                RestorePolicy::Adopt => {
+                    info!("Adopting interface {ifname}");
+                    if let Some(key) = &host.private_key {
+                        interface.restore_private_key(key);
+                    }
+                    wgapis_map.insert(ifname.clone(), wgapi);
+                    interfaces.insert(ifname, interface);
+                }
+                RestorePolicy::Remove => {
+                    info!("Removing interface {ifname}");
+                    let endpoint = interface
+                        .request
+                        .config
+                        .as_ref()
+                        .and_then(|config| config.peers.first())
+                        .and_then(|peer| peer.endpoint.clone())
+                        .unwrap_or_default();
+                    if let Err(err) = remove_wireguard_interface(&ifname, &mut wgapi, &endpoint) {
+                        warn!("Interface {ifname} may need to be removed manually: {err}");
+                    }
+                }
+            }
+        }
+        // Adopted interfaces stay protected; rules of removed ones are dropped.
+        self.reset_kill_switch(&interfaces);
+        self.save_state(&interfaces);
+    }
+
+    /// Check if `caller` may manage interface `ifname`, and log an audit record of the call.
+    fn authorize(&self, rpc: &str, caller: &Caller, ifname: &str) -> Result<(), Status> {
+        let Ok(interfaces) = self.interfaces.read() else {
+            error!("Failed to acquire read-write lock for interfaces");
+            return Err(Status::new(Code::Internal, "read-write lock error"));
+        };
+        let owner = interfaces.get(ifname).map(|interface| interface.owner);
+        // Interfaces which aren't managed yet may be created by anyone allowed to connect.
+        if owner.is_none_or(|owner| caller.may_manage(owner)) {
+            info!(target: AUDIT_TARGET, "{rpc}: {caller} allowed to access interface {ifname}");
+            Ok(())
+        } else {
+            warn!(
+                target: AUDIT_TARGET,
+                "{rpc}: {caller} denied access to interface {ifname} owned by {}",
+                owner
+                    .flatten()
+                    .map_or("unknown user".to_string(), |uid| format!("UID {uid}"))
+            );
+            Err(Status::new(
+                Code::PermissionDenied,
+                format!("interface {ifname} belongs to another user"),
+            ))
+        }
+    }
+}
+
+/// Client process which has sent a request.
+#[derive(Clone, Copy, Default)]
+struct Caller {
+    uid: Option<u32>,
+    pid: Option<i32>,
+}
+
+impl Caller {
+    /// Read peer credentials of the Unix socket connection (`SO_PEERCRED` on Linux).
+    #[cfg(unix)]
+    fn from_request<T>(request: &tonic::Request<T>) -> Self {
+        let credentials = request
+            .extensions()
+            .get::<UdsConnectInfo>()
+            .and_then(|info| info.peer_cred);
+        Self {
+            uid: credentials.map(|credentials| credentials.uid()),
+            pid: credentials.and_then(|credentials| credentials.pid()),
+        }
+    }
+
+    /// Dummy version of the above function for non-UNIX systems.
+    #[cfg(not(unix))]
+    fn from_request<T>(_request: &tonic::Request<T>) -> Self {
+        Self::default()
+    }
+
+    /// Root may manage all interfaces; other users only the ones they have created. Interfaces of
+    /// unknown owners, e.g. adopted from state files of older versions, are left to root.
+    #[cfg(unix)]
+    fn may_manage(&self, owner: Option<u32>) -> bool {
+        match (self.uid, owner) {
+            (Some(0), _) => true,
+            (Some(uid), Some(owner)) => uid == owner,
+            _ => false,
+        }
+    }
+
+    /// Callers can't be identified on non-UNIX systems, so access is only limited by the named
+    /// pipe's permissions.
+    #[cfg(not(unix))]
+    fn may_manage(&self, _owner: Option<u32>) -> bool {
+        true
+    }
+}
+
+impl fmt::Display for Caller {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.uid, self.pid) {
+            (Some(uid), Some(pid)) => write!(f, "UID {uid} (PID {pid})"),
+            (Some(uid), None) => write!(f, "UID {uid}"),
+            _ => write!(f, "unknown user"),
+        }
+    }
+}
+
+/// State of a peer, tracked to detect handshake and endpoint changes.
+#[derive(Clone, Copy, Default)]
+struct PeerState {
+    handshake: Option<SystemTime>,
+    stale: bool,
+    endpoint: Option<SocketAddr>,
+}
+
+impl PeerState {
+    fn new(peer: &Peer, now: SystemTime) -> Self {
+        let handshake = peer.last_handshake.filter(|time| *time != UNIX_EPOCH);
+        Self {
+            handshake,
+            stale: handshake.is_some_and(|time| {
+                now.duration_since(time).unwrap_or_default() > HANDSHAKE_STALE_AFTER
+            }),
+            endpoint: peer.endpoint,
+        }
+    }
+
+    /// Events caused by changes since `previous` state.
+    fn changes(&self, previous: &Self) -> Vec<InterfaceEventKind> {
+        let mut changes = Vec::new();
+        if previous.handshake.is_none() && self.handshake.is_some() {
+            changes.push(InterfaceEventKind::FirstHandshake);
+        }
+        if !previous.stale && self.stale {
+            changes.push(InterfaceEventKind::HandshakeStale);
+        }
+        if previous.endpoint.is_some() && previous.endpoint != self.endpoint {
+            changes.push(InterfaceEventKind::EndpointChanged);
+        }
+        changes
+    }
+}
+
+fn interface_event(ifname: &str, kind: InterfaceEventKind) -> InterfaceEvent {
+    InterfaceEvent {
+        interface_name: ifname.to_string(),
+        kind: kind.into(),
+        timestamp: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs(),
+        ..Default::default()
+    }
 }
 
 /// Helper function used to perform required configuration steps for a new interface.
@@ -103,6 +637,7 @@ fn configure_new_interface(
     request: &CreateInterfaceRequest,
     wgapi: &mut WGApi,
     interface_config: &InterfaceConfiguration,
+    #[cfg(target_os = "linux")] link: &mut LinkSetup,
 ) -> Result<(), Status> {
     // The WireGuard DNS config value can be a list of IP addresses and domain names, which will
     // be used as DNS servers and search domains respectively.
@@ -122,38 +657,411 @@ fn configure_new_interface(
     })?;
 
     #[cfg(not(windows))]
-    {
-        debug!("Configuring interface {ifname} routing");
-        wgapi
-            .configure_peer_routing(&interface_config.peers)
-            .map_err(|err| {
-                let msg =
-                    format!("Failed to configure routing for WireGuard interface {ifname}: {err}");
-                error!("{msg}");
-                Status::new(Code::Internal, msg)
-            })?;
-    }
+    configure_interface_routing(
+        ifname,
+        wgapi,
+        &interface_config.peers,
+        request.route_table,
+        #[cfg(target_os = "linux")]
+        link,
+    )?;
     if dns.is_empty() {
         debug!(
             "No DNS configuration provided for interface {ifname}, skipping DNS \
                 configuration"
         );
     } else {
+        configure_interface_dns(
+            ifname,
+            wgapi,
+            request.dns_mode(),
+            &dns,
+            &search_domains,
+            #[cfg(target_os = "linux")]
+            link,
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Configure routing of interface `ifname`. Allowed IPs of peers are routed through a dedicated
+/// routing table if one was requested (on Linux only, as part of `link`), or through the main one
+/// otherwise.
+#[cfg(not(windows))]
+fn configure_interface_routing(
+    ifname: &str,
+    wgapi: &mut WG,
+    peers: &[Peer],
+    route_table: Option<u32>,
+    #[cfg(target_os = "linux")] link: &mut LinkSetup,
+) -> Result<(), Status> {
+    #[cfg(target_os = "linux")]
+    if let Some(table) = route_table {
+        link.routes = Some((
+            table,
+            peers
+                .iter()
+                .flat_map(|peer| peer.allowed_ips.iter().cloned())
+                .collect(),
+        ));
+        return Ok(());
+    }
+    #[cfg(not(target_os = "linux"))]
+    if let Some(table) = route_table {
+        warn!(
+            "Routing tables are supported on Linux only, ignoring table {table} of interface \
+            {ifname}"
+        );
+    }
+
+    debug!("Configuring interface {ifname} routing");
+    wgapi.configure_peer_routing(peers).map_err(|err| {
+        let msg = format!("Failed to configure routing for WireGuard interface {ifname}: {err}");
+        error!("{msg}");
+        Status::new(Code::Internal, msg)
+    })
+}
+
+/// Configure DNS of interface `ifname`. In split mode, resolvers are only used for names in
+/// search domains (on Linux with systemd-resolved, as part of `link`); without search domains
+/// they're configured globally, as in global mode, which is used by connections which route all
+/// traffic.
+fn configure_interface_dns(
+    ifname: &str,
+    wgapi: &mut WG,
+    mode: DnsMode,
+    dns: &[IpAddr],
+    search_domains: &[&str],
+    #[cfg(target_os = "linux")] link: &mut LinkSetup,
+) -> Result<(), Status> {
+    debug!(
+        "The following DNS servers will be set: {dns:?}, search domains: {search_domains:?}, \
+        mode: {mode:?}"
+    );
+    #[cfg(target_os = "linux")]
+    if mode == DnsMode::Split && resolved::routing_domains(search_domains).is_empty() {
+        // Resolvers wouldn't be used for any names otherwise.
+        info!("No search domains for split DNS of interface {ifname}, configuring DNS globally");
+    } else if mode == DnsMode::Split {
+        link.split_dns = Some((
+            dns.to_vec(),
+            search_domains.iter().map(ToString::to_string).collect(),
+        ));
+        return Ok(());
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = mode;
+
+    configure_global_dns(ifname, wgapi, dns, search_domains)
+}
+
+fn configure_global_dns(
+    ifname: &str,
+    wgapi: &mut WG,
+    dns: &[IpAddr],
+    search_domains: &[&str],
+) -> Result<(), Status> {
+    wgapi.configure_dns(dns, search_domains).map_err(|err| {
+        let msg = format!("Failed to configure DNS for WireGuard interface {ifname}: {err}");
+        error!("{msg}");
+        Status::new(Code::Internal, msg)
+    })
+}
+
+/// Configuration of an interface done with `ip` and systemd-resolved rather than its `WGApi`.
+/// Those may block, so it's applied on a blocking thread, without holding locks of the daemon.
+#[cfg(target_os = "linux")]
+#[derive(Debug)]
+struct LinkSetup {
+    ifname: IfName,
+    /// Routing table to remove routes of the interface from.
+    flush_table: Option<u32>,
+    /// Routing table to route allowed IPs through, along with the allowed IPs.
+    routes: Option<(u32, Vec<IpAddrMask>)>,
+    /// Resolvers to use for names in search domains only, along with the domains.
+    split_dns: Option<(Vec<IpAddr>, Vec<String>)>,
+}
+
+#[cfg(target_os = "linux")]
+impl LinkSetup {
+    #[must_use]
+    fn new(ifname: &str) -> Self {
+        Self {
+            ifname: ifname.to_string(),
+            flush_table: None,
+            routes: None,
+            split_dns: None,
+        }
+    }
+
+    /// Returns resolvers and search domains which have to be configured globally, as split DNS
+    /// has failed.
+    fn apply(self) -> Result<Option<(Vec<IpAddr>, Vec<String>)>, Status> {
+        let ifname = &self.ifname;
+        if let Some(table) = self.flush_table {
+            debug!("Removing routes of interface {ifname} from table {table}");
+            routing::flush_routes(ifname, table).map_err(|err| {
+                let msg = format!(
+                    "Failed to remove routes of interface {ifname} from table {table}: {err}"
+                );
+                error!("{msg}");
+                Status::new(Code::Internal, msg)
+            })?;
+        }
+        if let Some((table, allowed_ips)) = &self.routes {
+            debug!("Configuring interface {ifname} routing in table {table}");
+            routing::add_routes(ifname, *table, allowed_ips).map_err(|err| {
+                let msg = format!(
+                    "Failed to configure routing in table {table} for WireGuard interface \
+                    {ifname}: {err}"
+                );
+                error!("{msg}");
+                Status::new(Code::Internal, msg)
+            })?;
+        }
+        if let Some((dns, search_domains)) = self.split_dns {
+            let domains: Vec<_> = search_domains.iter().map(String::as_str).collect();
+            match resolved::configure_split_dns(ifname, &dns, &domains) {
+                Ok(()) => info!("Configured split DNS for interface {ifname}"),
+                Err(err) => {
+                    warn!(
+                        "Failed to configure split DNS for interface {ifname}, falling back to \
+                        global DNS configuration: {err}"
+                    );
+                    return Ok(Some((dns, search_domains)));
+                }
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+/// Interfaces to apply the kill-switch to: managed ones, along with removed ones which are still
+/// protected. Interfaces are protected if they route all traffic and have requested it.
+#[cfg(target_os = "linux")]
+fn kill_switch_interfaces(
+    interfaces: &HashMap<IfName, InterfaceState>,
+    released: &mut HashMap<IfName, (InterfaceState, Instant)>,
+    now: Instant,
+) -> Vec<kill_switch::Interface> {
+    released.retain(|ifname, (_, until)| *until > now && !interfaces.contains_key(ifname));
+    interfaces
+        .iter()
+        .chain(released.iter().map(|(ifname, (state, _))| (ifname, state)))
+        .filter_map(|(ifname, state)| {
+            let config = state.request.config.as_ref()?;
+            let routes_all_traffic = config
+                .peers
+                .iter()
+                .flat_map(|peer| &peer.allowed_ips)
+                .any(|ip| ip.trim().ends_with("/0"));
+            Some(kill_switch::Interface {
+                ifname: ifname.clone(),
+                endpoints: config
+                    .peers
+                    .iter()
+                    .filter_map(|peer| peer.endpoint.as_ref()?.parse().ok())
+                    .collect(),
+                protected: state.request.kill_switch && routes_all_traffic,
+            })
+        })
+        .collect()
+}
+
+#[cfg(target_os = "linux")]
+fn apply_kill_switch(
+    interfaces: &HashMap<IfName, InterfaceState>,
+    released: &Mutex<HashMap<IfName, (InterfaceState, Instant)>>,
+) -> Result<(), Status> {
+    let Ok(mut released) = released.lock() else {
+        error!("Failed to acquire lock for interfaces released by the kill-switch");
+        return Err(Status::new(Code::Internal, "lock error"));
+    };
+    let interfaces = kill_switch_interfaces(interfaces, &mut released, Instant::now());
+    kill_switch::apply(&interfaces).map_err(|err| {
+        let msg = format!("Failed to apply kill-switch rules: {err}");
+        error!("{msg}");
+        Status::new(Code::Internal, msg)
+    })
+}
+
+/// Apply new configuration to a live interface without re-creating it, so that established
+/// connections aren't dropped.
+fn reconfigure_interface(
+    ifname: &str,
+    wgapi: &mut WG,
+    current: &InterfaceConfiguration,
+    new: &InterfaceConfiguration,
+    current_request: &CreateInterfaceRequest,
+    new_request: &CreateInterfaceRequest,
+    #[cfg(target_os = "linux")] link: &mut LinkSetup,
+) -> Result<(), Status> {
+    let (dns, search_domains) = dns_borrow(&new_request.dns);
+    let dns_changed =
+        new_request.dns != current_request.dns || new_request.dns_mode != current_request.dns_mode;
+    if dns_changed && dns.is_empty() {
+        let msg = format!("DNS can't be removed from live interface {ifname}");
+        error!("{msg}");
+        return Err(Status::new(Code::FailedPrecondition, msg));
+    }
+
+    if new.addresses != current.addresses
+        || new.mtu != current.mtu
+        || new.prvkey != current.prvkey
+        || new.fwmark != current.fwmark
+    {
         debug!(
-            "The following DNS servers will be set: {dns:?}, search domains: \
-                {search_domains:?}"
+            "Addresses, MTU, private key or firewall mark of interface {ifname} changed, \
+            reconfiguring it"
         );
-        wgapi.configure_dns(&dns, &search_domains).map_err(|err| {
-            let msg = format!("Failed to configure DNS for WireGuard interface {ifname}: {err}");
+        wgapi.configure_interface(new).map_err(|err| {
+            let msg = format!("Failed to configure WireGuard interface {ifname}: {err}");
             error!("{msg}");
             Status::new(Code::Internal, msg)
         })?;
+    } else {
+        for peer in &current.peers {
+            if !new
+                .peers
+                .iter()
+                .any(|new| new.public_key == peer.public_key)
+            {
+                debug!("Removing peer {} from interface {ifname}", peer.public_key);
+                wgapi.remove_peer(&peer.public_key).map_err(|err| {
+                    let msg = format!("Failed to remove peer from interface {ifname}: {err}");
+                    error!("{msg}");
+                    Status::new(Code::Internal, msg)
+                })?;
+            }
+        }
+        for peer in &new.peers {
+            debug!("Configuring peer {} on interface {ifname}", peer.public_key);
+            wgapi.configure_peer(peer).map_err(|err| {
+                let msg = format!("Failed to configure peer on interface {ifname}: {err}");
+                error!("{msg}");
+                Status::new(Code::Internal, msg)
+            })?;
+        }
+    }
+
+    // Routes in the previous table are left behind otherwise.
+    #[cfg(target_os = "linux")]
+    if current_request.route_table != new_request.route_table {
+        link.flush_table = current_request.route_table;
+    }
+    #[cfg(not(windows))]
+    configure_interface_routing(
+        ifname,
+        wgapi,
+        &new.peers,
+        new_request.route_table,
+        #[cfg(target_os = "linux")]
+        link,
+    )?;
+    if dns_changed {
+        configure_interface_dns(
+            ifname,
+            wgapi,
+            new_request.dns_mode(),
+            &dns,
+            &search_domains,
+            #[cfg(target_os = "linux")]
+            link,
+        )?;
     }
 
     Ok(())
 }
 
+/// Remove WireGuard interface along with its endpoint routing.
+fn remove_wireguard_interface(ifname: &str, wgapi: &mut WG, endpoint: &str) -> Result<(), Status> {
+    #[cfg(not(windows))]
+    {
+        debug!("Cleaning up interface {ifname} routing");
+        // Ignore error as this should not be considered fatal,
+        // e.g. endpoint might fail to resolve DNS name.
+        if let Err(err) = wgapi.remove_endpoint_routing(endpoint) {
+            error!("Failed to remove routing for endpoint {endpoint}: {err}");
+        }
+    }
+    #[cfg(windows)]
+    let _ = endpoint;
+
+    wgapi.remove_interface().map_err(|err| {
+        let msg = format!("Failed to remove WireGuard interface {ifname}: {err}");
+        error!("{msg}");
+        Status::new(Code::Internal, msg)
+    })
+}
+
+/// Statistics collector, shared by all subscribers of an interface.
+struct StatsCollector {
+    sender: broadcast::Sender<Host>,
+    handle: JoinHandle<()>,
+}
+
+/// Periodically read statistics of interface `ifname` and publish them to subscribers, until the
+/// last one leaves or the interface can't be read anymore.
+async fn collect_stats(
+    ifname: IfName,
+    wgapis: Arc<RwLock<HashMap<IfName, WG>>>,
+    collectors: Arc<Mutex<HashMap<IfName, StatsCollector>>>,
+    sender: broadcast::Sender<Host>,
+    period: Duration,
+) {
+    let mut interval = interval(period);
+    loop {
+        interval.tick().await;
+        debug!("Gathering network usage statistics for client's network activity on {ifname}");
+        let result = {
+            let Ok(wgapis_map) = wgapis.read() else {
+                error!("Failed to acquire read-write lock for WGApis");
+                break;
+            };
+            let Some(wgapi) = wgapis_map.get(&ifname) else {
+                error!("Unknown interface {ifname}");
+                break;
+            };
+            wgapi.read_interface_data()
+        };
+        match result {
+            // Sending only fails if there are no subscribers, which is handled below.
+            Ok(host) => {
+                let _ = sender.send(host);
+            }
+            Err(err) => {
+                error!("Failed to retrieve network usage stats for interface {ifname}: {err}");
+                break;
+            }
+        }
+
+        let Ok(collectors) = collectors.lock() else {
+            error!("Failed to acquire lock for statistics collectors");
+            break;
+        };
+        if sender.receiver_count() == 0 {
+            debug!("The last subscriber of interface {ifname} statistics has left");
+            drop(collectors);
+            break;
+        }
+    }
+
+    // Remove this collector, unless it has been replaced already.
+    if let Ok(mut collectors) = collectors.lock() {
+        if collectors
+            .get(&ifname)
+            .is_some_and(|collector| collector.sender.same_channel(&sender))
+        {
+            collectors.remove(&ifname);
+        }
+    }
+    info!("Statistics collector task for interface {ifname} has stopped");
+}
+
 type InterfaceDataStream = Pin<Box<dyn Stream<Item = Result<InterfaceData, Status>> + Send>>;
+type InterfaceEventStream = Pin<Box<dyn Stream<Item = Result<InterfaceEvent, Status>> + Send>>;
 
 pub(crate) fn setup_wgapi(ifname: &str) -> Result<WG, Status> {
     let wgapi = WG::new(ifname).map_err(|err| {
@@ -168,6 +1076,7 @@ pub(crate) fn setup_wgapi(ifname: &str) -> Result<WG, Status> {
 #[tonic::async_trait]
 impl DesktopDaemonService for DaemonService {
     type ReadInterfaceDataStream = InterfaceDataStream;
+    type WatchEventsStream = InterfaceEventStream;
 
     #[cfg(not(windows))]
     async fn save_service_locations(
@@ -285,6 +1194,7 @@ impl DesktopDaemonService for DaemonService {
         request: tonic::Request<CreateInterfaceRequest>,
     ) -> Result<Response<()>, Status> {
         debug!("Received a request to create a new interface");
+        let caller = Caller::from_request(&request);
         let request = request.into_inner();
         let config: InterfaceConfiguration = request
             .config
@@ -295,45 +1205,93 @@ impl DesktopDaemonService for DaemonService {
             ))?
             .into();
         let ifname = &config.name;
-        let _span = info_span!("create_interface", interface_name = &ifname).entered();
-        // Setup WireGuard API.
-        let Ok(mut wgapis_map) = self.wgapis.write() else {
-            error!("Failed to acquire read-write lock for WGApis");
-            return Err(Status::new(Code::Internal, "read-write lock error"));
-        };
-        let wgapi = wgapis_map
-            .entry(ifname.clone())
-            .or_insert(setup_wgapi(ifname)?);
-
-        // create new interface
-        debug!("Creating new interface {ifname}");
-        wgapi.create_interface().map_err(|err| {
-            let msg = format!("Failed to create WireGuard interface {ifname}: {err}");
-            error!("{msg}");
-            Status::new(Code::Internal, msg)
-        })?;
-        info!("Done creating a new interface {ifname}");
+        let span = info_span!("create_interface", interface_name = &ifname);
+        async {
+            // Don't let users reconfigure interfaces of others.
+            self.authorize("create_interface", &caller, ifname)?;
+            #[cfg(target_os = "linux")]
+            let mut link = LinkSetup::new(ifname);
+            let result = {
+                // Setup WireGuard API.
+                let Ok(mut wgapis_map) = self.wgapis.write() else {
+                    error!("Failed to acquire read-write lock for WGApis");
+                    return Err(Status::new(Code::Internal, "read-write lock error"));
+                };
+                let wgapi = wgapis_map
+                    .entry(ifname.clone())
+                    .or_insert(setup_wgapi(ifname)?);
 
-        // attempt to configure new interface
-        // remove interface if configuration fails to avoid duplicate interfaces
-        match configure_new_interface(ifname, &request, wgapi, &config) {
-            Ok(()) => info!("Finished configuring new interface {ifname}"),
-            Err(err) => {
-                error!("Failed to configure interface {ifname}. Error: {err}");
-
-                debug!("Removing newly created interface {ifname} due to configuration failure");
-                wgapi.remove_interface().map_err(|err| {
-                    let msg = format!("Failed to remove WireGuard interface {ifname}: {err}");
+                // create new interface
+                debug!("Creating new interface {ifname}");
+                wgapi.create_interface().map_err(|err| {
+                    let msg = format!("Failed to create WireGuard interface {ifname}: {err}");
                     error!("{msg}");
                     Status::new(Code::Internal, msg)
                 })?;
+                info!("Done creating a new interface {ifname}");
+
+                configure_new_interface(
+                    ifname,
+                    &request,
+                    wgapi,
+                    &config,
+                    #[cfg(target_os = "linux")]
+                    &mut link,
+                )
+            };
+            #[cfg(target_os = "linux")]
+            let result = match result {
+                Ok(()) => self.configure_link(link).await,
+                Err(err) => Err(err),
+            };
 
+            // remove interface if configuration fails to avoid duplicate interfaces
+            if let Err(err) = result {
+                error!("Failed to configure interface {ifname}. Error: {err}");
+                debug!("Removing newly created interface {ifname} due to configuration failure");
+                self.discard_interface(ifname, caller.uid, &err)?;
                 return Err(err);
             }
-        }
+            info!("Finished configuring new interface {ifname}");
 
-        debug!("Finished creating a new interface {ifname}");
-        Ok(Response::new(()))
+            let kill_switch_result = match self.interfaces.write() {
+                Ok(mut interfaces) => {
+                    interfaces.insert(
+                        ifname.clone(),
+                        InterfaceState {
+                            request,
+                            owner: caller.uid,
+                        },
+                    );
+                    // Don't leave the interface unprotected if the kill-switch can't be enabled.
+                    let result = self.apply_kill_switch(&interfaces);
+                    if result.is_ok() {
+                        self.save_state(&interfaces);
+                    } else {
+                        interfaces.remove(ifname);
+                    }
+                    result
+                }
+                Err(_) => {
+                    error!("Failed to acquire read-write lock for interfaces");
+                    Ok(())
+                }
+            };
+            if let Err(err) = kill_switch_result {
+                debug!("Removing newly created interface {ifname} due to kill-switch failure");
+                self.discard_interface(ifname, caller.uid, &err)?;
+                return Err(err);
+            }
+            self.emit(
+                caller.uid,
+                interface_event(ifname, InterfaceEventKind::Created),
+            );
+
+            debug!("Finished creating a new interface {ifname}");
+            Ok(Response::new(()))
+        }
+        .instrument(span)
+        .await
     }
 
     async fn remove_interface(
@@ -341,21 +1299,21 @@ impl DesktopDaemonService for DaemonService {
         request: tonic::Request<RemoveInterfaceRequest>,
     ) -> Result<Response<()>, Status> {
         debug!("Received a request to remove an interface");
+        let caller = Caller::from_request(&request);
         let request = request.into_inner();
         let ifname = request.interface_name;
         let _span = info_span!("remove_interface", interface_name = &ifname).entered();
+        self.authorize("remove_interface", &caller, &ifname)?;
         debug!("Removing interface {ifname}");
 
-        // Stop stats task.
-        if let Ok(mut tasks) = self.stat_tasks.lock() {
-            if let Some(handle) = tasks.remove(&ifname) {
+        // Stop stats collector; its subscribers' streams end along with it.
+        if let Ok(mut collectors) = self.collectors.lock() {
+            if let Some(collector) = collectors.remove(&ifname) {
                 info!("Stopping statistics collector task for interface {ifname}");
-                handle.abort();
+                collector.handle.abort();
             }
         }
 
-        // `WGApi::remove_interface`` takes `&mut self` under Windows.
-        #[allow(unused_mut)]
         let mut wgapi = {
             let Ok(mut wgapis_map) = self.wgapis.write() else {
                 error!("Failed to acquire read-write lock for WGApis");
@@ -367,34 +1325,229 @@ impl DesktopDaemonService for DaemonService {
             };
             wgapi
         };
-
-        #[cfg(not(windows))]
-        {
-            debug!("Cleaning up interface {ifname} routing");
-            // Ignore error as this should not be considered fatal,
-            // e.g. endpoint might fail to resolve DNS name.
-            if let Err(err) = wgapi.remove_endpoint_routing(&request.endpoint) {
-                error!(
-                    "Failed to remove routing for endpoint {}: {err}",
-                    request.endpoint
-                );
+        let mut owner = None;
+        if let Ok(mut interfaces) = self.interfaces.write() {
+            if let Some(state) = interfaces.remove(&ifname) {
+                owner = state.owner;
+                // Otherwise the rules are dropped right away, so disconnecting restores access.
+                if request.reconnect {
+                    self.release_kill_switch(&ifname, state);
+                }
             }
+            // Errors are logged; there's nothing more to do about them here.
+            let _ = self.apply_kill_switch(&interfaces);
+            self.save_state(&interfaces);
         }
 
-        wgapi.remove_interface().map_err(|err| {
-            let msg = format!("Failed to remove WireGuard interface {ifname}: {err}");
-            error!("{msg}");
-            Status::new(Code::Internal, msg)
-        })?;
+        remove_wireguard_interface(&ifname, &mut wgapi, &request.endpoint)?;
+        self.emit(owner, interface_event(&ifname, InterfaceEventKind::Removed));
 
         debug!("Finished removing interface {ifname}");
         Ok(Response::new(()))
     }
 
+    async fn update_interface(
+        &self,
+        request: tonic::Request<UpdateInterfaceRequest>,
+    ) -> Result<Response<()>, Status> {
+        debug!("Received a request to update an interface");
+        let caller = Caller::from_request(&request);
+        let request = request.into_inner();
+        let mut config: InterfaceConfiguration = request
+            .config
+            .ok_or(Status::new(
+                Code::InvalidArgument,
+                "Missing interface config in request",
+            ))?
+            .into();
+        let ifname = config.name.clone();
+        let span = info_span!("update_interface", interface_name = &ifname);
+        async {
+            self.authorize("update_interface", &caller, &ifname)?;
+            #[cfg(target_os = "linux")]
+            let mut link = LinkSetup::new(&ifname);
+            let (owner, new_request, result) = {
+                let (Ok(mut wgapis_map), Ok(interfaces)) =
+                    (self.wgapis.write(), self.interfaces.read())
+                else {
+                    error!("Failed to acquire read-write lock for WGApis");
+                    return Err(Status::new(Code::Internal, "read-write lock error"));
+                };
+                let (Some(wgapi), Some(state)) =
+                    (wgapis_map.get_mut(&ifname), interfaces.get(&ifname))
+                else {
+                    error!("Unknown interface {ifname}");
+                    return Err(Status::new(Code::Internal, "unknown interface"));
+                };
+                let current: InterfaceConfiguration =
+                    state.request.config.clone().unwrap_or_default().into();
+
+                // Clients don't keep preshared keys of MFA sessions, so keep the ones in use.
+                for peer in &mut config.peers {
+                    if peer.preshared_key.is_none() {
+                        peer.preshared_key = current
+                            .peers
+                            .iter()
+                            .find(|current| current.public_key == peer.public_key)
+                            .and_then(|current| current.preshared_key.clone());
+                    }
+                }
+                if config.prvkey.is_empty() {
+                    config.prvkey.clone_from(&current.prvkey);
+                }
+                // Configuring an empty key would break the interface.
+                if config.prvkey.is_empty() {
+                    let msg = format!("Private key of interface {ifname} is unknown");
+                    error!("{msg}");
+                    return Err(Status::new(Code::FailedPrecondition, msg));
+                }
+
+                debug!("Updating interface {ifname}");
+                let new_request = CreateInterfaceRequest {
+                    config: Some(config.clone().into()),
+                    dns: request.dns,
+                    kill_switch: state.request.kill_switch,
+                    dns_mode: request.dns_mode,
+                    route_table: request.route_table,
+                };
+                let result = reconfigure_interface(
+                    &ifname,
+                    wgapi,
+                    &current,
+                    &config,
+                    &state.request,
+                    &new_request,
+                    #[cfg(target_os = "linux")]
+                    &mut link,
+                );
+                (state.owner, new_request, result)
+            };
+            #[cfg(target_os = "linux")]
+            let result = match result {
+                Ok(()) => self.configure_link(link).await,
+                Err(err) => Err(err),
+            };
+            if let Err(err) = result {
+                let mut event = interface_event(&ifname, InterfaceEventKind::ConfigurationFailed);
+                event.message = Some(err.message().to_string());
+                self.emit(owner, event);
+                return Err(err);
+            }
+            self.emit(owner, interface_event(&ifname, InterfaceEventKind::Updated));
+
+            let Ok(mut interfaces) = self.interfaces.write() else {
+                error!("Failed to acquire read-write lock for interfaces");
+                return Err(Status::new(Code::Internal, "read-write lock error"));
+            };
+            let Some(state) = interfaces.get_mut(&ifname) else {
+                error!("Interface {ifname} has been removed while being updated");
+                return Err(Status::new(Code::Internal, "unknown interface"));
+            };
+            state.request = new_request;
+            // Endpoints may have changed.
+            let kill_switch_result = self.apply_kill_switch(&interfaces);
+            self.save_state(&interfaces);
+            kill_switch_result?;
+
+            info!("Finished updating interface {ifname}");
+            Ok(Response::new(()))
+        }
+        .instrument(span)
+        .await
+    }
+
+    async fn list_interfaces(
+        &self,
+        request: tonic::Request<()>,
+    ) -> Result<Response<ListInterfacesResponse>, Status> {
+        debug!("Received a request to list managed interfaces");
+        let caller = Caller::from_request(&request);
+        let Ok(wgapis_map) = self.wgapis.read() else {
+            error!("Failed to acquire read-write lock for WGApis");
+            return Err(Status::new(Code::Internal, "read-write lock error"));
+        };
+        let Ok(states) = self.interfaces.read() else {
+            error!("Failed to acquire read-write lock for interfaces");
+            return Err(Status::new(Code::Internal, "read-write lock error"));
+        };
+
+        let mut interfaces = Vec::with_capacity(wgapis_map.len());
+        for (ifname, wgapi) in wgapis_map.iter() {
+            let Some(state) = states.get(ifname) else {
+                debug!("Interface {ifname} hasn't been configured, skipping it");
+                continue;
+            };
+            // Users only see interfaces they may manage.
+            if !caller.may_manage(state.owner) {
+                continue;
+            }
+            let data = match wgapi.read_interface_data() {
+                Ok(host) => Some(host.into()),
+                Err(err) => {
+                    error!("Failed to read data of interface {ifname}: {err}");
+                    None
+                }
+            };
+            // Private key never leaves the daemon.
+            let mut config = state.request.config.clone();
+            if let Some(config) = config.as_mut() {
+                config.prvkey.clear();
+            }
+            interfaces.push(ManagedInterface {
+                config,
+                dns: state.request.dns.clone(),
+                data,
+            });
+        }
+
+        info!(
+            target: AUDIT_TARGET,
+            "list_interfaces: {caller} listed {} interfaces",
+            interfaces.len()
+        );
+        Ok(Response::new(ListInterfacesResponse { interfaces }))
+    }
+
+    async fn watch_events(
+        &self,
+        request: tonic::Request<()>,
+    ) -> Result<Response<Self::WatchEventsStream>, Status> {
+        let caller = Caller::from_request(&request);
+        info!(target: AUDIT_TARGET, "watch_events: {caller} started watching events");
+        let mut events = self.events.subscribe();
+        let (tx, rx) = mpsc::channel(EVENT_CHANNEL_CAPACITY);
+
+        tokio::spawn(async move {
+            loop {
+                match events.recv().await {
+                    Ok((owner, event)) => {
+                        // Users only see events of interfaces they may manage.
+                        if caller.may_manage(owner) && tx.send(Ok(event)).await.is_err() {
+                            break;
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(count)) => {
+                        warn!(
+                            "{caller} hasn't kept up with interface events, {count} were dropped"
+                        );
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+            debug!("{caller} has stopped watching interface events");
+        });
+
+        let output_stream = ReceiverStream::new(rx);
+        Ok(Response::new(
+            Box::pin(output_stream) as Self::WatchEventsStream
+        ))
+    }
+
     async fn read_interface_data(
         &self,
         request: tonic::Request<ReadInterfaceDataRequest>,
     ) -> Result<Response<Self::ReadInterfaceDataStream>, Status> {
+        let caller = Caller::from_request(&request);
         let request = request.into_inner();
         let ifname = request.interface_name.clone();
         debug!(
@@ -402,38 +1555,42 @@ impl DesktopDaemonService for DaemonService {
             {ifname}"
         );
         let span = info_span!("read_interface_data", interface_name = &ifname);
+        span.in_scope(|| self.authorize("read_interface_data", &caller, &ifname))?;
 
-        let wgapis = Arc::clone(&self.wgapis);
-        let mut interval = interval(self.stats_period);
+        let mut stats = span.in_scope(|| self.subscribe_stats(&ifname))?;
+        // Send current statistics on the first tick, instead of waiting for the collector.
+        let current = span.in_scope(|| self.read_stats(&ifname));
+        // Subscribers can't get statistics more often than they are collected.
+        let period = request
+            .interval
+            .map_or(self.stats_period, Duration::from_secs)
+            .max(self.stats_period);
+        let mut interval = interval(period);
         let (tx, rx) = mpsc::channel(64);
 
-        span.in_scope(|| {
-            info!("Spawning statistics collector task for interface {ifname}");
-        });
-        let handle = tokio::spawn(
+        tokio::spawn(
             async move {
                 // Helper map to track if peer data is actually changing to avoid sending duplicate
-                // stats.
+                // stats to this subscriber.
                 let mut peer_map = HashMap::new();
+                // Latest statistics which haven't been sent yet.
+                let mut latest = current;
 
                 loop {
-                    // Loop delay
-                    interval.tick().await;
-                    debug!(
-                    "Gathering network usage statistics for client's network activity on {ifname}");
-                    let result = {
-                        let Ok(wgapis_map) = wgapis.read() else {
-                            error!("Failed to acquire read-write lock for WGApis");
-                            break;
-                        };
-                        let Some(wgapi) = wgapis_map.get(&ifname) else {
-                            error!("Unknown interface {ifname}");
-                            break;
-                        };
-                        wgapi.read_interface_data()
-                    };
-                    match result {
-                        Ok(mut host) => {
+                    select! {
+                        result = stats.recv() => match result {
+                            Ok(host) => latest = Some(host),
+                            // Only the latest statistics matter.
+                            Err(broadcast::error::RecvError::Lagged(_)) => (),
+                            Err(broadcast::error::RecvError::Closed) => {
+                                debug!("Statistics collector for interface {ifname} has stopped");
+                                break;
+                            }
+                        },
+                        _ = interval.tick() => {
+                            let Some(mut host) = latest.take() else {
+                                continue;
+                            };
                             let peers = &mut host.peers;
                             debug!(
                                 "Found {} peers configured on WireGuard interface",
@@ -470,27 +1627,21 @@ impl DesktopDaemonService for DaemonService {
                                 );
                                 break;
                             }
-                        }
-                        Err(err) => {
-                            error!(
-                                "Failed to retrieve network usage stats for interface {ifname}: \
-                                {err}"
+                            debug!(
+                                "Network activity statistics for interface {ifname} sent to the \
+                                client"
                             );
-                            break;
                         }
+                        () = tx.closed() => break,
                     }
-                    debug!("Network activity statistics for interface {ifname} sent to the client");
                 }
                 debug!(
                     "The client has disconnected from the network usage statistics data stream \
-                for interface {ifname}, stopping the statistics data collection task."
+                for interface {ifname}, unsubscribing from the statistics collector."
                 );
             }
             .instrument(span),
         );
-        if let Ok(mut tasks) = self.stat_tasks.lock() {
-            tasks.insert(request.interface_name, handle);
-        }
 
         let output_stream = ReceiverStream::new(rx);
         Ok(Response::new(
@@ -504,6 +1655,12 @@ pub async fn run_server(config: Config) -> anyhow::Result<()> {
     debug!("Starting Defguard interface management daemon");
 
     let daemon_service = DaemonService::new(&config);
+    daemon_service.restore_interfaces(config.restore_policy);
+    daemon_service.spawn_event_monitor();
+    if let Some(address) = config.metrics_address {
+        daemon_service.spawn_metrics_server(address).await?;
+    }
+    let metrics_layer = daemon_service.metrics_layer();
 
     // Remove existing socket if it exists
     if Path::new(DAEMON_SOCKET_PATH).exists() {
@@ -535,15 +1692,47 @@ pub async fn run_server(config: Config) -> anyhow::Result<()> {
     info!("Defguard daemon version {VERSION} started, listening on socket {DAEMON_SOCKET_PATH}",);
     debug!("Defguard daemon configuration: {config:?}");
 
-    Server::builder()
+    let result = Server::builder()
         .trace_fn(|_| tracing::info_span!("defguard_service"))
+        .layer(metrics_layer)
         .add_service(DesktopDaemonServiceServer::new(daemon_service))
-        .serve_with_incoming(uds_stream)
-        .await?;
+        .serve_with_incoming_shutdown(uds_stream, shutdown_signal())
+        .await;
 
+    // Interfaces outlive the daemon, but nothing would release the kill-switch without it.
+    #[cfg(target_os = "linux")]
+    if let Err(err) = kill_switch::clear() {
+        error!("Failed to remove kill-switch rules: {err}");
+    }
+    result?;
+
+    info!("Defguard daemon has been stopped");
     Ok(())
 }
 
+/// Wait for SIGTERM or SIGINT.
+#[cfg(unix)]
+async fn shutdown_signal() {
+    use tokio::signal::{
+        ctrl_c,
+        unix::{signal, SignalKind},
+    };
+
+    match signal(SignalKind::terminate()) {
+        Ok(mut terminate) => {
+            select! {
+                _ = terminate.recv() => {}
+                _ = ctrl_c() => {}
+            }
+        }
+        Err(err) => {
+            warn!("Failed to listen for SIGTERM: {err}");
+            let _ = ctrl_c().await;
+        }
+    }
+    info!("Stopping Defguard daemon");
+}
+
 #[cfg(windows)]
 pub(crate) async fn run_server(
     config: Config,
@@ -553,15 +1742,116 @@ pub(crate) async fn run_server(
 
     let stream = get_named_pipe_server_stream();
     let daemon_service = DaemonService::new(&config, service_location_manager);
+    daemon_service.restore_interfaces(config.restore_policy);
+    daemon_service.spawn_event_monitor();
+    if let Some(address) = config.metrics_address {
+        daemon_service.spawn_metrics_server(address).await?;
+    }
+    let metrics_layer = daemon_service.metrics_layer();
 
     info!("Defguard daemon version {VERSION} started, listening on named pipe {PIPE_NAME}");
     debug!("Defguard daemon configuration: {config:?}");
 
     Server::builder()
         .trace_fn(|_| tracing::info_span!("defguard_service"))
+        .layer(metrics_layer)
         .add_service(DesktopDaemonServiceServer::new(daemon_service))
         .serve_with_incoming(stream)
         .await?;
 
     Ok(())
 }
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    #[cfg(target_os = "linux")]
+    use crate::service::fixtures::interface_state;
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn kill_switch_protection() {
+        let mut interfaces = HashMap::from([
+            (
+                "wg0".to_string(),
+                interface_state("0.0.0.0/0", "198.51.100.1:51820", true),
+            ),
+            (
+                "wg1".to_string(),
+                interface_state("10.0.0.0/24", "203.0.113.1:51821", false),
+            ),
+        ]);
+        let mut released = HashMap::new();
+        let now = Instant::now();
+        let protected = |interfaces: &[kill_switch::Interface]| {
+            let mut ifnames: Vec<_> = interfaces
+                .iter()
+                .map(|interface| (interface.ifname.clone(), interface.protected))
+                .collect();
+            ifnames.sort();
+            ifnames
+        };
+
+        // All managed interfaces are allowed through; only the full-tunnel one is protected.
+        let result = kill_switch_interfaces(&interfaces, &mut released, now);
+        assert_eq!(
+            protected(&result),
+            [("wg0".to_string(), true), ("wg1".to_string(), false)]
+        );
+        let wg1 = result.iter().find(|interface| interface.ifname == "wg1");
+        assert_eq!(
+            wg1.unwrap().endpoints,
+            ["203.0.113.1:51821".parse::<SocketAddr>().unwrap()]
+        );
+
+        // Removed interface stays protected, e.g. while it's being re-created on reconnect.
+        let state = interfaces.remove("wg0").unwrap();
+        released.insert("wg0".to_string(), (state, now + KILL_SWITCH_RELEASE_DELAY));
+        let result = kill_switch_interfaces(&interfaces, &mut released, now);
+        assert_eq!(
+            protected(&result),
+            [("wg0".to_string(), true), ("wg1".to_string(), false)]
+        );
+
+        // Once re-created, the interface is protected as a managed one.
+        let state = interface_state("0.0.0.0/0", "198.51.100.1:51820", true);
+        interfaces.insert("wg0".to_string(), state.clone());
+        let result = kill_switch_interfaces(&interfaces, &mut released, now);
+        assert_eq!(result.len(), 2);
+        assert!(released.is_empty());
+
+        // Otherwise the protection ends after the delay.
+        interfaces.remove("wg0");
+        released.insert("wg0".to_string(), (state, now + KILL_SWITCH_RELEASE_DELAY));
+        let later = now + KILL_SWITCH_RELEASE_DELAY;
+        let result = kill_switch_interfaces(&interfaces, &mut released, later);
+        assert_eq!(protected(&result), [("wg1".to_string(), false)]);
+        assert!(released.is_empty());
+    }
+
+    #[test]
+    fn caller_may_manage() {
+        let root = Caller {
+            uid: Some(0),
+            pid: Some(1),
+        };
+        let owner = Caller {
+            uid: Some(1000),
+            pid: Some(2),
+        };
+        let other = Caller {
+            uid: Some(1001),
+            pid: Some(3),
+        };
+        let unknown = Caller::default();
+
+        assert!(root.may_manage(Some(1000)));
+        assert!(root.may_manage(None));
+        assert!(owner.may_manage(Some(1000)));
+        assert!(!owner.may_manage(None));
+        assert!(!other.may_manage(Some(1000)));
+        assert!(!other.may_manage(None));
+        assert!(!unknown.may_manage(Some(1000)));
+        assert!(!unknown.may_manage(None));
+    }
+}
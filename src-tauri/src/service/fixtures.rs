@@ -0,0 +1,29 @@
+//! Interface states shared by service tests.
+
+use super::{
+    proto::{CreateInterfaceRequest, InterfaceConfig, Peer},
+    state::InterfaceState,
+};
+
+/// State of an interface with a single peer at `endpoint`, without a known owner.
+pub(super) fn interface_state(
+    allowed_ips: &str,
+    endpoint: &str,
+    kill_switch: bool,
+) -> InterfaceState {
+    InterfaceState {
+        request: CreateInterfaceRequest {
+            config: Some(InterfaceConfig {
+                peers: vec![Peer {
+                    allowed_ips: vec![allowed_ips.into()],
+                    endpoint: Some(endpoint.into()),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }),
+            kill_switch,
+            ..Default::default()
+        },
+        owner: None,
+    }
+}
@@ -0,0 +1,154 @@
+//! Kill-switch for connections which route all traffic through the tunnel.
+//!
+//! While such a connection is active, a dedicated nftables table drops outgoing traffic which
+//! doesn't go through one of the managed interfaces, except for traffic to their WireGuard endpoints
+//! (so tunnels can be re-established) and to local networks.
+
+use std::{
+    fmt::Write as _,
+    io::{self, Write},
+    net::SocketAddr,
+    process::{Command, Stdio},
+};
+
+use tracing::{debug, info};
+
+const NFT: &str = "nft";
+const TABLE: &str = "inet defguard_kill_switch";
+// Local networks, including link-local, multicast and broadcast addresses.
+const LAN_IPV4: &str =
+    "10.0.0.0/8, 172.16.0.0/12, 192.168.0.0/16, 169.254.0.0/16, 224.0.0.0/4, 255.255.255.255";
+const LAN_IPV6: &str = "fc00::/7, fe80::/10, ff00::/8";
+
+/// Interface managed by the daemon.
+pub(super) struct Interface {
+    pub(super) ifname: String,
+    /// Endpoints of interface peers.
+    pub(super) endpoints: Vec<SocketAddr>,
+    /// Whether the interface enables the kill-switch.
+    pub(super) protected: bool,
+}
+
+/// Render nftables script which replaces the kill-switch table with one letting traffic out only
+/// through `interfaces`, or removes it if none of them is protected.
+fn ruleset(interfaces: &[Interface]) -> String {
+    // Declaring the table first makes deleting it safe if it doesn't exist.
+    let mut script = format!("table {TABLE}\ndelete table {TABLE}\n");
+    if !interfaces.iter().any(|interface| interface.protected) {
+        return script;
+    }
+
+    let ifnames = interfaces
+        .iter()
+        .map(|interface| format!("\"{}\"", interface.ifname))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let _ = write!(
+        script,
+        "table {TABLE} {{\n\
+        \tchain output {{\n\
+        \t\ttype filter hook output priority filter; policy drop;\n\
+        \t\toifname \"lo\" accept\n\
+        \t\toifname {{ {ifnames} }} accept\n"
+    );
+    for endpoint in interfaces.iter().flat_map(|interface| &interface.endpoints) {
+        let family = if endpoint.is_ipv4() { "ip" } else { "ip6" };
+        let _ = writeln!(
+            script,
+            "\t\t{family} daddr {} udp dport {} accept",
+            endpoint.ip(),
+            endpoint.port()
+        );
+    }
+    let _ = write!(
+        script,
+        "\t\tip daddr {{ {LAN_IPV4} }} accept\n\
+        \t\tip6 daddr {{ {LAN_IPV6} }} accept\n\
+        \t\tudp sport 68 udp dport 67 accept\n\
+        \t}}\n\
+        }}\n"
+    );
+
+    script
+}
+
+/// Make the kill-switch protect `interfaces`; it's disabled if none of them is protected.
+pub(super) fn apply(interfaces: &[Interface]) -> io::Result<()> {
+    let script = ruleset(interfaces);
+    debug!("Applying kill-switch nftables ruleset:\n{script}");
+    let mut child = Command::new(NFT)
+        .args(["-f", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(script.as_bytes())?;
+    }
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "{NFT} has failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    let ifnames: Vec<_> = interfaces
+        .iter()
+        .filter(|interface| interface.protected)
+        .map(|interface| interface.ifname.as_str())
+        .collect();
+    if ifnames.is_empty() {
+        info!("Kill-switch has been disabled");
+    } else {
+        info!("Kill-switch protects interfaces: {}", ifnames.join(", "));
+    }
+    Ok(())
+}
+
+/// Remove the kill-switch table, e.g. one left over from a previous run. Without nftables
+/// installed there can't be any table, so that's fine too.
+pub(super) fn clear() -> io::Result<()> {
+    match apply(&[]) {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_ruleset() {
+        let disabled = "table inet defguard_kill_switch\ndelete table inet defguard_kill_switch\n";
+        assert_eq!(ruleset(&[]), disabled);
+
+        let mut interfaces = vec![
+            Interface {
+                ifname: "wg0".into(),
+                endpoints: vec![
+                    "198.51.100.1:51820".parse().unwrap(),
+                    "[2001:db8::1]:51821".parse().unwrap(),
+                ],
+                protected: true,
+            },
+            Interface {
+                ifname: "wg1".into(),
+                endpoints: vec!["203.0.113.1:51822".parse().unwrap()],
+                protected: false,
+            },
+        ];
+        let script = ruleset(&interfaces);
+        assert!(script.contains("policy drop;"));
+        assert!(script.contains("ip daddr 198.51.100.1 udp dport 51820 accept"));
+        assert!(script.contains("ip6 daddr 2001:db8::1 udp dport 51821 accept"));
+        // Other managed interfaces keep working while the kill-switch is enabled.
+        assert!(script.contains("oifname { \"wg0\", \"wg1\" } accept"));
+        assert!(script.contains("ip daddr 203.0.113.1 udp dport 51822 accept"));
+
+        // Unprotected interfaces alone don't enable the kill-switch.
+        interfaces.remove(0);
+        assert_eq!(ruleset(&interfaces), disabled);
+    }
+}
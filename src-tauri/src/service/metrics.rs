@@ -0,0 +1,431 @@
+//! Metrics of managed interfaces and of the daemon itself, exposed over HTTP in the OpenMetrics
+//! text format, so they can be scraped by Prometheus.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, UNIX_EPOCH},
+};
+
+use defguard_wireguard_rs::host::Host;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    time::timeout,
+};
+use tonic::{
+    codegen::{http, BoxFuture},
+    Code,
+};
+use tower::{Layer, Service};
+use tracing::{debug, error, info};
+
+use crate::VERSION;
+
+const CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+const METRICS_PATH: &str = "/metrics";
+// Scrapers send short requests; anything longer is not a scrape.
+const MAX_REQUEST_LENGTH: usize = 8192;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Counters of gRPC calls handled by the daemon.
+#[derive(Default)]
+pub(crate) struct Metrics {
+    // Number of calls; key is RPC name.
+    rpc_requests: Mutex<BTreeMap<String, u64>>,
+    // Number of failed calls; key is RPC name and status code. Streams which fail after they've
+    // started report their status in trailers, which aren't inspected, so those aren't counted.
+    rpc_errors: Mutex<BTreeMap<(String, String), u64>>,
+}
+
+impl Metrics {
+    fn record_rpc(&self, rpc: &str, code: Code) {
+        if let Ok(mut requests) = self.rpc_requests.lock() {
+            *requests.entry(rpc.to_string()).or_default() += 1;
+        }
+        if code != Code::Ok {
+            if let Ok(mut errors) = self.rpc_errors.lock() {
+                *errors
+                    .entry((rpc.to_string(), format!("{code:?}")))
+                    .or_default() += 1;
+            }
+        }
+    }
+
+    /// Render metrics of the daemon, which manages `managed` interfaces, and of `interfaces`
+    /// which could be read.
+    pub(crate) fn render(&self, managed: usize, interfaces: &[(String, Host)]) -> String {
+        let mut output = String::new();
+
+        family(
+            &mut output,
+            "defguard_service_build_info",
+            "gauge",
+            "Version of the daemon",
+        );
+        sample(
+            &mut output,
+            "defguard_service_build_info",
+            &[("version", VERSION)],
+            1,
+        );
+
+        family(
+            &mut output,
+            "defguard_service_managed_interfaces",
+            "gauge",
+            "Number of interfaces managed by the daemon",
+        );
+        sample(
+            &mut output,
+            "defguard_service_managed_interfaces",
+            &[],
+            managed as u64,
+        );
+
+        family(
+            &mut output,
+            "defguard_service_rpc_requests",
+            "counter",
+            "Number of gRPC calls handled by the daemon",
+        );
+        if let Ok(requests) = self.rpc_requests.lock() {
+            for (rpc, count) in requests.iter() {
+                sample(
+                    &mut output,
+                    "defguard_service_rpc_requests_total",
+                    &[("rpc", rpc)],
+                    *count,
+                );
+            }
+        }
+
+        family(
+            &mut output,
+            "defguard_service_rpc_errors",
+            "counter",
+            "Number of gRPC calls which have failed, except for streams failing after their start",
+        );
+        if let Ok(errors) = self.rpc_errors.lock() {
+            for ((rpc, code), count) in errors.iter() {
+                sample(
+                    &mut output,
+                    "defguard_service_rpc_errors_total",
+                    &[("rpc", rpc), ("code", code)],
+                    *count,
+                );
+            }
+        }
+
+        family(
+            &mut output,
+            "defguard_interface_peers",
+            "gauge",
+            "Number of peers configured on the interface",
+        );
+        for (ifname, host) in interfaces {
+            sample(
+                &mut output,
+                "defguard_interface_peers",
+                &[("interface", ifname)],
+                host.peers.len() as u64,
+            );
+        }
+
+        // Samples of a family mustn't be interleaved with others, so peers are iterated per family.
+        let peers: Vec<_> = interfaces
+            .iter()
+            .flat_map(|(ifname, host)| {
+                host.peers
+                    .values()
+                    .map(move |peer| (ifname.as_str(), peer.public_key.to_string(), peer))
+            })
+            .collect();
+        family(
+            &mut output,
+            "defguard_peer_received_bytes",
+            "counter",
+            "Number of bytes received from the peer",
+        );
+        for (ifname, public_key, peer) in &peers {
+            sample(
+                &mut output,
+                "defguard_peer_received_bytes_total",
+                &[("interface", ifname), ("peer", public_key)],
+                peer.rx_bytes,
+            );
+        }
+
+        family(
+            &mut output,
+            "defguard_peer_transmitted_bytes",
+            "counter",
+            "Number of bytes sent to the peer",
+        );
+        for (ifname, public_key, peer) in &peers {
+            sample(
+                &mut output,
+                "defguard_peer_transmitted_bytes_total",
+                &[("interface", ifname), ("peer", public_key)],
+                peer.tx_bytes,
+            );
+        }
+
+        family(
+            &mut output,
+            "defguard_peer_last_handshake_seconds",
+            "gauge",
+            "UNIX timestamp of the latest handshake with the peer",
+        );
+        for (ifname, public_key, peer) in &peers {
+            if let Some(handshake) = peer
+                .last_handshake
+                .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                .filter(|time| !time.is_zero())
+            {
+                sample(
+                    &mut output,
+                    "defguard_peer_last_handshake_seconds",
+                    &[("interface", ifname), ("peer", public_key)],
+                    handshake.as_secs(),
+                );
+            }
+        }
+
+        output.push_str("# EOF\n");
+        output
+    }
+}
+
+fn family(output: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(output, "# TYPE {name} {kind}\n# HELP {name} {help}");
+}
+
+fn sample(output: &mut String, name: &str, labels: &[(&str, &str)], value: u64) {
+    output.push_str(name);
+    if !labels.is_empty() {
+        let labels: Vec<_> = labels
+            .iter()
+            .map(|(label, value)| {
+                let value = value
+                    .replace('\\', "\\\\")
+                    .replace('"', "\\\"")
+                    .replace('\n', "\\n");
+                format!("{label}=\"{value}\"")
+            })
+            .collect();
+        let _ = write!(output, "{{{}}}", labels.join(","));
+    }
+    let _ = writeln!(output, " {value}");
+}
+
+/// Tower layer which counts gRPC calls and their failures.
+#[derive(Clone)]
+pub(crate) struct RpcMetricsLayer {
+    metrics: Arc<Metrics>,
+}
+
+impl RpcMetricsLayer {
+    #[must_use]
+    pub(crate) fn new(metrics: Arc<Metrics>) -> Self {
+        Self { metrics }
+    }
+}
+
+impl<S> Layer<S> for RpcMetricsLayer {
+    type Service = RpcMetricsService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RpcMetricsService {
+            inner,
+            metrics: Arc::clone(&self.metrics),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct RpcMetricsService<S> {
+    inner: S,
+    metrics: Arc<Metrics>,
+}
+
+impl<S, ReqBody, ResBody> Service<http::Request<ReqBody>> for RpcMetricsService<S>
+where
+    S: Service<http::Request<ReqBody>, Response = http::Response<ResBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<ReqBody>) -> Self::Future {
+        // Path is "/<package>.<service>/<method>".
+        let rpc = request
+            .uri()
+            .path()
+            .rsplit('/')
+            .next()
+            .unwrap_or_default()
+            .to_string();
+        let metrics = Arc::clone(&self.metrics);
+        let future = self.inner.call(request);
+        Box::pin(async move {
+            let response = future.await?;
+            // Calls failing right away are answered with status in headers. Status of the others,
+            // including streams failing later on, comes in trailers, so they count as successful.
+            let code = response
+                .headers()
+                .get("grpc-status")
+                .map_or(Code::Ok, |status| Code::from_bytes(status.as_bytes()));
+            metrics.record_rpc(&rpc, code);
+            Ok(response)
+        })
+    }
+}
+
+/// Serve metrics rendered by `render` over HTTP, until the listener fails. Rendering may block, so
+/// it's done on a blocking thread.
+pub(crate) async fn serve<F>(listener: TcpListener, render: F)
+where
+    F: Fn() -> String + Send + Sync + 'static,
+{
+    let render = Arc::new(render);
+    if let Ok(address) = listener.local_addr() {
+        info!("Serving metrics on http://{address}{METRICS_PATH}");
+    }
+    loop {
+        let (stream, address) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(err) => {
+                error!("Failed to accept metrics connection: {err}");
+                break;
+            }
+        };
+        let render = Arc::clone(&render);
+        tokio::spawn(async move {
+            match timeout(REQUEST_TIMEOUT, handle_connection(stream, render)).await {
+                Ok(Ok(())) => (),
+                Ok(Err(err)) => debug!("Failed to serve metrics to {address}: {err}"),
+                Err(_) => debug!("Metrics request from {address} has timed out"),
+            }
+        });
+    }
+}
+
+async fn handle_connection<F>(mut stream: TcpStream, render: Arc<F>) -> std::io::Result<()>
+where
+    F: Fn() -> String + Send + Sync + 'static,
+{
+    let mut request = Vec::new();
+    let mut buffer = [0; 1024];
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+        let count = stream.read(&mut buffer).await?;
+        if count == 0 {
+            return Ok(());
+        }
+        request.extend_from_slice(&buffer[..count]);
+        if request.len() > MAX_REQUEST_LENGTH {
+            return respond(&mut stream, "413 Content Too Large", "text/plain", "").await;
+        }
+    }
+
+    let request = String::from_utf8_lossy(&request);
+    let mut request_line = request.lines().next().unwrap_or_default().split(' ');
+    let (method, path) = (request_line.next(), request_line.next());
+    let path = path.map(|path| path.split('?').next().unwrap_or_default());
+    match (method, path) {
+        (Some("GET"), Some(METRICS_PATH)) => {
+            let body = tokio::task::spawn_blocking(move || render())
+                .await
+                .map_err(std::io::Error::other)?;
+            respond(&mut stream, "200 OK", CONTENT_TYPE, &body).await
+        }
+        (Some(_), Some(METRICS_PATH)) => {
+            respond(&mut stream, "405 Method Not Allowed", "text/plain", "").await
+        }
+        _ => respond(&mut stream, "404 Not Found", "text/plain", "").await,
+    }
+}
+
+async fn respond(
+    stream: &mut TcpStream,
+    status: &str,
+    content_type: &str,
+    body: &str,
+) -> std::io::Result<()> {
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\n\
+        Connection: close\r\n\r\n{body}",
+        body.len()
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// Bind metrics listener to `address`.
+pub(crate) async fn bind(address: SocketAddr) -> std::io::Result<TcpListener> {
+    debug!("Binding metrics listener to {address}");
+    TcpListener::bind(address).await
+}
+
+#[cfg(test)]
+mod tests {
+    use defguard_wireguard_rs::{key::Key, peer::Peer};
+
+    use super::*;
+
+    #[test]
+    fn render_metrics() {
+        let metrics = Metrics::default();
+        metrics.record_rpc("CreateInterface", Code::Ok);
+        metrics.record_rpc("CreateInterface", Code::PermissionDenied);
+
+        let key = Key::new([1; 32]);
+        let mut peer = Peer::new(key.clone());
+        peer.rx_bytes = 100;
+        peer.tx_bytes = 200;
+        peer.last_handshake = Some(UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+        let mut host = Host::default();
+        host.peers.insert(key.clone(), peer);
+
+        let other_key = Key::new([2; 32]);
+        host.peers
+            .insert(other_key.clone(), Peer::new(other_key.clone()));
+
+        let output = metrics.render(1, &[("wg0".into(), host)]);
+        let labels = format!("{{interface=\"wg0\",peer=\"{key}\"}}");
+        assert!(output.contains("defguard_service_managed_interfaces 1\n"));
+        assert!(output.contains("defguard_service_rpc_requests_total{rpc=\"CreateInterface\"} 2\n"));
+        assert!(output.contains(
+            "defguard_service_rpc_errors_total{rpc=\"CreateInterface\",code=\"PermissionDenied\"} 1\n"
+        ));
+        assert!(output.contains(&format!("defguard_peer_received_bytes_total{labels} 100\n")));
+        assert!(output.contains(&format!(
+            "defguard_peer_transmitted_bytes_total{labels} 200\n"
+        )));
+        assert!(output.contains(&format!(
+            "defguard_peer_last_handshake_seconds{labels} 1700000000\n"
+        )));
+        assert!(output.ends_with("# EOF\n"));
+
+        // Samples of each family follow its header, without any of other families in between.
+        let mut families = Vec::new();
+        for line in output.lines() {
+            if let Some(family) = line.strip_prefix("# TYPE ") {
+                let name = family.split(' ').next().unwrap();
+                assert!(!families.contains(&name), "{name} is declared twice");
+                families.push(name);
+            } else if !line.starts_with('#') {
+                let family = families.last().unwrap();
+                assert!(line.starts_with(family), "{line} doesn't follow {family}");
+            }
+        }
+    }
+}
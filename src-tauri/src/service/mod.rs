@@ -6,8 +6,20 @@ pub mod proto {
 }
 #[cfg(not(target_os = "macos"))]
 pub mod daemon;
+#[cfg(all(test, not(target_os = "macos")))]
+mod fixtures;
+#[cfg(target_os = "linux")]
+mod kill_switch;
+#[cfg(not(target_os = "macos"))]
+pub mod metrics;
 #[cfg(windows)]
 pub mod named_pipe;
+#[cfg(target_os = "linux")]
+mod resolved;
+#[cfg(target_os = "linux")]
+mod routing;
+#[cfg(not(target_os = "macos"))]
+pub mod state;
 pub mod utils;
 #[cfg(windows)]
 pub mod windows;
@@ -35,6 +47,7 @@ impl From<InterfaceConfiguration> for proto::InterfaceConfig {
             port: u32::from(config.port),
             peers: config.peers.into_iter().map(Into::into).collect(),
             mtu: config.mtu,
+            fwmark: config.fwmark,
         }
     }
 }
@@ -53,7 +66,7 @@ impl From<proto::InterfaceConfig> for InterfaceConfiguration {
             port: config.port as u16,
             peers: config.peers.into_iter().map(Into::into).collect(),
             mtu: config.mtu,
-            fwmark: None, // TODO: add to config
+            fwmark: config.fwmark,
         }
     }
 }
@@ -0,0 +1,92 @@
+//! Split DNS through systemd-resolved.
+//!
+//! Resolvers of a location are registered on its WireGuard link for the location's search
+//! domains only (as routing-only `~domain` entries), so DNS configuration of other links, and
+//! global DNS, are left intact. Locations without search domains don't use split DNS at all, as
+//! their resolvers wouldn't be used for any names.
+
+use std::net::IpAddr;
+
+use nix::{
+    libc::{AF_INET, AF_INET6},
+    net::if_::if_nametoindex,
+};
+use tracing::debug;
+use zbus::blocking::Connection;
+
+const DESTINATION: &str = "org.freedesktop.resolve1";
+const PATH: &str = "/org/freedesktop/resolve1";
+const INTERFACE: &str = "org.freedesktop.resolve1.Manager";
+
+/// Use `dns` resolvers on link `ifname` for names in `domains` only; the root domain is ignored, as
+/// it would make the link the default DNS route.
+pub(super) fn configure_split_dns(
+    ifname: &str,
+    dns: &[IpAddr],
+    domains: &[&str],
+) -> zbus::Result<()> {
+    let ifindex = if_nametoindex(ifname)
+        .map_err(|err| zbus::Error::Failure(format!("unknown interface {ifname}: {err}")))?;
+    // resolved expects signed interface index.
+    let ifindex = ifindex as i32;
+    let servers: Vec<_> = dns
+        .iter()
+        .map(|ip| match ip {
+            IpAddr::V4(ip) => (AF_INET, ip.octets().to_vec()),
+            IpAddr::V6(ip) => (AF_INET6, ip.octets().to_vec()),
+        })
+        .collect();
+    let domains = routing_domains(domains);
+
+    let connection = Connection::system()?;
+    debug!("Setting DNS servers {dns:?} on link {ifname} ({ifindex})");
+    connection.call_method(
+        Some(DESTINATION),
+        PATH,
+        Some(INTERFACE),
+        "SetLinkDNS",
+        &(ifindex, servers),
+    )?;
+    debug!("Setting routing-only domains {domains:?} on link {ifname} ({ifindex})");
+    connection.call_method(
+        Some(DESTINATION),
+        PATH,
+        Some(INTERFACE),
+        "SetLinkDomains",
+        &(ifindex, domains),
+    )?;
+    // Don't send queries for other domains to this link.
+    connection.call_method(
+        Some(DESTINATION),
+        PATH,
+        Some(INTERFACE),
+        "SetLinkDefaultRoute",
+        &(ifindex, false),
+    )?;
+
+    Ok(())
+}
+
+/// Routing-only domains; resolvers of the link aren't used for anything else.
+pub(super) fn routing_domains<'a>(domains: &[&'a str]) -> Vec<(&'a str, bool)> {
+    domains
+        .iter()
+        .map(|domain| domain.trim_start_matches('~'))
+        .filter(|domain| !domain.is_empty() && *domain != ".")
+        .map(|domain| (domain, true))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_dns_domains() {
+        assert_eq!(
+            routing_domains(&["example.com", "~corp.example.com", "~.", "."]),
+            [("example.com", true), ("corp.example.com", true)]
+        );
+        assert!(routing_domains(&[]).is_empty());
+    }
+}
@@ -0,0 +1,78 @@
+//! Routing of interfaces which use a dedicated routing table.
+//!
+//! As with `Table` in wg-quick, routes to allowed IPs of peers are added to the given table only;
+//! selecting the table (e.g. with `ip rule` matching the firewall mark) is left to the user.
+//!
+//! Routes are managed with `ip` from iproute2, which must be installed.
+
+use std::{
+    io,
+    process::{Command, Stdio},
+};
+
+use defguard_wireguard_rs::net::IpAddrMask;
+use tracing::debug;
+
+const IP: &str = "ip";
+
+fn ip(args: &[&str]) -> io::Result<()> {
+    debug!("Running: {IP} {}", args.join(" "));
+    let output = Command::new(IP)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .output()
+        .map_err(|err| {
+            if err.kind() == io::ErrorKind::NotFound {
+                io::Error::new(
+                    err.kind(),
+                    format!("{IP} command not found, make sure iproute2 is installed"),
+                )
+            } else {
+                err
+            }
+        })?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!(
+            "{IP} {} has failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )))
+    }
+}
+
+/// Route `allowed_ips` through interface `ifname` in routing table `table`.
+pub(super) fn add_routes<'a>(
+    ifname: &str,
+    table: u32,
+    allowed_ips: impl IntoIterator<Item = &'a IpAddrMask>,
+) -> io::Result<()> {
+    let table = table.to_string();
+    for addr in allowed_ips {
+        let family = if addr.ip.is_ipv4() { "-4" } else { "-6" };
+        let destination = addr.to_string();
+        ip(&[
+            family,
+            "route",
+            "replace",
+            &destination,
+            "dev",
+            ifname,
+            "table",
+            &table,
+        ])?;
+    }
+    Ok(())
+}
+
+/// Remove all routes through interface `ifname` from routing table `table`.
+pub(super) fn flush_routes(ifname: &str, table: u32) -> io::Result<()> {
+    let table = table.to_string();
+    for family in ["-4", "-6"] {
+        ip(&[family, "route", "flush", "table", &table, "dev", ifname])?;
+    }
+    Ok(())
+}
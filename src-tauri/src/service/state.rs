@@ -0,0 +1,124 @@
+//! State of interfaces managed by the daemon. It is persisted, so that interfaces which outlive
+//! the daemon (e.g. when it crashes or gets restarted) can be re-adopted or cleaned up.
+
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::{
+    collections::HashMap,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::Path,
+};
+
+use clap::ValueEnum;
+use defguard_wireguard_rs::key::Key;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, error};
+
+use super::proto::CreateInterfaceRequest;
+
+/// What to do with interfaces left over from a previous run of the daemon.
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum RestorePolicy {
+    /// Take over management of interfaces which still exist.
+    #[default]
+    Adopt,
+    /// Remove interfaces and routes to their endpoints. DNS configuration goes away along with the
+    /// interface, as `WGApi` clears it when removing the interface.
+    Remove,
+}
+
+/// Managed interface, as created by a client.
+#[derive(Clone, Deserialize, Serialize)]
+pub(super) struct InterfaceState {
+    /// Request with which the interface has been created.
+    pub(super) request: CreateInterfaceRequest,
+    /// UID of the user who has created the interface; unknown on Windows.
+    pub(super) owner: Option<u32>,
+}
+
+impl InterfaceState {
+    /// Private keys aren't persisted, so put back the one in use by the live interface;
+    /// otherwise updates of restored interfaces would have no key to configure.
+    pub(super) fn restore_private_key(&mut self, key: &Key) {
+        if let Some(config) = self.request.config.as_mut() {
+            if config.prvkey.is_empty() {
+                config.prvkey = key.to_string();
+            }
+        }
+    }
+}
+
+/// Read state of managed interfaces; key is interface name.
+pub(super) fn load(path: &Path) -> HashMap<String, InterfaceState> {
+    match fs::read(path) {
+        Ok(content) => serde_json::from_slice(&content).unwrap_or_else(|err| {
+            error!("Failed to parse state file {path:?}, ignoring it: {err}");
+            HashMap::new()
+        }),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            debug!("State file {path:?} doesn't exist, there are no interfaces to restore");
+            HashMap::new()
+        }
+        Err(err) => {
+            error!("Failed to read state file {path:?}, ignoring it: {err}");
+            HashMap::new()
+        }
+    }
+}
+
+/// Write state of managed interfaces to the state file.
+pub(super) fn save(path: &Path, interfaces: &HashMap<String, InterfaceState>) -> io::Result<()> {
+    let mut interfaces = interfaces.clone();
+    // Private keys aren't needed to re-adopt or remove interfaces, so don't keep them on disk.
+    for interface in interfaces.values_mut() {
+        if let Some(config) = interface.request.config.as_mut() {
+            config.prvkey.clear();
+        }
+    }
+    let content = serde_json::to_vec(&interfaces)?;
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    // Write to a temporary file first, so the state file is never left half-written.
+    let tmp_path = path.with_extension("tmp");
+    let mut options = OpenOptions::new();
+    options.create(true).truncate(true).write(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    options.open(&tmp_path)?.write_all(&content)?;
+    fs::rename(tmp_path, path)?;
+    debug!("Saved state of {} interfaces to {path:?}", interfaces.len());
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process, str::FromStr};
+
+    use super::*;
+    use crate::service::fixtures::interface_state;
+
+    #[test]
+    fn restore_private_key() {
+        let path = env::temp_dir().join(format!("defguard-state-{}.json", process::id()));
+        let key = Key::generate();
+        let mut state = interface_state("10.0.0.0/24", "198.51.100.1:51820", false);
+        state.request.config.as_mut().unwrap().prvkey = key.to_string();
+        state.owner = Some(1000);
+        save(&path, &HashMap::from([("wg0".to_string(), state)])).unwrap();
+        let mut interfaces = load(&path);
+        fs::remove_file(&path).unwrap();
+
+        let state = interfaces.get_mut("wg0").unwrap();
+        assert_eq!(state.owner, Some(1000));
+        let config = state.request.config.as_ref().unwrap();
+        assert!(config.prvkey.is_empty());
+
+        state.restore_private_key(&key);
+        let config = state.request.config.as_ref().unwrap();
+        assert_eq!(Key::from_str(&config.prvkey).unwrap(), key);
+    }
+}
@@ -12,9 +12,9 @@ pub fn logging_setup(log_dir: &str, log_level: &str) -> WorkerGuard {
     let file_appender = tracing_appender::rolling::daily(log_dir, "defguard-service.log");
     let (non_blocking, guard) = tracing_appender::non_blocking(file_appender);
 
-    // prepare log level filter for stdout
+    // prepare log level filter for stdout; audit records are always logged
     let stdout_filter = EnvFilter::try_from_default_env()
-        .unwrap_or_else(|_| format!("{log_level},hyper=info,h2=info").into());
+        .unwrap_or_else(|_| format!("{log_level},hyper=info,h2=info,audit=info").into());
 
     // prepare log level filter for JSON file
     let json_filter = EnvFilter::new("DEBUG,hyper=info,h2=info");
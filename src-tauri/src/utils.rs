@@ -23,7 +23,7 @@ use windows_service::{
 #[cfg(windows)]
 use windows_sys::Win32::Foundation::ERROR_SERVICE_DOES_NOT_EXIST;
 
-#[cfg(windows)]
+#[cfg(any(windows, target_os = "linux"))]
 use crate::active_connections::find_connection;
 #[cfg(target_os = "macos")]
 use crate::apple::tunnel_stats;
@@ -32,7 +32,8 @@ use crate::{
     commands::LocationInterfaceDetails,
     database::{
         models::{
-            connection::{ActiveConnection, Connection},
+            connection::{ActiveConnection, Connection, DisconnectReason},
+            connection_event::{record_event, ConnectionEventKind},
             location::Location,
             tunnel::{Tunnel, TunnelConnection},
             wireguard_keys::WireguardKeys,
@@ -51,7 +52,10 @@ use crate::{
     database::models::{location_stats::peer_to_location_stats, tunnel::peer_to_tunnel_stats},
     service::{
         client::DAEMON_CLIENT,
-        proto::{CreateInterfaceRequest, ReadInterfaceDataRequest, RemoveInterfaceRequest},
+        proto::{
+            CreateInterfaceRequest, DnsMode, ReadInterfaceDataRequest, RemoveInterfaceRequest,
+            UpdateInterfaceRequest,
+        },
     },
 };
 
@@ -61,6 +65,27 @@ pub(crate) static DEFAULT_ROUTE_IPV6: &str = "::/0";
 #[cfg(target_os = "macos")]
 static TUNNEL_START_DELAY: Duration = Duration::from_secs(1);
 
+/// Check if interface routes all traffic through its peers.
+#[cfg(not(target_os = "macos"))]
+fn routes_all_traffic(config: &InterfaceConfiguration) -> bool {
+    config
+        .peers
+        .iter()
+        .flat_map(|peer| &peer.allowed_ips)
+        .any(|addr| addr.cidr == 0)
+}
+
+/// Location resolvers are only used for its search domains, unless all traffic goes through it.
+/// The mode follows from allowed IPs only; there's no per-location setting for it.
+#[cfg(not(target_os = "macos"))]
+fn dns_mode(config: &InterfaceConfiguration) -> DnsMode {
+    if routes_all_traffic(config) {
+        DnsMode::Global
+    } else {
+        DnsMode::Split
+    }
+}
+
 /// Setup client interface for `Instance`.
 #[cfg(not(target_os = "macos"))]
 pub(crate) async fn setup_interface(
@@ -85,14 +110,20 @@ pub(crate) async fn setup_interface(
     };
     debug!("Found free port: {port} for interface {interface_name}.");
 
+    let mut conn = pool.acquire().await?;
     let mut interface_config = location
-        .interface_configuration(pool, interface_name.clone(), preshared_key, mtu)
+        .interface_configuration(&mut conn, interface_name.clone(), preshared_key, mtu)
         .await?;
     interface_config.mtu = mtu;
     debug!("Creating interface for location {location} with configuration {interface_config:?}");
     let request = CreateInterfaceRequest {
         config: Some(interface_config.clone().into()),
         dns: location.dns.clone(),
+        kill_switch: routes_all_traffic(&interface_config),
+        dns_mode: dns_mode(&interface_config).into(),
+        route_table: location
+            .route_table
+            .and_then(|table| u32::try_from(table).ok()),
     };
     if let Err(error) = DAEMON_CLIENT.clone().create_interface(request).await {
         if error.code() == Code::Unavailable {
@@ -125,6 +156,101 @@ pub(crate) async fn setup_interface(
     }
 }
 
+/// Apply current configuration of locations and tunnels to their active connections, without
+/// dropping them. Preshared keys of MFA sessions are kept by the background service.
+/// All connections are updated, even if some of them fail; returned are errors of the failed ones.
+#[cfg(not(target_os = "macos"))]
+pub(crate) async fn update_active_connections(
+    connections: &[ActiveConnection],
+    mtu: Option<u32>,
+) -> Vec<Error> {
+    let mut errors = Vec::new();
+    for connection in connections {
+        if let Err(err) = update_active_connection(connection, mtu).await {
+            error!(
+                "Failed to apply new configuration to interface {}: {err}",
+                connection.interface_name
+            );
+            errors.push(err);
+        }
+    }
+
+    errors
+}
+
+#[cfg(not(target_os = "macos"))]
+async fn update_active_connection(
+    connection: &ActiveConnection,
+    mtu: Option<u32>,
+) -> Result<(), Error> {
+    let interface_name = connection.interface_name.clone();
+    let (request, name) = match connection.connection_type {
+        ConnectionType::Location => {
+            let mut conn = DB_POOL.acquire().await?;
+            let Some(location) = Location::find_by_id(&mut *conn, connection.location_id).await?
+            else {
+                return Err(Error::InternalError(format!(
+                    "Location {} of active connection has been removed",
+                    connection.location_id
+                )));
+            };
+            let interface_config = location
+                .interface_configuration(&mut conn, interface_name, None, mtu)
+                .await?;
+            let request = UpdateInterfaceRequest {
+                dns_mode: dns_mode(&interface_config).into(),
+                config: Some(interface_config.into()),
+                dns: location.dns.clone(),
+                route_table: location
+                    .route_table
+                    .and_then(|table| u32::try_from(table).ok()),
+            };
+            (request, location.to_string())
+        }
+        ConnectionType::Tunnel => {
+            let Some(tunnel) = Tunnel::find_by_id(&*DB_POOL, connection.location_id).await? else {
+                return Err(Error::InternalError(format!(
+                    "Tunnel {} of active connection has been removed",
+                    connection.location_id
+                )));
+            };
+            // Port is kept by the background service, unless the interface has to be recreated.
+            let interface_config = tunnel_interface_configuration(&tunnel, interface_name, 0, mtu)?;
+            let request = UpdateInterfaceRequest {
+                dns_mode: dns_mode(&interface_config).into(),
+                config: Some(interface_config.into()),
+                dns: tunnel.dns.clone(),
+                route_table: tunnel
+                    .route_table
+                    .and_then(|table| u32::try_from(table).ok()),
+            };
+            (request, tunnel.to_string())
+        }
+    };
+    debug!(
+        "Updating interface {} of {} {name}",
+        connection.interface_name, connection.connection_type
+    );
+    DAEMON_CLIENT
+        .clone()
+        .update_interface(request)
+        .await
+        .map_err(|error| {
+            Error::InternalError(format!(
+                "Failed to update interface {} of {} {name}: {}",
+                connection.interface_name,
+                connection.connection_type,
+                error.message()
+            ))
+        })?;
+    info!(
+        "Applied new configuration of {} {name} to interface {}",
+        connection.connection_type, connection.interface_name
+    );
+
+    Ok(())
+}
+
 #[cfg(target_os = "macos")]
 pub(crate) async fn setup_interface(
     location: &Location<Id>,
@@ -221,6 +347,8 @@ pub(crate) async fn stats_handler(interface_name: String, connection_type: Conne
     let pool = DB_POOL.clone();
     let request = ReadInterfaceDataRequest {
         interface_name: interface_name.clone(),
+        // Use the daemon's statistics period.
+        interval: None,
     };
     let mut stream = DAEMON_CLIENT
         .clone()
@@ -364,15 +492,14 @@ pub fn get_service_log_dir() -> &'static Path {
     Path::new(path)
 }
 
-/// Setup client interface
+/// Build interface configuration of a tunnel.
 #[cfg(not(target_os = "macos"))]
-pub async fn setup_interface_tunnel(
+fn tunnel_interface_configuration(
     tunnel: &Tunnel<Id>,
-    name: &str,
+    interface_name: String,
+    port: u16,
     mtu: Option<u32>,
-) -> Result<String, Error> {
-    debug!("Setting up interface for tunnel {tunnel}");
-    let interface_name = get_interface_name(name);
+) -> Result<InterfaceConfiguration, Error> {
     // prepare peer config
     debug!(
         "Decoding tunnel {tunnel} public key: {}.",
@@ -432,17 +559,6 @@ pub async fn setup_interface_tunnel(
     }
     debug!("Parsed tunnel {tunnel} allowed IPs: {:?}", peer.allowed_ips);
 
-    // request interface configuration
-    debug!("Looking for a free port for interface {interface_name}.");
-    let Some(port) = find_free_tcp_port() else {
-        let msg = format!(
-            "Couldn't find free port for interface {interface_name} while setting up tunnel {tunnel}"
-        );
-        error!("{msg}");
-        return Err(Error::InternalError(msg));
-    };
-    debug!("Found free port: {port} for interface {interface_name}.");
-
     let addresses = tunnel
         .address
         .split(',')
@@ -455,19 +571,50 @@ pub async fn setup_interface_tunnel(
             Error::InternalError(msg)
         })?;
     let interface_config = InterfaceConfiguration {
-        name: interface_name.clone(),
+        name: interface_name,
         prvkey: tunnel.prvkey.clone(),
         addresses,
         port,
-        peers: vec![peer.clone()],
+        peers: vec![peer],
         mtu,
-        fwmark: None, // TODO: add
+        fwmark: tunnel.fwmark.and_then(|fwmark| u32::try_from(fwmark).ok()),
     };
 
+    Ok(interface_config)
+}
+
+/// Setup client interface
+#[cfg(not(target_os = "macos"))]
+pub async fn setup_interface_tunnel(
+    tunnel: &Tunnel<Id>,
+    name: &str,
+    mtu: Option<u32>,
+) -> Result<String, Error> {
+    debug!("Setting up interface for tunnel {tunnel}");
+    let interface_name = get_interface_name(name);
+    // request interface configuration
+    debug!("Looking for a free port for interface {interface_name}.");
+    let Some(port) = find_free_tcp_port() else {
+        let msg = format!(
+            "Couldn't find free port for interface {interface_name} while setting up tunnel {tunnel}"
+        );
+        error!("{msg}");
+        return Err(Error::InternalError(msg));
+    };
+    debug!("Found free port: {port} for interface {interface_name}.");
+
+    let interface_config =
+        tunnel_interface_configuration(tunnel, interface_name.clone(), port, mtu)?;
+
     debug!("Creating interface {interface_config:?}");
     let request = CreateInterfaceRequest {
         config: Some(interface_config.clone().into()),
         dns: tunnel.dns.clone(),
+        kill_switch: routes_all_traffic(&interface_config),
+        dns_mode: dns_mode(&interface_config).into(),
+        route_table: tunnel
+            .route_table
+            .and_then(|table| u32::try_from(table).ok()),
     };
     if let Some(pre_up) = &tunnel.pre_up {
         debug!(
@@ -765,9 +912,43 @@ pub fn execute_command(command: &str) -> Result<(), Error> {
     Ok(())
 }
 
+/// Save history of a closed connection and record its disconnection in the timeline.
+pub(crate) async fn save_connection_history(
+    active_connection: &ActiveConnection,
+    reason: DisconnectReason,
+) -> Result<(), Error> {
+    let id = active_connection.location_id;
+    let connection_type = active_connection.connection_type;
+    match connection_type {
+        ConnectionType::Location => {
+            let mut connection: Connection = active_connection.into();
+            connection.reason = Some(reason);
+            let connection = connection.save(&*DB_POOL).await?;
+            trace!("Saved connection: {connection:?}");
+        }
+        ConnectionType::Tunnel => {
+            let mut connection: TunnelConnection = active_connection.into();
+            connection.reason = Some(reason);
+            let connection = connection.save(&*DB_POOL).await?;
+            trace!("Saved connection: {connection:#?}");
+        }
+    }
+    record_event(
+        id,
+        connection_type,
+        ConnectionEventKind::Disconnected,
+        Some(reason),
+        None,
+    )
+    .await;
+
+    Ok(())
+}
+
 /// Helper function to remove interface and close connection
 pub(crate) async fn disconnect_interface(
     active_connection: &ActiveConnection,
+    reason: DisconnectReason,
 ) -> Result<(), Error> {
     debug!(
         "Disconnecting interface {}.",
@@ -803,6 +984,7 @@ pub(crate) async fn disconnect_interface(
                 let request = RemoveInterfaceRequest {
                     interface_name,
                     endpoint: location.endpoint.clone(),
+                    reconnect: reason == DisconnectReason::Reconnect,
                 };
                 debug!(
                     "Sending request to the background service to remove interface {} for location \
@@ -827,13 +1009,11 @@ pub(crate) async fn disconnect_interface(
                 }
             }
 
-            let connection: Connection = active_connection.into();
-            let connection = connection.save(&*DB_POOL).await?;
+            save_connection_history(active_connection, reason).await?;
             debug!(
                 "Saved location {} new connection status in the database",
                 location.name
             );
-            trace!("Saved connection: {connection:?}");
             info!(
                 "Network interface {} for location {location} has been removed",
                 active_connection.interface_name
@@ -879,6 +1059,7 @@ pub(crate) async fn disconnect_interface(
                 let request = RemoveInterfaceRequest {
                     interface_name,
                     endpoint: tunnel.endpoint.clone(),
+                    reconnect: reason == DisconnectReason::Reconnect,
                 };
                 if let Err(error) = DAEMON_CLIENT.clone().remove_interface(request).await {
                     error!(
@@ -904,13 +1085,11 @@ pub(crate) async fn disconnect_interface(
                     active_connection.interface_name
                 );
             }
-            let connection: TunnelConnection = active_connection.into();
-            let connection = connection.save(&*DB_POOL).await?;
+            save_connection_history(active_connection, reason).await?;
             debug!(
                 "Saved new tunnel {} connection status in the database",
                 tunnel.name
             );
-            trace!("Saved connection: {connection:#?}");
             info!(
                 "Network interface {} for tunnel {tunnel} has been removed",
                 active_connection.interface_name
@@ -1087,6 +1266,117 @@ pub async fn sync_connections(app_handle: &AppHandle) -> Result<(), Error> {
     Ok(())
 }
 
+/// Find location or tunnel to which a daemon-managed interface belongs, by its peer public key.
+/// The background service reports keys hex-encoded, while locations and tunnels store them
+/// base64-encoded, so keys are compared after decoding.
+#[cfg(target_os = "linux")]
+fn find_interface_owner(
+    peer_pubkey: &str,
+    locations: &[Location<Id>],
+    tunnels: &[Tunnel<Id>],
+) -> Option<(Id, String, ConnectionType)> {
+    let peer_pubkey = match Key::decode(peer_pubkey) {
+        Ok(key) => key,
+        Err(err) => {
+            warn!("Failed to decode public key {peer_pubkey} of interface peer: {err}");
+            return None;
+        }
+    };
+    let matches = |pubkey: &str| Key::from_str(pubkey).is_ok_and(|key| key == peer_pubkey);
+    if let Some(location) = locations.iter().find(|location| matches(&location.pubkey)) {
+        return Some((location.id, location.name.clone(), ConnectionType::Location));
+    }
+    tunnels
+        .iter()
+        .find(|tunnel| matches(&tunnel.server_pubkey))
+        .map(|tunnel| (tunnel.id, tunnel.name.clone(), ConnectionType::Tunnel))
+}
+
+/// Rebuild active connections from interfaces managed by the background service.
+/// Interfaces outlive the client, e.g. after it crashes or gets restarted, so they have to be
+/// picked up again to reflect the real system's state.
+#[cfg(target_os = "linux")]
+pub async fn sync_connections(app_handle: &AppHandle) -> Result<(), Error> {
+    debug!("Synchronizing active connections with interfaces managed by the background service...");
+    let interfaces = DAEMON_CLIENT
+        .clone()
+        .list_interfaces(())
+        .await
+        .map_err(|err| {
+            error!("Failed to list interfaces managed by the background service: {err}");
+            Error::InternalError(format!(
+                "Failed to list interfaces managed by the background service: {err}"
+            ))
+        })?
+        .into_inner()
+        .interfaces;
+    debug!(
+        "The background service manages {} interfaces",
+        interfaces.len()
+    );
+    if interfaces.is_empty() {
+        return Ok(());
+    }
+
+    let locations = Location::all(&*DB_POOL, false).await?;
+    let tunnels = Tunnel::all(&*DB_POOL).await?;
+    let appstate = app_handle.state::<AppState>();
+    for interface in interfaces {
+        let Some(config) = interface.config else {
+            continue;
+        };
+        let interface_name = config.name;
+        // Interfaces are created with a single peer, the gateway or the tunnel's server.
+        let Some(peer) = config.peers.first() else {
+            debug!("Interface {interface_name} has no peers, skipping synchronization");
+            continue;
+        };
+        let Some((id, name, connection_type)) =
+            find_interface_owner(&peer.public_key, &locations, &tunnels)
+        else {
+            debug!(
+                "Interface {interface_name} doesn't belong to any known location or tunnel, \
+                skipping synchronization"
+            );
+            continue;
+        };
+
+        if find_connection(id, connection_type).await.is_some() {
+            debug!(
+                "{connection_type} {name} has already a connected state, skipping synchronization"
+            );
+            continue;
+        }
+
+        info!(
+            "Found interface {interface_name} of {connection_type} {name}, marking it as connected"
+        );
+        appstate
+            .add_connection(id, &interface_name, connection_type)
+            .await;
+
+        debug!("Sending event informing the frontend that a new connection has been created.");
+        app_handle.emit(EventKey::ConnectionChanged.into(), ())?;
+        debug!("Event informing the frontend that a new connection has been created sent.");
+
+        debug!("Spawning service log watcher for {connection_type} {name}...");
+        spawn_log_watcher_task(
+            app_handle,
+            id,
+            interface_name,
+            connection_type,
+            Level::DEBUG,
+            None,
+        )
+        .await?;
+        debug!("Service log watcher for {connection_type} {name} spawned.");
+    }
+
+    debug!("Active connections synchronized with the background service state");
+
+    Ok(())
+}
+
 #[must_use]
 pub(crate) fn construct_platform_header() -> String {
     let os = os_info::get();
@@ -1116,3 +1406,44 @@ pub async fn get_all_tunnels_locations() -> (Vec<Tunnel<Id>>, Vec<Location<Id>>)
     let locations = Location::all(&*DB_POOL, false).await.unwrap_or_default();
     (tunnels, locations)
 }
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use sqlx::SqlitePool;
+
+    use super::*;
+    use crate::database::models::fixtures;
+
+    #[sqlx::test]
+    async fn interface_owner(pool: SqlitePool) {
+        let location_key = Key::generate().public_key();
+        let tunnel_key = Key::generate().public_key();
+        let mut location = fixtures::location(&pool).await;
+        location.name = "office".into();
+        location.pubkey = location_key.to_string();
+        let mut tunnel = fixtures::tunnel(&pool).await;
+        tunnel.name = "tunnel".into();
+        tunnel.server_pubkey = tunnel_key.to_string();
+        let locations = [location];
+        let tunnels = [tunnel];
+
+        // Peers are reported by the background service with hex-encoded keys.
+        assert_eq!(
+            find_interface_owner(&location_key.to_lower_hex(), &locations, &tunnels),
+            Some((locations[0].id, "office".into(), ConnectionType::Location))
+        );
+        assert_eq!(
+            find_interface_owner(&tunnel_key.to_lower_hex(), &locations, &tunnels),
+            Some((tunnels[0].id, "tunnel".into(), ConnectionType::Tunnel))
+        );
+        let unknown_key = Key::generate().public_key();
+        assert_eq!(
+            find_interface_owner(&unknown_key.to_lower_hex(), &locations, &tunnels),
+            None
+        );
+        assert_eq!(
+            find_interface_owner("not a key", &locations, &tunnels),
+            None
+        );
+    }
+}
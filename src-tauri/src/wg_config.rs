@@ -20,6 +20,10 @@ pub enum WireguardConfigParseError {
     InvalidKey(String),
     #[error("Invalid port: {0}")]
     InvalidPort(String),
+    #[error("Invalid firewall mark: {0}")]
+    InvalidFwMark(String),
+    #[error("Invalid routing table: {0}")]
+    InvalidTable(String),
 }
 
 impl From<TryFromSliceError> for WireguardConfigParseError {
@@ -34,6 +38,38 @@ impl From<DecodeError> for WireguardConfigParseError {
     }
 }
 
+/// Parse `FwMark` value, which is either hexadecimal (with "0x" prefix) or decimal number.
+/// "off" and 0 mean no mark.
+fn parse_fwmark(value: &str) -> Result<Option<i64>, WireguardConfigParseError> {
+    let value = value.trim();
+    if value.eq_ignore_ascii_case("off") {
+        return Ok(None);
+    }
+    let fwmark = match value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+    {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => value.parse(),
+    }
+    .map_err(|_| WireguardConfigParseError::InvalidFwMark(value.to_string()))?;
+
+    Ok((fwmark != 0).then_some(i64::from(fwmark)))
+}
+
+/// Parse `Table` value. "auto" and "off" are treated as the main table; named tables aren't
+/// supported.
+fn parse_route_table(value: &str) -> Result<Option<i64>, WireguardConfigParseError> {
+    let value = value.trim();
+    if value.eq_ignore_ascii_case("auto") || value.eq_ignore_ascii_case("off") {
+        return Ok(None);
+    }
+    value
+        .parse::<u32>()
+        .map(|table| Some(i64::from(table)))
+        .map_err(|_| WireguardConfigParseError::InvalidTable(value.to_string()))
+}
+
 pub fn parse_wireguard_config(
     filename: &str,
     config: &str,
@@ -67,6 +103,16 @@ pub fn parse_wireguard_config(
     let post_up = interface_section.get("PostUp");
     let pre_down = interface_section.get("PreDown");
     let post_down = interface_section.get("PostDown");
+    let fwmark = interface_section
+        .get("FwMark")
+        .map(parse_fwmark)
+        .transpose()?
+        .flatten();
+    let route_table = interface_section
+        .get("Table")
+        .map(parse_route_table)
+        .transpose()?
+        .flatten();
 
     // Parse Peer section (assuming only one peer)
     let peer_section = config
@@ -106,6 +152,8 @@ pub fn parse_wireguard_config(
         post_up.map(str::to_string),
         pre_down.map(str::to_string),
         post_down.map(str::to_string),
+        fwmark,
+        route_table,
     );
 
     Ok(tunnel)
@@ -124,6 +172,8 @@ mod test {
             Address = 10.0.0.1/24
             ListenPort = 55055
             DNS = 10.0.0.2,tnt,teonite.net
+            FwMark = 0xca6c
+            Table = 1234
             PostUp = iptables -I OUTPUT ! -o %i -m mark ! --mark $(wg show %i fwmark) -m addrtype ! --dst-type LOCAL -j REJECT
 
             [Peer]
@@ -158,5 +208,7 @@ mod test {
           Some("iptables -I OUTPUT ! -o %i -m mark ! --mark $(wg show %i fwmark) -m addrtype ! --dst-type LOCAL -j REJECT".to_string()));
         assert_eq!(tunnel.pre_down, None);
         assert_eq!(tunnel.post_down, None);
+        assert_eq!(tunnel.fwmark, Some(0xca6c));
+        assert_eq!(tunnel.route_table, Some(1234));
     }
 }
@@ -7,6 +7,7 @@ import type { NewApplicationVersionInfo } from '../../../shared/hooks/api/types'
 import type {
   CommonWireguardFields,
   Connection,
+  ConnectionEvent,
   DefguardInstance,
   LocationStats,
   Tunnel,
@@ -14,17 +15,23 @@ import type {
 import type {
   AppConfig,
   ConnectionRequest,
+  DataCap,
+  ExportRequest,
   GetLocationsRequest,
   LocationDetails,
   LocationDetailsRequest,
+  ProbeTargetRequest,
   ProvisioningConfig,
   RoutingRequest,
   SaveConfigRequest,
+  SaveDataCapRequest,
   SaveDeviceConfigResponse,
   StatsRequest,
   TauriCommandKey,
   TunnelRequest,
   UpdateInstanceRequest,
+  UsageTotal,
+  UsageTotalsRequest,
 } from './types';
 
 // Streamlines logging for invokes
@@ -74,18 +81,39 @@ const disconnect = async (data: ConnectionRequest): Promise<void> =>
 const getLocationStats = async (data: StatsRequest): Promise<LocationStats[]> =>
   invokeWrapper('location_stats', data);
 
+const getUsageTotals = async (data: UsageTotalsRequest): Promise<UsageTotal[]> =>
+  invokeWrapper('usage_totals', data);
+
+const getDataCap = async (locationId: number): Promise<DataCap | null> =>
+  invokeWrapper('data_cap', { locationId });
+
+const saveDataCap = async (data: SaveDataCapRequest): Promise<void> =>
+  invokeWrapper('save_data_cap', data);
+
+const deleteDataCap = async (locationId: number): Promise<void> =>
+  invokeWrapper('delete_data_cap', { locationId });
+
+const exportData = async (data: ExportRequest): Promise<void> =>
+  invokeWrapper('export_data', data);
+
 const getLastConnection = async (data: ConnectionRequest): Promise<Connection> =>
   invokeWrapper('last_connection', data);
 
 const getConnectionHistory = async (data: ConnectionRequest): Promise<Connection[]> =>
   invokeWrapper('all_connections', data);
 
+const getConnectionTimeline = async (data: StatsRequest): Promise<ConnectionEvent[]> =>
+  invokeWrapper('connection_timeline', data);
+
 const getActiveConnection = async (data: ConnectionRequest): Promise<Connection> =>
   invokeWrapper('active_connection', data);
 
 const updateLocationRouting = async (data: RoutingRequest): Promise<Connection> =>
   invokeWrapper('update_location_routing', data);
 
+const updateProbeTarget = async (data: ProbeTargetRequest): Promise<void> =>
+  invokeWrapper('update_probe_target', data);
+
 const deleteInstance = async (id: number): Promise<void> =>
   invokeWrapper('delete_instance', { instanceId: id });
 
@@ -154,11 +182,18 @@ export const clientApi = {
   connect,
   disconnect,
   getLocationStats,
+  getUsageTotals,
+  getDataCap,
+  saveDataCap,
+  deleteDataCap,
+  exportData,
   getLastConnection,
   getConnectionHistory,
+  getConnectionTimeline,
   getActiveConnection,
   saveConfig,
   updateLocationRouting,
+  updateProbeTarget,
   deleteInstance,
   deleteTunnel,
   getLocationDetails,
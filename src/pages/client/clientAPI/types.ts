@@ -24,6 +24,52 @@ export type StatsRequest = {
   from?: string;
 };
 
+export type UsagePeriod = 'day' | 'month';
+
+export type UsageTotalsRequest = {
+  locationId: number;
+  connectionType: ClientConnectionType;
+  period: UsagePeriod;
+  from?: string;
+};
+
+export type UsageTotal = {
+  period_start: string;
+  upload: number;
+  download: number;
+};
+
+export type DataCap = {
+  id: number;
+  location_id: number;
+  // bytes, upload and download combined
+  cap: number;
+  period: UsagePeriod;
+  disconnect: boolean;
+};
+
+export type SaveDataCapRequest = {
+  locationId: number;
+  cap: number;
+  period: UsagePeriod;
+  disconnect: boolean;
+};
+
+export type ExportFormat = 'csv' | 'json';
+
+export type ExportData = 'history' | 'stats';
+
+export type ExportRequest = {
+  locationId: number;
+  connectionType: ClientConnectionType;
+  data: ExportData;
+  format: ExportFormat;
+  from?: string;
+  to?: string;
+  // file to export to
+  path: string;
+};
+
 export type SaveConfigRequest = {
   privateKey: string;
   response: CreateDeviceResponse;
@@ -81,6 +127,10 @@ export type AppConfig = {
   check_for_updates: boolean;
   peer_alive_period: number;
   mtu: number;
+  stats_retention_raw: number;
+  stats_retention_minute: number;
+  stats_retention_hour: number;
+  stats_retention_day: number;
 };
 
 export type PlatformInfo = {
@@ -121,6 +171,15 @@ export type TunnelRequest = {
   post_up?: string;
   pre_down?: string;
   post_down?: string;
+  fwmark?: number | null;
+  route_table?: number | null;
+};
+
+export type ProbeTargetRequest = {
+  locationId: number;
+  connectionType: ClientConnectionType;
+  // IP address with an optional UDP port, or null to disable probing
+  probeTarget: string | null;
 };
 
 export type LocationDetailsRequest = {
@@ -134,11 +193,18 @@ export type TauriCommandKey =
   | 'connect'
   | 'disconnect'
   | 'location_stats'
+  | 'usage_totals'
+  | 'data_cap'
+  | 'save_data_cap'
+  | 'delete_data_cap'
+  | 'export_data'
   | 'last_connection'
   | 'all_connections'
+  | 'connection_timeline'
   | 'active_connection'
   | 'save_device_config'
   | 'update_location_routing'
+  | 'update_probe_target'
   | 'delete_instance'
   | 'update_instance'
   | 'parse_tunnel_config'
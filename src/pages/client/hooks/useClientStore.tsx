@@ -36,6 +36,10 @@ const defaultValues: StoreValues = {
     check_for_updates: true,
     peer_alive_period: 300,
     mtu: 0,
+    stats_retention_raw: 30,
+    stats_retention_minute: 30,
+    stats_retention_hour: 180,
+    stats_retention_day: 730,
   },
   platformInfo: {
     client_version: '',
@@ -50,6 +50,11 @@ type FormFields = {
   post_up?: string;
   pre_down?: string;
   post_down?: string;
+  // Imported from wg-quick configuration, not editable.
+  fwmark?: number | null;
+  route_table?: number | null;
+  // Set from connection details, not editable.
+  probe_target?: string | null;
 };
 const defaultValues: FormFields = {
   name: '',
@@ -188,6 +193,9 @@ export const EditTunnelFormCard = ({ tunnel, submitRef }: Props) => {
         post_up: z.string().nullable(),
         pre_down: z.string().nullable(),
         post_down: z.string().nullable(),
+        fwmark: z.number().nullable().optional(),
+        route_table: z.number().nullable().optional(),
+        probe_target: z.string().nullable().optional(),
       }),
     [LL.form.errors],
   );
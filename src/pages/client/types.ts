@@ -31,6 +31,12 @@ export type LocationStats = {
   collected_at: number;
   download: number;
   upload: number;
+  // Connection quality, available if the location has a probe target.
+  // milliseconds
+  rtt?: number | null;
+  jitter?: number | null;
+  // fraction of lost probes
+  loss?: number | null;
 };
 
 export type Connection = {
@@ -41,6 +47,33 @@ export type Connection = {
   end: string;
   upload?: number;
   download?: number;
+  // why the connection has ended; null if not known
+  reason?: DisconnectReason | null;
+};
+
+export type DisconnectReason =
+  | 'User'
+  | 'Dropped'
+  | 'AppExit'
+  | 'Service'
+  | 'DataCap'
+  | 'Reconnect';
+
+export type ConnectionEventKind =
+  | 'Connecting'
+  | 'Connected'
+  | 'Failed'
+  | 'Reconnecting'
+  | 'Disconnected';
+
+export type ConnectionEvent = {
+  id: number;
+  kind: ConnectionEventKind;
+  // set for disconnections only
+  reason: DisconnectReason | null;
+  // error text of failures
+  message: string | null;
+  timestamp: string;
 };
 
 export type Tunnel = {
@@ -56,6 +89,8 @@ export type Tunnel = {
   post_up?: string;
   pre_down?: string;
   post_down?: string;
+  fwmark?: number | null;
+  route_table?: number | null;
 } & CommonWireguardFields;
 
 // Common fields between Tunnel, Location and instance
@@ -75,6 +110,8 @@ export type CommonWireguardFields = {
   pubkey: string;
   instance_id: number;
   network_id: number;
+  // In-tunnel target of connection quality probes.
+  probe_target?: string | null;
 };
 
 export type SelectedInstance = {
@@ -115,4 +152,5 @@ export enum TauriEventKey {
   MFA_TRIGGER = 'mfa-trigger',
   VERSION_MISMATCH = 'version-mismatch',
   UUID_MISMATCH = 'uuid-mismatch',
+  DATA_CAP_REACHED = 'data-cap-reached',
 }